@@ -0,0 +1,86 @@
+//! Compares drawing sprites on the packed [Screen] against the previous
+//! implementation, which stored one `bool` per pixel and set each pixel of a
+//! sprite individually.
+
+use chip8::Screen;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 32;
+
+/// The 15 byte sprite drawn by most benchmarks, as large as `Draw` allows.
+const SPRITE: [u8; 15] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, 0x20, 0x60, 0x20, 0x20, 0x70, 0xF0, 0x10, 0xF0, 0x80, 0xF0,
+];
+
+struct BoolScreen([bool; WIDTH * HEIGHT]);
+
+impl BoolScreen {
+    fn draw_sprite(&mut self, x: usize, y: usize, sprite: &[u8]) -> usize {
+        let mut collided_rows = 0;
+
+        for (y_offset, row) in sprite.iter().enumerate() {
+            let mut collided = false;
+
+            for (x_offset, bit) in byte_to_bits(*row).into_iter().enumerate() {
+                let i = (y + y_offset) % HEIGHT * WIDTH + (x + x_offset) % WIDTH;
+                let prev = self.0[i];
+                self.0[i] = prev ^ bit;
+                collided |= prev & bit;
+            }
+
+            collided_rows += collided as usize;
+        }
+
+        collided_rows
+    }
+}
+
+fn byte_to_bits(byte: u8) -> Vec<bool> {
+    (0..u8::BITS).rev().map(|i| byte >> i & 1 == 1).collect()
+}
+
+fn draw_sprite(c: &mut Criterion) {
+    let mut group = c.benchmark_group("draw_sprite");
+
+    group.bench_function("packed", |b| {
+        let mut screen = Screen::new();
+        let mut x = 0;
+        b.iter(|| {
+            x = (x + 3) % WIDTH;
+            black_box(screen.draw_sprite(black_box(x), 7, &SPRITE, false))
+        })
+    });
+
+    group.bench_function("packed_wrapping", |b| {
+        let mut screen = Screen::new();
+        let mut x = 0;
+        b.iter(|| {
+            x = (x + 3) % WIDTH;
+            black_box(screen.draw_sprite(black_box(x), 25, &SPRITE, true))
+        })
+    });
+
+    group.bench_function("bool_per_pixel", |b| {
+        let mut screen = BoolScreen([false; WIDTH * HEIGHT]);
+        let mut x = 0;
+        b.iter(|| {
+            x = (x + 3) % WIDTH;
+            black_box(screen.draw_sprite(black_box(x), 7, &SPRITE))
+        })
+    });
+
+    group.finish();
+}
+
+fn framebuffer(c: &mut Criterion) {
+    let mut screen = Screen::new();
+    screen.draw_sprite(10, 10, &SPRITE, false);
+
+    c.bench_function("framebuffer_iter", |b| {
+        b.iter(|| black_box(screen.framebuffer().iter().filter(|on| *on).count()))
+    });
+}
+
+criterion_group!(benches, draw_sprite, framebuffer);
+criterion_main!(benches);
@@ -0,0 +1,640 @@
+//! Static analysis of a ROM, without running it: its control-flow graph of
+//! basic blocks, the subroutines reached from it, and the places where it
+//! overwrites its own code.
+//!
+//! Instructions are found by following every jump, skip and call from the
+//! entry, so data which is never jumped to isn't mistaken for code. `JP V0,
+//! nnn` jumps to an address only known when it runs, so it can't be followed
+//! and is flagged instead.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Write};
+
+use serde::Serialize;
+
+use crate::opcode::{disassemble_word, Opcode};
+
+/// How control gets from one block to another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EdgeKind {
+    /// Runs on into the next instruction.
+    Fallthrough,
+    Jump,
+    /// A skip instruction skips the next instruction.
+    Skip,
+    /// A call, which comes back to the [EdgeKind::Fallthrough] after it.
+    Call,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct Edge {
+    pub target: u16,
+    pub kind: EdgeKind,
+}
+
+/// Instructions which always run one after another, as only the first is
+/// jumped to and only the last jumps.
+#[derive(Debug, Clone, Serialize)]
+pub struct Block {
+    pub start: u16,
+    /// Addresses of the instructions, with their bytes.
+    #[serde(skip)]
+    pub instructions: Vec<(u16, [u8; 2])>,
+    pub edges: Vec<Edge>,
+    /// Base address of the `JP V0, nnn` ending the block, whose targets are
+    /// unknown.
+    pub indirect: Option<u16>,
+    /// Whether the block runs into bytes which aren't an instruction, or off
+    /// the end of the ROM.
+    pub invalid: bool,
+}
+
+/// An instruction which writes to memory holding instructions.
+#[derive(Debug, Clone, Serialize)]
+pub struct SelfModification {
+    /// Address of the instruction doing the writing.
+    pub writer: u16,
+    /// First and last address written.
+    pub start: u16,
+    pub end: u16,
+    /// Instructions which are overwritten.
+    pub overwritten: Vec<u16>,
+}
+
+/// Blocks reached from each subroutine, by the address it's called at.
+type Subroutines = BTreeMap<u16, BTreeSet<u16>>;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Analysis {
+    pub entry: u16,
+    pub blocks: BTreeMap<u16, Block>,
+    /// Each subroutine, by the address it's called at, with the blocks
+    /// reached from it without following calls. The entry counts as one too.
+    pub subroutines: Subroutines,
+    /// Calls from one subroutine to another.
+    pub calls: BTreeSet<(u16, u16)>,
+    pub self_modifications: Vec<SelfModification>,
+    /// Instructions which write to memory through an `I` which isn't known
+    /// statically, so could be modifying code too.
+    pub unknown_writes: Vec<u16>,
+}
+
+/// Analyses `rom` when loaded at `load_address`.
+pub fn analyze(rom: &[u8], load_address: usize) -> Analysis {
+    let start = load_address as u16;
+    let fetch = |address: u16| -> Option<[u8; 2]> {
+        let offset = (address as usize).checked_sub(load_address)?;
+        Some([*rom.get(offset)?, *rom.get(offset + 1)?])
+    };
+
+    // Find every instruction, and where blocks have to start.
+    let mut instructions = BTreeMap::new();
+    let mut leaders = BTreeSet::from([start]);
+    let mut pending = vec![start];
+    while let Some(pc) = pending.pop() {
+        if instructions.contains_key(&pc) {
+            continue;
+        }
+        let Some(word) = fetch(pc) else {
+            continue;
+        };
+        let opcode = Opcode::decode(word);
+
+        let (edges, ends_block) = successors(pc, opcode.as_ref());
+        instructions.insert(pc, (word, opcode));
+        for edge in &edges {
+            if ends_block {
+                leaders.insert(edge.target);
+            }
+            pending.push(edge.target);
+        }
+    }
+
+    // Split the instructions into blocks at the leaders.
+    let mut blocks = BTreeMap::new();
+    for &leader in &leaders {
+        let mut block = Block {
+            start: leader,
+            instructions: vec![],
+            edges: vec![],
+            indirect: None,
+            invalid: false,
+        };
+
+        let mut pc = leader;
+        loop {
+            let Some((word, opcode)) = instructions.get(&pc) else {
+                // Off the end of the ROM.
+                block.invalid = true;
+                break;
+            };
+            block.instructions.push((pc, *word));
+
+            let (edges, ends_block) = successors(pc, opcode.as_ref());
+            match opcode {
+                None => block.invalid = true,
+                Some(Opcode::JumpPlusV0 { nnn }) => block.indirect = Some(*nnn),
+                _ => {}
+            }
+            if ends_block || opcode.is_none() || leaders.contains(&(pc + 2)) {
+                block.edges = edges;
+                break;
+            }
+            pc += 2;
+        }
+
+        // Blocks for targets outside the ROM have nothing in them.
+        if !block.instructions.is_empty() {
+            blocks.insert(leader, block);
+        }
+    }
+
+    let (subroutines, calls) = subroutines(start, &blocks);
+    let (self_modifications, unknown_writes) = self_modifications(&blocks, &instructions);
+
+    Analysis {
+        entry: start,
+        blocks,
+        subroutines,
+        calls,
+        self_modifications,
+        unknown_writes,
+    }
+}
+
+/// Where control can go after the instruction at `pc`, and whether it ends a
+/// block.
+fn successors(pc: u16, opcode: Option<&Opcode>) -> (Vec<Edge>, bool) {
+    let edge = |target, kind| Edge { target, kind };
+    let next = edge(pc + 2, EdgeKind::Fallthrough);
+
+    match opcode {
+        None | Some(Opcode::Return) | Some(Opcode::JumpPlusV0 { .. }) => (vec![], true),
+        Some(Opcode::Jump { nnn }) => (vec![edge(*nnn, EdgeKind::Jump)], true),
+        Some(Opcode::Call { nnn }) => (vec![edge(*nnn, EdgeKind::Call), next], true),
+        Some(
+            Opcode::SkipEqualsConstant { .. }
+            | Opcode::SkipNotEqualsConstant { .. }
+            | Opcode::SkipEquals { .. }
+            | Opcode::SkipNotEquals { .. }
+            | Opcode::KeyPressSkip { .. }
+            | Opcode::KeyNotPressSkip { .. },
+        ) => (vec![next, edge(pc + 4, EdgeKind::Skip)], true),
+        Some(_) => (vec![next], false),
+    }
+}
+
+/// Finds the blocks of each subroutine, and the calls between them.
+fn subroutines(entry: u16, blocks: &BTreeMap<u16, Block>) -> (Subroutines, BTreeSet<(u16, u16)>) {
+    let mut subroutines = BTreeMap::new();
+    let mut calls = BTreeSet::new();
+
+    let mut pending = vec![entry];
+    while let Some(subroutine) = pending.pop() {
+        if subroutines.contains_key(&subroutine) {
+            continue;
+        }
+
+        let mut reached = BTreeSet::new();
+        let mut to_visit = vec![subroutine];
+        while let Some(start) = to_visit.pop() {
+            let Some(block) = blocks.get(&start) else {
+                continue;
+            };
+            if !reached.insert(start) {
+                continue;
+            }
+
+            for edge in &block.edges {
+                if edge.kind == EdgeKind::Call {
+                    calls.insert((subroutine, edge.target));
+                    pending.push(edge.target);
+                } else {
+                    to_visit.push(edge.target);
+                }
+            }
+        }
+
+        subroutines.insert(subroutine, reached);
+    }
+
+    (subroutines, calls)
+}
+
+/// Finds the instructions which write over instructions, following the value
+/// of `I` through each block, and those which write where `I` isn't known.
+fn self_modifications(
+    blocks: &BTreeMap<u16, Block>,
+    instructions: &BTreeMap<u16, ([u8; 2], Option<Opcode>)>,
+) -> (Vec<SelfModification>, Vec<u16>) {
+    let mut modifications = vec![];
+    let mut unknown_writes = vec![];
+
+    for block in blocks.values() {
+        let mut index = None;
+
+        for &(pc, word) in &block.instructions {
+            let written = match Opcode::decode(word) {
+                Some(Opcode::LoadIndex { nnn }) => {
+                    index = Some(nnn);
+                    continue;
+                }
+                Some(Opcode::AddIndex { .. } | Opcode::LocateSprite { .. }) => {
+                    index = None;
+                    continue;
+                }
+                Some(Opcode::StoreRegisters { vx }) => vx as u16 + 1,
+                Some(Opcode::LoadBcd { .. }) => 3,
+                _ => continue,
+            };
+
+            let Some(start) = index else {
+                unknown_writes.push(pc);
+                continue;
+            };
+            let end = start + written - 1;
+            // An instruction is overwritten if either of its bytes are.
+            let overwritten = instructions
+                .range(start.saturating_sub(1)..=end)
+                .map(|(&address, _)| address)
+                .filter(|&address| address + 1 >= start)
+                .collect::<Vec<_>>();
+
+            if !overwritten.is_empty() {
+                modifications.push(SelfModification {
+                    writer: pc,
+                    start,
+                    end,
+                    overwritten,
+                });
+            }
+        }
+    }
+
+    (modifications, unknown_writes)
+}
+
+impl Analysis {
+    /// Whether any instruction in `block` is overwritten by the program.
+    fn is_modified(&self, block: &Block) -> bool {
+        block.instructions.iter().any(|(pc, _)| {
+            self.self_modifications
+                .iter()
+                .any(|modification| modification.overwritten.contains(pc))
+        })
+    }
+
+    /// The basic blocks as a Graphviz graph, with each block listing its
+    /// instructions.
+    ///
+    /// Calls are dashed, skips are labelled, and blocks ending in `JP V0, nnn`
+    /// are red, and those which are overwritten orange.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from(
+            "digraph cfg {\n  node [shape=box, fontname=monospace];\n  edge [fontname=monospace];\n",
+        );
+
+        for block in self.blocks.values() {
+            let mut label = block
+                .instructions
+                .iter()
+                .map(|&(pc, word)| disassemble_word(pc as usize, word))
+                .collect::<Vec<_>>()
+                .join("\\l");
+            label += "\\l";
+
+            let color = if block.indirect.is_some() || block.invalid {
+                ", color=red"
+            } else if self.is_modified(block) {
+                ", color=orange"
+            } else {
+                ""
+            };
+            let peripheries = if block.start == self.entry {
+                ", peripheries=2"
+            } else {
+                ""
+            };
+            let _ = writeln!(
+                dot,
+                "  b{:03X} [label=\"{label}\"{color}{peripheries}];",
+                block.start
+            );
+        }
+
+        for block in self.blocks.values() {
+            for edge in &block.edges {
+                if !self.blocks.contains_key(&edge.target) {
+                    let _ = writeln!(
+                        dot,
+                        "  b{:03X} [label=\"{:03X}: outside the ROM\", style=dashed];",
+                        edge.target, edge.target
+                    );
+                }
+
+                let style = match edge.kind {
+                    EdgeKind::Fallthrough | EdgeKind::Jump => "",
+                    EdgeKind::Skip => " [label=\"skip\"]",
+                    EdgeKind::Call => " [style=dashed]",
+                };
+                let _ = writeln!(
+                    dot,
+                    "  b{:03X} -> b{:03X}{style};",
+                    block.start, edge.target
+                );
+            }
+        }
+
+        dot + "}\n"
+    }
+
+    /// The calls between subroutines as a Graphviz graph.
+    pub fn to_call_graph_dot(&self) -> String {
+        let mut dot = String::from("digraph calls {\n  node [shape=box, fontname=monospace];\n");
+
+        for (&subroutine, blocks) in &self.subroutines {
+            let instructions = blocks
+                .iter()
+                .filter_map(|start| self.blocks.get(start))
+                .map(|block| block.instructions.len())
+                .sum::<usize>();
+            let peripheries = if subroutine == self.entry {
+                ", peripheries=2"
+            } else {
+                ""
+            };
+            let _ = writeln!(
+                dot,
+                "  s{subroutine:03X} [label=\"{subroutine:03X}\\n{instructions} instructions\"{peripheries}];",
+            );
+        }
+        for (caller, callee) in &self.calls {
+            let _ = writeln!(dot, "  s{caller:03X} -> s{callee:03X};");
+        }
+
+        dot + "}\n"
+    }
+
+    /// The whole analysis as JSON, with instructions disassembled.
+    pub fn to_json(&self) -> String {
+        #[derive(Serialize)]
+        struct Json<'a> {
+            #[serde(flatten)]
+            analysis: &'a Analysis,
+            instructions: BTreeMap<u16, String>,
+        }
+
+        let instructions = self
+            .blocks
+            .values()
+            .flat_map(|block| &block.instructions)
+            .map(|&(pc, word)| {
+                let instruction = Opcode::decode(word)
+                    .map_or(format!("DW {:#06X}", u16::from_be_bytes(word)), |opcode| {
+                        opcode.to_string()
+                    });
+                (pc, instruction)
+            })
+            .collect();
+
+        serde_json::to_string_pretty(&Json {
+            analysis: self,
+            instructions,
+        })
+        .unwrap()
+    }
+}
+
+impl fmt::Display for Analysis {
+    /// Lists the blocks with their instructions and where they go, then the
+    /// subroutines, and anything which can't be analysed statically.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} blocks in {} subroutine{}, starting at {:03X}",
+            self.blocks.len(),
+            self.subroutines.len(),
+            if self.subroutines.len() == 1 { "" } else { "s" },
+            self.entry
+        )?;
+
+        for block in self.blocks.values() {
+            writeln!(f, "\nBlock {:03X}:", block.start)?;
+            for &(pc, word) in &block.instructions {
+                writeln!(f, "  {}", disassemble_word(pc as usize, word))?;
+            }
+
+            let edges = block
+                .edges
+                .iter()
+                .map(|edge| match edge.kind {
+                    EdgeKind::Fallthrough => format!("{:03X}", edge.target),
+                    EdgeKind::Jump => format!("{:03X} (jump)", edge.target),
+                    EdgeKind::Skip => format!("{:03X} (skip)", edge.target),
+                    EdgeKind::Call => format!("{:03X} (call)", edge.target),
+                })
+                .collect::<Vec<_>>();
+            if !edges.is_empty() {
+                writeln!(f, "  -> {}", edges.join(", "))?;
+            }
+            if let Some(base) = block.indirect {
+                writeln!(f, "  -> {base:03X} + V0 (indirect)")?;
+            }
+            if block.invalid {
+                writeln!(f, "  -> runs into data or off the end of the ROM")?;
+            }
+        }
+
+        writeln!(f, "\nSubroutines:")?;
+        for (subroutine, blocks) in &self.subroutines {
+            let callees = self
+                .calls
+                .iter()
+                .filter(|(caller, _)| caller == subroutine)
+                .map(|(_, callee)| format!("{callee:03X}"))
+                .collect::<Vec<_>>();
+            let entry = if *subroutine == self.entry {
+                " (entry)"
+            } else {
+                ""
+            };
+            write!(f, "  {subroutine:03X}{entry}: {} blocks", blocks.len())?;
+            if !callees.is_empty() {
+                write!(f, ", calls {}", callees.join(", "))?;
+            }
+            writeln!(f)?;
+        }
+
+        let indirect = self
+            .blocks
+            .values()
+            .filter_map(|block| block.indirect.map(|base| (block, base)))
+            .collect::<Vec<_>>();
+        if !indirect.is_empty() {
+            writeln!(f, "\nIndirect jumps, whose targets weren't followed:")?;
+            for (block, base) in indirect {
+                let (pc, _) = block.instructions.last().unwrap();
+                writeln!(f, "  {pc:03X}: JP V0, {base:#05X}")?;
+            }
+        }
+
+        if !self.self_modifications.is_empty() {
+            writeln!(f, "\nSelf-modifying code:")?;
+            for modification in &self.self_modifications {
+                let overwritten = modification
+                    .overwritten
+                    .iter()
+                    .map(|pc| format!("{pc:03X}"))
+                    .collect::<Vec<_>>();
+                writeln!(
+                    f,
+                    "  {:03X} writes {:03X}-{:03X}, over the instructions at {}",
+                    modification.writer,
+                    modification.start,
+                    modification.end,
+                    overwritten.join(", ")
+                )?;
+            }
+        }
+
+        if !self.unknown_writes.is_empty() {
+            let writes = self
+                .unknown_writes
+                .iter()
+                .map(|pc| format!("{pc:03X}"))
+                .collect::<Vec<_>>();
+            writeln!(
+                f,
+                "\nWrites where I isn't known, which could modify code: {}",
+                writes.join(", ")
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edges(analysis: &Analysis, start: u16) -> Vec<(u16, EdgeKind)> {
+        analysis.blocks[&start]
+            .edges
+            .iter()
+            .map(|edge| (edge.target, edge.kind))
+            .collect()
+    }
+
+    #[test]
+    fn skips_branch_to_both_instructions() {
+        // 200: SE V0, 0x00; JP 0x206; CLS
+        // 206: JP 0x206
+        let analysis = analyze(&[0x30, 0x00, 0x12, 0x06, 0x00, 0xE0, 0x12, 0x06], 0x200);
+
+        assert_eq!(
+            analysis.blocks.keys().copied().collect::<Vec<_>>(),
+            [0x200, 0x202, 0x204, 0x206]
+        );
+        assert_eq!(
+            edges(&analysis, 0x200),
+            [(0x202, EdgeKind::Fallthrough), (0x204, EdgeKind::Skip)]
+        );
+        assert_eq!(edges(&analysis, 0x202), [(0x206, EdgeKind::Jump)]);
+        // CLS runs on into 206, which starts a block as it's jumped to.
+        assert_eq!(edges(&analysis, 0x204), [(0x206, EdgeKind::Fallthrough)]);
+        assert_eq!(edges(&analysis, 0x206), [(0x206, EdgeKind::Jump)]);
+        assert_eq!(analysis.subroutines.len(), 1);
+    }
+
+    #[test]
+    fn calls_start_subroutines() {
+        // 200: CALL 0x206; JP 0x202
+        // 204: data
+        // 206: CALL 0x20A; RET
+        // 20A: RET
+        let rom = [
+            0x22, 0x06, 0x12, 0x02, 0xFF, 0xFF, 0x22, 0x0A, 0x00, 0xEE, 0x00, 0xEE,
+        ];
+        let analysis = analyze(&rom, 0x200);
+
+        assert_eq!(
+            edges(&analysis, 0x200),
+            [(0x206, EdgeKind::Call), (0x202, EdgeKind::Fallthrough)]
+        );
+        assert_eq!(
+            analysis.subroutines,
+            BTreeMap::from([
+                (0x200, BTreeSet::from([0x200, 0x202])),
+                (0x206, BTreeSet::from([0x206, 0x208])),
+                (0x20A, BTreeSet::from([0x20A])),
+            ])
+        );
+        assert_eq!(
+            analysis.calls,
+            BTreeSet::from([(0x200, 0x206), (0x206, 0x20A)])
+        );
+        // The data between them is never reached, so isn't taken for code.
+        assert!(!analysis.blocks.contains_key(&0x204));
+        assert_eq!(analysis.to_string().lines().last(), Some("  20A: 1 blocks"));
+    }
+
+    #[test]
+    fn jumps_into_data_are_invalid() {
+        // 200: JP 0x203, into the middle of the data after it
+        let analysis = analyze(&[0x12, 0x03, 0xFF, 0xFF, 0xFF], 0x200);
+
+        let block = &analysis.blocks[&0x203];
+        assert_eq!(block.instructions, [(0x203, [0xFF, 0xFF])]);
+        assert!(block.edges.is_empty());
+        assert!(block.invalid);
+        assert!(analysis.to_string().contains(
+            "Block 203:\n  203: FFFF  DW 0xFFFF\n  -> runs into data or off the end of the ROM\n"
+        ));
+    }
+
+    #[test]
+    fn jumps_out_of_the_rom_are_kept_as_edges() {
+        // 200: LD V0, 0x01; JP 0x300
+        let analysis = analyze(&[0x60, 0x01, 0x13, 0x00], 0x200);
+
+        assert_eq!(analysis.blocks.len(), 1);
+        assert_eq!(edges(&analysis, 0x200), [(0x300, EdgeKind::Jump)]);
+        assert!(analysis
+            .to_dot()
+            .contains("  b300 [label=\"300: outside the ROM\", style=dashed];\n"));
+    }
+
+    #[test]
+    fn running_off_the_end_is_invalid() {
+        // 200: LD V0, 0x01, then a lone byte
+        let analysis = analyze(&[0x60, 0x01, 0x00], 0x200);
+
+        assert!(analysis.blocks[&0x200].invalid);
+    }
+
+    #[test]
+    fn indirect_jumps_are_flagged() {
+        // 200: JP V0, 0x300
+        let analysis = analyze(&[0xB3, 0x00], 0x200);
+
+        assert_eq!(analysis.blocks[&0x200].indirect, Some(0x300));
+        assert!(analysis.blocks[&0x200].edges.is_empty());
+    }
+
+    #[test]
+    fn finds_writes_over_code() {
+        // 200: LD I, 0x205; LD [I], V1; LD F, V0; LD B, V0
+        let analysis = analyze(&[0xA2, 0x05, 0xF1, 0x55, 0xF0, 0x29, 0xF0, 0x33], 0x200);
+
+        let [modification] = analysis.self_modifications.as_slice() else {
+            panic!("{:?}", analysis.self_modifications);
+        };
+        assert_eq!(modification.writer, 0x202);
+        assert_eq!((modification.start, modification.end), (0x205, 0x206));
+        assert_eq!(modification.overwritten, [0x204, 0x206]);
+        // After LD F, I points at the font, which isn't known statically.
+        assert_eq!(analysis.unknown_writes, [0x206]);
+    }
+}
@@ -0,0 +1,187 @@
+//! Octo cartridges, GIF images which carry the source of an Octo program and
+//! the settings to run it with, as shared by the Octojam archive.
+//!
+//! The payload is hidden in the colours of the animation frames, with each
+//! byte spread over two successive pixels: a bit from the red channel, two
+//! from the green and one from the blue make up each nibble. It starts with
+//! its length as a 32-bit big-endian number, followed by JSON with the
+//! program and its options.
+
+use serde::Deserialize;
+
+use crate::database::RomInfo;
+use crate::error::Error;
+use crate::octo;
+use crate::palette::{parse_color, Palette};
+use crate::quirks::Platform;
+
+/// Start of every GIF file.
+pub const MAGIC: &[u8] = b"GIF8";
+
+#[derive(Deserialize)]
+struct Payload {
+    program: String,
+    #[serde(default)]
+    options: Options,
+}
+
+/// The options of Octo which matter here, leaving out the ones for
+/// instructions and quirks which aren't emulated.
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Options {
+    tickrate: Option<usize>,
+    max_size: Option<usize>,
+    fill_color: Option<String>,
+    background_color: Option<String>,
+    /// Octo stores quirks either as booleans or as 0 and 1.
+    clip_quirks: Option<serde_json::Value>,
+}
+
+/// A program from an Octo cartridge, compiled.
+pub struct Cartridge {
+    pub rom: Vec<u8>,
+    pub info: RomInfo,
+}
+
+impl Cartridge {
+    /// Decodes the cartridge in the GIF `image`, and compiles its program.
+    ///
+    /// `title` is used for the [RomInfo], as cartridges don't carry one.
+    pub fn decode(title: &str, image: &[u8]) -> Result<Self, Error> {
+        let payload = payload(image)
+            .map_err(|err| Error::ErrorStr(format!("Can't decode Octo cartridge: {err}")))?;
+        let payload: Payload = serde_json::from_slice(&payload)
+            .map_err(|err| Error::ErrorStr(format!("Invalid Octo cartridge: {err}")))?;
+
+        let rom = octo::compile(&payload.program)
+            .map_err(|err| Error::ErrorStr(format!("Can't compile Octo cartridge: {err}")))?;
+
+        let options = payload.options;
+
+        // Octo picks the platform by how much memory programs may use.
+        let platform = match options.max_size {
+            Some(3583) => Platform::Superchip,
+            Some(65024) => Platform::XoChip,
+            _ => Platform::Chip8,
+        };
+
+        let mut quirks = platform.quirks();
+        if let Some(clip) = options.clip_quirks.as_ref().and_then(flag) {
+            quirks.wrap_sprites = !clip;
+        }
+
+        let color = |color: &Option<String>| color.as_deref().and_then(|c| parse_color(c).ok());
+        let palette = match (color(&options.fill_color), color(&options.background_color)) {
+            (Some(foreground), Some(background)) => Some(
+                Palette::default()
+                    .with_foreground(foreground)
+                    .with_background(background),
+            ),
+            _ => None,
+        };
+
+        Ok(Self {
+            rom,
+            info: RomInfo {
+                title: title.to_string(),
+                authors: vec![],
+                description: None,
+                platform: Some(platform),
+                quirks: Some(quirks),
+                instructions_per_frame: options.tickrate,
+                palette,
+                keys: vec![],
+            },
+        })
+    }
+}
+
+/// Reads the payload hidden in the frames of a GIF.
+fn payload(image: &[u8]) -> Result<Vec<u8>, gif::DecodingError> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::RGBA);
+    let mut decoder = options.read_info(image)?;
+
+    let mut nibbles = vec![];
+    while let Some(frame) = decoder.read_next_frame()? {
+        nibbles.extend(frame.buffer.chunks_exact(4).map(|rgba| {
+            let (r, g, b) = (rgba[0], rgba[1], rgba[2]);
+            (r << 3) & 0x8 | (g << 1) & 0x6 | b & 0x1
+        }));
+    }
+
+    let bytes = nibbles
+        .chunks_exact(2)
+        .map(|pair| pair[0] << 4 | pair[1])
+        .collect::<Vec<_>>();
+
+    let Some((length, data)) = bytes.split_first_chunk::<4>() else {
+        return Ok(vec![]);
+    };
+    let length = u32::from_be_bytes(*length) as usize;
+
+    Ok(data[..length.min(data.len())].to_vec())
+}
+
+/// Reads a quirk stored as a boolean or a number.
+fn flag(value: &serde_json::Value) -> Option<bool> {
+    value
+        .as_bool()
+        .or_else(|| value.as_u64().map(|number| number != 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A GIF carrying `payload` after its length, as Octo writes cartridges.
+    fn cartridge(payload: &[u8]) -> Vec<u8> {
+        let bytes = [&(payload.len() as u32).to_be_bytes()[..], payload].concat();
+        let rgb = bytes
+            .iter()
+            .flat_map(|byte| [byte >> 4, byte & 0xF])
+            .flat_map(|nibble| [(nibble >> 3) & 0x1, (nibble >> 1) & 0x3, nibble & 0x1])
+            .collect::<Vec<_>>();
+
+        let mut image = vec![];
+        let width = (rgb.len() / 3) as u16;
+        let mut encoder = gif::Encoder::new(&mut image, width, 1, &[]).unwrap();
+        encoder
+            .write_frame(&gif::Frame::from_rgb(width, 1, &rgb))
+            .unwrap();
+        drop(encoder);
+        image
+    }
+
+    #[test]
+    fn payload_round_trips() {
+        let data = b"{\"program\": \": main\"}";
+        assert_eq!(payload(&cartridge(data)).unwrap(), data);
+    }
+
+    #[test]
+    fn decode_applies_options() {
+        let image = cartridge(
+            br#"{"program": ": main clear", "options": {"tickrate": 20, "maxSize": 3583, "clipQuirks": 0}}"#,
+        );
+        let cartridge = Cartridge::decode("Test", &image).unwrap();
+
+        assert_eq!(cartridge.rom, [0x12, 0x02, 0x00, 0xE0]);
+        assert_eq!(cartridge.info.title, "Test");
+        assert_eq!(cartridge.info.platform, Some(Platform::Superchip));
+        assert_eq!(cartridge.info.instructions_per_frame, Some(20));
+        assert!(cartridge.info.quirks.unwrap().wrap_sprites);
+    }
+
+    #[test]
+    fn decode_reports_compile_errors() {
+        let image = cartridge(br#"{"program": ": start"}"#);
+        let err = Cartridge::decode("Test", &image).err().unwrap();
+
+        assert_eq!(
+            err.to_string(),
+            "Can't compile Octo cartridge: Program has no 'main' label"
+        );
+    }
+}
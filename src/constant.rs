@@ -5,7 +5,7 @@ pub const HEIGHT: usize = 32;
 /// Offset in memory to place fonts.
 ///
 /// For some reason, it is popular to put the font from `050 - 09F`.
-pub const FONT_OFFSET: usize = 050;
+pub const FONT_OFFSET: usize = 0x050;
 
 pub const FONT_SIZE: usize = 5;
 
@@ -33,4 +33,9 @@ pub const MEMORY_SIZE: usize = 4096;
 /// Offset in memory to insert a rom.
 pub const ROM_LOAD_OFFSET: usize = 512;
 
+/// Where the stack is kept when it's in memory, as on the COSMAC VIP, which
+/// grows down from the end.
+pub const STACK_OFFSET: usize = 0xEA0;
+pub const STACK_END: usize = 0xED0;
+
 pub const FPS: f64 = 1. / 60.;
@@ -0,0 +1,235 @@
+//! Records which parts of a ROM the [crate::Emulator] ran as instructions or
+//! read as data, to see what a test ROM exercises.
+
+use std::fmt::Write;
+
+use crate::constant::MEMORY_SIZE;
+use crate::opcode::disassemble_word;
+
+pub struct Coverage {
+    /// Where the ROM was loaded.
+    start: usize,
+    rom: Vec<u8>,
+    /// Times an instruction started at each address in memory.
+    executed: Vec<u64>,
+    /// Times each address in memory was read as data, e.g. by a sprite being
+    /// drawn.
+    read: Vec<u64>,
+}
+
+impl Coverage {
+    /// Starts recording the coverage of `rom`, loaded at `start`.
+    pub fn new(start: usize, rom: Vec<u8>) -> Self {
+        Self {
+            start,
+            rom,
+            executed: vec![0; MEMORY_SIZE],
+            read: vec![0; MEMORY_SIZE],
+        }
+    }
+
+    /// Notes that the instruction at `pc` is about to run.
+    pub(crate) fn execute(&mut self, pc: u16) {
+        self.executed[pc as usize] += 1;
+    }
+
+    /// Notes that the `len` bytes from `address` were read as data.
+    pub(crate) fn read(&mut self, address: usize, len: usize) {
+        for read in self.read.iter_mut().skip(address).take(len) {
+            *read += 1;
+        }
+    }
+
+    /// Addresses of the ROM, two bytes at a time as instructions are, with
+    /// the last one being a single byte for ROMs of odd length.
+    fn words(&self) -> impl Iterator<Item = (usize, &[u64], &[u64])> + '_ {
+        let end = self.end();
+
+        (self.start..end).step_by(2).map(move |address| {
+            let next = (address + 2).min(end);
+            (
+                address,
+                &self.executed[address..next],
+                &self.read[address..next],
+            )
+        })
+    }
+
+    /// The ROM disassembled, with each line starting with how many times it
+    /// ran, `data` if it was only read as data, or `-` if neither.
+    pub fn listing(&self) -> String {
+        let mut listing = String::new();
+
+        for (address, executed, read) in self.words() {
+            let offset = address - self.start;
+            let line = match self.rom[offset..] {
+                [high, low, ..] => disassemble_word(address, [high, low]),
+                [byte] => format!("{address:03X}: {byte:02X}    DB {byte:#04X}"),
+                [] => unreachable!(),
+            };
+
+            let count = executed.iter().sum::<u64>();
+            let marker = if count > 0 {
+                count.to_string()
+            } else if read.iter().any(|&read| read > 0) {
+                "data".to_string()
+            } else {
+                "-".to_string()
+            };
+            let _ = writeln!(listing, "{marker:>10}  {line}");
+        }
+
+        listing
+    }
+
+    /// An lcov tracefile for `name`, where each line of [Coverage::listing]
+    /// is a line of source, and lines only read as data aren't counted.
+    pub fn lcov(&self, name: &str) -> String {
+        let mut lcov = format!("TN:\nSF:{name}\n");
+        let (mut found, mut hit) = (0, 0);
+
+        for (line, (_, executed, read)) in self.words().enumerate() {
+            let count = executed.iter().sum::<u64>();
+            if count == 0 && read.iter().any(|&read| read > 0) {
+                continue;
+            }
+
+            found += 1;
+            hit += (count > 0) as u32;
+            let _ = writeln!(lcov, "DA:{},{count}", line + 1);
+        }
+
+        let _ = writeln!(lcov, "LF:{found}\nLH:{hit}\nend_of_record");
+        lcov
+    }
+
+    /// How much of the ROM was run or read, in bytes.
+    pub fn summary(&self) -> String {
+        let rom = self.start..self.end();
+        let executed = self.executed[rom.clone()]
+            .iter()
+            .filter(|&&count| count > 0)
+            .count();
+        // Instructions are two bytes, but only their first is counted as
+        // executed.
+        let (mut code, mut data, mut both) = (0, 0, 0);
+        for address in rom.clone() {
+            let in_instruction = self.executed[address] > 0
+                || (address > rom.start && self.executed[address - 1] > 0);
+            let read = self.read[address] > 0;
+            match (in_instruction, read) {
+                (true, true) => both += 1,
+                (true, false) => code += 1,
+                (false, true) => data += 1,
+                (false, false) => {}
+            }
+        }
+
+        let touched = code + data + both;
+        let len = rom.len();
+        let percent = |bytes: usize| 100. * bytes as f64 / len.max(1) as f64;
+        let mut summary = format!(
+            "{len} bytes at {:#05X}-{:#05X}\n",
+            rom.start,
+            rom.end.saturating_sub(1)
+        );
+        let _ = writeln!(
+            summary,
+            "  {executed} different instructions run, {} bytes ({:.1}%)",
+            code + both,
+            percent(code + both)
+        );
+        let _ = writeln!(
+            summary,
+            "  {} bytes read as data ({:.1}%)",
+            data + both,
+            percent(data + both)
+        );
+        let _ = writeln!(
+            summary,
+            "  {} bytes untouched ({:.1}%)",
+            len - touched,
+            percent(len - touched)
+        );
+
+        summary
+    }
+
+    /// End of the ROM in memory.
+    fn end(&self) -> usize {
+        (self.start + self.rom.len()).min(MEMORY_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::Emulator;
+    use crate::pacing::Pacing;
+    use crate::quirks::Quirks;
+    use crate::window::headless::HeadlessWindow;
+
+    /// 200: LD I, 0x208; DRW V0, V0, 1; JP 0x204; CLS
+    /// 208: a 1 byte sprite
+    const ROM: [u8; 9] = [0xA2, 0x08, 0xD0, 0x01, 0x12, 0x04, 0x00, 0xE0, 0xF0];
+
+    /// The coverage of [ROM] after a frame of 10 instructions.
+    fn coverage() -> Coverage {
+        let mut emulator = Emulator::new(
+            Box::new(HeadlessWindow::new(1)),
+            Quirks::default(),
+            Pacing {
+                instructions_per_frame: 10,
+                ..Pacing::default()
+            },
+        );
+        emulator.load_rom("test", ROM.to_vec()).unwrap();
+        emulator.set_coverage(Coverage::new(0x200, ROM.to_vec()));
+        emulator.start();
+        emulator.take_coverage().unwrap()
+    }
+
+    #[test]
+    fn counts_instructions_run_and_bytes_read() {
+        let coverage = coverage();
+
+        assert_eq!(coverage.executed[0x200..0x209], [1, 0, 1, 0, 8, 0, 0, 0, 0]);
+        assert_eq!(coverage.read[0x200..0x209], [0, 0, 0, 0, 0, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn listing_marks_each_word() {
+        assert_eq!(
+            coverage().listing(),
+            concat!(
+                "         1  200: A208  LD I, 0x208\n",
+                "         1  202: D001  DRW V0, V0, 1\n",
+                "         8  204: 1204  JP 0x204\n",
+                "         -  206: 00E0  CLS\n",
+                "      data  208: F0    DB 0xF0\n",
+            )
+        );
+    }
+
+    #[test]
+    fn lcov_skips_data() {
+        // The sprite at 208 isn't a line of code.
+        assert_eq!(
+            coverage().lcov("test.ch8"),
+            "TN:\nSF:test.ch8\nDA:1,1\nDA:2,1\nDA:3,8\nDA:4,0\nLF:4\nLH:3\nend_of_record\n"
+        );
+    }
+
+    #[test]
+    fn summary_counts_bytes() {
+        assert_eq!(
+            coverage().summary(),
+            concat!(
+                "9 bytes at 0x200-0x208\n",
+                "  3 different instructions run, 6 bytes (66.7%)\n",
+                "  1 bytes read as data (11.1%)\n",
+                "  2 bytes untouched (22.2%)\n",
+            )
+        );
+    }
+}
@@ -0,0 +1,134 @@
+//! Settings for known ROMs, from a database bundled into the binary.
+//!
+//! The database follows the layout of `programs.json` from the community
+//! [CHIP-8 database](https://github.com/chip-8/chip-8-database), where each
+//! program lists its ROMs keyed by the SHA-1 of their bytes.
+//!
+//! Only the ROMs in `roms/` are bundled so far, so for other ROMs the
+//! platform and speed have to be given on the command line.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::OnceLock;
+
+use serde::Deserialize;
+
+use crate::keypad::Key;
+use crate::palette::{parse_color, Palette};
+use crate::quirks::{Platform, Quirks};
+
+const DATABASE: &str = include_str!("../data/roms.json");
+
+#[derive(Deserialize)]
+struct Program {
+    title: String,
+    #[serde(default)]
+    authors: Vec<String>,
+    description: Option<String>,
+    roms: HashMap<String, Rom>,
+}
+
+#[derive(Deserialize)]
+struct Rom {
+    #[serde(default)]
+    platforms: Vec<String>,
+    tickrate: Option<usize>,
+    colors: Option<Colors>,
+    #[serde(default)]
+    keys: BTreeMap<String, u8>,
+}
+
+#[derive(Deserialize)]
+struct Colors {
+    /// Background first, then the foreground.
+    #[serde(default)]
+    pixels: Vec<String>,
+}
+
+/// What the database, or an Octo cartridge, knows about a ROM.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RomInfo {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub description: Option<String>,
+    /// The first platform the ROM runs on which is supported.
+    pub platform: Option<Platform>,
+    /// Quirks to run with, when they differ from those of the platform.
+    pub quirks: Option<Quirks>,
+    pub instructions_per_frame: Option<usize>,
+    pub palette: Option<Palette>,
+    /// What each key does in the program, e.g. `("up", Key::W)`.
+    pub keys: Vec<(String, Key)>,
+}
+
+/// The bundled database, which is only parsed the first time it's needed.
+fn programs() -> &'static [Program] {
+    static PROGRAMS: OnceLock<Vec<Program>> = OnceLock::new();
+
+    PROGRAMS.get_or_init(|| {
+        serde_json::from_str(DATABASE).expect("the bundled ROM database is invalid")
+    })
+}
+
+impl RomInfo {
+    /// Finds `rom` in the bundled database.
+    pub fn lookup(rom: &[u8]) -> Option<RomInfo> {
+        let hash = sha1(rom);
+
+        programs().iter().find_map(|program| {
+            let rom = program.roms.get(&hash)?;
+
+            let colors = rom.colors.as_ref().map_or(&[][..], |colors| &colors.pixels);
+            let palette = match colors {
+                [background, foreground, ..] => Some(
+                    Palette::default()
+                        .with_background(parse_color(background).ok()?)
+                        .with_foreground(parse_color(foreground).ok()?),
+                ),
+                _ => None,
+            };
+
+            Some(RomInfo {
+                title: program.title.clone(),
+                authors: program.authors.clone(),
+                description: program.description.clone(),
+                platform: rom
+                    .platforms
+                    .iter()
+                    .find_map(|platform| platform.parse().ok()),
+                quirks: None,
+                instructions_per_frame: rom.tickrate,
+                palette,
+                keys: rom
+                    .keys
+                    .iter()
+                    .filter(|(_, keypad)| **keypad <= 0xF)
+                    .map(|(action, keypad)| (action.clone(), Key::from(*keypad)))
+                    .collect(),
+            })
+        })
+    }
+}
+
+/// SHA-1 of `rom` as lowercase hex, which identifies it in the database.
+pub fn sha1(rom: &[u8]) -> String {
+    sha1_smol::Sha1::from(rom).digest().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_bundled_roms() {
+        let info = RomInfo::lookup(include_bytes!("../roms/maze.ch8")).unwrap();
+
+        assert_eq!(info.title, "Maze");
+        assert_eq!(info.platform, Some(Platform::Chip8));
+        assert_eq!(info.instructions_per_frame, Some(10));
+    }
+
+    #[test]
+    fn unknown_roms_arent_found() {
+        assert_eq!(RomInfo::lookup(&[0x12, 0x00]), None);
+    }
+}
@@ -1,19 +1,33 @@
 use crate::constant::*;
+use crate::coverage::Coverage;
+use crate::error::Error;
 use crate::keypad::Key;
 use crate::opcode::Opcode;
+use crate::pacing::{self, FrameLimiter, Pacing, SLOW_MOTION};
+use crate::profile::Profile;
+use crate::quirks::Quirks;
 use crate::screen::Screen;
-use crate::window::Window;
+use crate::stack::{Push, Stack};
+use crate::sys::{Machine, SysHandler};
+use crate::timing;
+use crate::trace::{Step, Trace};
+use crate::window::{Hotkey, Status, Window};
+use log::{debug, error, info, trace, warn};
 use rand::{rngs::StdRng, Rng, SeedableRng};
-use std::time::Instant;
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::time::{Duration, Instant};
 
 pub struct Emulator {
     memory: [u8; MEMORY_SIZE],
-    verbose: bool,
+    quirks: Quirks,
+    pacing: Pacing,
+    limiter: FrameLimiter,
     registers: [u8; 16],
 
     pc: u16,
     index: u16,
-    stack: Vec<u16>,
+    stack: Stack,
 
     delay_timer: u8,
     sound_timer: u8,
@@ -23,25 +37,69 @@ pub struct Emulator {
     screen: Screen,
     window: Box<dyn Window + 'static>,
 
-    last_ins_time: Instant,
+    paused: bool,
+    /// Whether the program stopped, e.g. on a stack overflow, so no more
+    /// instructions run.
+    halted: bool,
+    /// Whether the stack wrapping around was warned about.
+    warned_stack_wrap: bool,
+    /// Whether to run one frame while paused.
+    advance: bool,
+    /// Index into [SLOW_MOTION].
+    slow_motion: usize,
+
+    status: Status,
+    /// Start of the second which instructions and frames are being counted
+    /// over, for the rates in [Status].
+    second_start: Instant,
+    instructions: u32,
+    frames: u32,
+    /// Frames which ended after their deadline, and the latest any ended.
+    overruns: u32,
+    worst_overrun: Duration,
+
+    /// Instructions run since the ROM was loaded.
+    cycles: u64,
+    /// Machine cycles left in the current frame with [Pacing::vip_timing],
+    /// which is negative when the last instruction ran past the end of it.
+    machine_cycles: i64,
+    trace: Option<Trace>,
+    profile: Option<Profile>,
+    coverage: Option<Coverage>,
+    /// Handlers for `SYS nnn`, by `nnn`.
+    sys_handlers: HashMap<u16, SysHandler>,
+    /// Addresses called with `SYS nnn` which have no handler and were warned
+    /// about, so each is only warned about once.
+    unhandled_sys: HashSet<u16>,
 }
 
 impl Emulator {
-    pub fn new(window: Box<dyn Window + 'static>, verbose: bool) -> Self {
+    /// Creates an emulator showing its screen in `window`, where `quirks`
+    /// should have passed [Quirks::check].
+    pub fn new(window: Box<dyn Window + 'static>, quirks: Quirks, pacing: Pacing) -> Self {
         let mut memory: [u8; MEMORY_SIZE] = [0; MEMORY_SIZE];
         FONTS
             .into_iter()
             .enumerate()
             .for_each(|(i, char)| memory[FONT_OFFSET + i] = char);
 
+        info!("Quirks: {quirks}");
+        if pacing.vip_timing {
+            debug!("COSMAC VIP timing");
+        } else {
+            debug!("{} instructions per frame", pacing.instructions_per_frame);
+        }
+
         Self {
             memory,
             screen: Screen::new(),
-            verbose,
+            quirks,
+            pacing,
+            limiter: FrameLimiter::new(),
             // max pc is actually u12 (from nnn which is 12 bytes).
             pc: ROM_LOAD_OFFSET as u16,
             index: 0,
-            stack: vec![],
+            stack: Stack::new(&quirks),
 
             delay_timer: u8::MAX,
             sound_timer: u8::MAX,
@@ -51,238 +109,876 @@ impl Emulator {
             rng: StdRng::seed_from_u64(1),
             window,
 
-            last_ins_time: Instant::now(),
+            paused: false,
+            halted: false,
+            warned_stack_wrap: false,
+            advance: false,
+            slow_motion: 0,
+
+            status: Status {
+                quirks,
+                speed: 1.,
+                ..Status::default()
+            },
+            second_start: Instant::now(),
+            instructions: 0,
+            frames: 0,
+            overruns: 0,
+            worst_overrun: Duration::ZERO,
+
+            cycles: 0,
+            machine_cycles: 0,
+            trace: None,
+            profile: None,
+            coverage: None,
+            sys_handlers: HashMap::new(),
+            unhandled_sys: HashSet::new(),
         }
     }
-    pub fn load_rom(&mut self, rom: Vec<u8>) {
-        self.memory[ROM_LOAD_OFFSET..(ROM_LOAD_OFFSET + rom.len())].copy_from_slice(rom.as_slice());
+
+    /// Gives back the window, e.g. to show a [crate::Menu] after the program
+    /// exits.
+    pub fn into_window(self) -> Box<dyn Window + 'static> {
+        self.window
+    }
+
+    /// Logs each instruction run from now on to `trace`.
+    pub fn set_trace(&mut self, trace: Trace) {
+        self.trace = Some(trace);
+    }
+
+    /// Stops tracing, giving back the trace to be finished.
+    pub fn take_trace(&mut self) -> Option<Trace> {
+        self.trace.take()
+    }
+
+    /// Counts where the program spends its time from now on in `profile`.
+    pub fn set_profile(&mut self, profile: Profile) {
+        self.profile = Some(profile);
+    }
+
+    /// Stops profiling, giving back the profile to report.
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+
+    /// Records which addresses are run or read as data from now on in
+    /// `coverage`.
+    pub fn set_coverage(&mut self, coverage: Coverage) {
+        self.coverage = Some(coverage);
+    }
+
+    /// Stops recording coverage, giving it back to report.
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take()
+    }
+
+    /// Runs `handler` whenever the program calls the machine code routine at
+    /// `address` with `SYS nnn`, replacing any handler already there. Calls to
+    /// addresses without a handler are skipped.
+    pub fn set_sys_handler(&mut self, address: u16, handler: SysHandler) {
+        self.sys_handlers.insert(address, handler);
+    }
+
+    /// Where each subroutine which hasn't returned yet was called from, the
+    /// innermost first.
+    pub fn backtrace(&self) -> Vec<u16> {
+        self.stack
+            .return_addresses(&self.memory)
+            .into_iter()
+            .map(|address| address.wrapping_sub(2))
+            .collect()
+    }
+
+    /// [Emulator::backtrace] as text, for logs.
+    fn backtrace_text(&self) -> String {
+        let backtrace = self.backtrace();
+        if backtrace.is_empty() {
+            return "no calls".to_string();
+        }
+
+        backtrace
+            .iter()
+            .enumerate()
+            .map(|(i, call)| format!("#{i} {call:03X}"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Instructions run since the ROM was loaded.
+    pub fn instructions(&self) -> u64 {
+        self.cycles
+    }
+
+    /// What the program has drawn.
+    pub fn screen(&self) -> &Screen {
+        &self.screen
+    }
+
+    /// Loads `rom` at [ROM_LOAD_OFFSET], where `name` is only used for
+    /// display.
+    pub fn load_rom(&mut self, name: &str, rom: Vec<u8>) -> Result<(), Error> {
+        self.load_rom_at(name, rom, ROM_LOAD_OFFSET)
+    }
+
+    /// Loads `rom` at `offset` in memory, and starts executing from there.
+    ///
+    /// Most ROMs are loaded at [ROM_LOAD_OFFSET], but some platforms differ,
+    /// e.g. ROMs for the ETI-660 start at `0x600`.
+    pub fn load_rom_at(&mut self, name: &str, rom: Vec<u8>, offset: usize) -> Result<(), Error> {
+        Self::check_rom(name, &rom, offset)?;
+
+        self.status.rom = name.to_string();
+        self.memory[offset..offset + rom.len()].copy_from_slice(rom.as_slice());
+        self.pc = offset as u16;
+        info!("Loaded '{name}', {} bytes at {offset:#05X}", rom.len());
+
+        Ok(())
+    }
+
+    /// Checks that [Emulator::load_rom_at] can load `rom` at `offset`, so that
+    /// problems can be reported before a window is opened.
+    pub fn check_rom(name: &str, rom: &[u8], offset: usize) -> Result<(), Error> {
+        if rom.is_empty() {
+            return Err(Error::ErrorStr(format!("ROM '{name}' is empty")));
+        }
+        // The PC is always even, and the font is below the usual offset.
+        if !offset.is_multiple_of(2) || offset < FONT_OFFSET + FONTS.len() || offset >= MEMORY_SIZE
+        {
+            return Err(Error::ErrorStr(format!(
+                "Can't load a ROM at {offset:#05X}, expected an even address from {:#05X} to {:#05X}",
+                FONT_OFFSET + FONTS.len(),
+                MEMORY_SIZE - 2
+            )));
+        }
+        if rom.len() > MEMORY_SIZE - offset {
+            return Err(Error::ErrorStr(format!(
+                "ROM '{name}' is {} bytes, but only {} bytes of memory are free after {offset:#05X}",
+                rom.len(),
+                MEMORY_SIZE - offset
+            )));
+        }
+
+        Ok(())
     }
 
     pub fn start(&mut self) {
         while self.window.is_running() {
-            let opcode = Opcode::from([
-                self.memory[self.pc as usize],
-                self.memory[self.pc as usize + 1],
-            ]);
-
-            if self.verbose {
-                let current_ins_time = Instant::now();
-                self.last_ins_time = current_ins_time;
-                let time_since_last_ins = current_ins_time.duration_since(self.last_ins_time);
-                println!(
-                    "[+{}ms] pc {} {opcode:?}",
-                    time_since_last_ins.as_millis(),
-                    self.pc,
-                );
+            for hotkey in self.window.hotkeys_pressed() {
+                if hotkey == Hotkey::Exit {
+                    return;
+                }
+                self.handle_hotkey(hotkey);
             }
 
-            self.pc += 2;
+            let speed = self.speed();
+            let deadline = self.limiter.start_frame(pacing::frame_period(speed));
 
-            match opcode {
-                Opcode::Sys { nnn } => unimplemented!("SYS {nnn} is unimplemented"),
-                Opcode::Clear => {
-                    self.screen.clear();
-                }
-                Opcode::Return => {
-                    let addr = self.stack.pop().unwrap();
-                    self.pc = addr;
-                }
-                Opcode::Jump { nnn } => {
-                    self.pc = nnn;
+            if !self.paused || mem::take(&mut self.advance) {
+                match pacing::frames_per_shown(speed) {
+                    Some(frames) => {
+                        for _ in 0..frames {
+                            self.run_frame();
+                        }
+                    }
+                    None => {
+                        self.run_frame();
+                        while Instant::now() < deadline {
+                            self.run_frame();
+                        }
+                    }
                 }
-                Opcode::Call { nnn } => {
-                    self.stack.push(self.pc);
-                    self.pc = nnn;
+            }
+
+            self.update_status(speed);
+
+            self.window
+                .update(
+                    self.screen.framebuffer(),
+                    self.screen.damage(),
+                    &self.status,
+                )
+                .unwrap();
+            self.screen.present();
+            self.frames += 1;
+
+            // Slow motion still needs the limiter to wait, as the window only
+            // waits for the next refresh.
+            let wait = !(self.window.paces_frames() && speed >= 1.);
+            if wait && !speed.is_infinite() {
+                let overrun = Instant::now().saturating_duration_since(deadline);
+                if !overrun.is_zero() {
+                    self.overruns += 1;
+                    self.worst_overrun = self.worst_overrun.max(overrun);
                 }
-                Opcode::SkipEqualsConstant { vx, nn } => {
-                    let x = self.registers[vx as usize];
-                    if x == nn {
-                        self.pc += 2;
+            }
+            self.limiter.end_frame(wait);
+        }
+    }
+
+    /// How many times faster than normal to run the next frame.
+    fn speed(&self) -> f64 {
+        if self.window.is_hotkey_down(Hotkey::Turbo) {
+            self.pacing.turbo.speed()
+        } else {
+            SLOW_MOTION[self.slow_motion]
+        }
+    }
+
+    fn handle_hotkey(&mut self, hotkey: Hotkey) {
+        match hotkey {
+            Hotkey::Pause => self.paused = !self.paused,
+            Hotkey::FrameAdvance => {
+                self.paused = true;
+                self.advance = true;
+            }
+            // Handled in `speed`, as it only applies while held.
+            Hotkey::Turbo => {}
+            // Handled in `start`.
+            Hotkey::Exit => {}
+            Hotkey::SlowMotion => self.slow_motion = (self.slow_motion + 1) % SLOW_MOTION.len(),
+        }
+    }
+
+    /// Runs the instructions of one frame, and then ticks the timers.
+    fn run_frame(&mut self) {
+        if self.pacing.vip_timing {
+            self.run_vip_frame();
+        } else {
+            for _ in 0..self.pacing.instructions_per_frame {
+                if self.halted {
+                    break;
+                }
+                self.step();
+            }
+        }
+
+        self.delay_timer -= if self.delay_timer > 0 { 1 } else { 0 };
+        self.sound_timer -= if self.sound_timer > 0 { 1 } else { 0 };
+    }
+
+    /// Runs the instructions which fit in the machine cycles the COSMAC VIP
+    /// has between two interrupts, after those the display takes.
+    fn run_vip_frame(&mut self) {
+        // Nothing runs again once halted, so the cycles would only pile up.
+        if self.halted {
+            return;
+        }
+        self.machine_cycles += timing::FRAME_CYCLES - timing::DISPLAY_CYCLES;
+
+        let mut first = true;
+        while self.machine_cycles > 0 && !self.halted {
+            let Some(word) = self.fetch() else {
+                break;
+            };
+            let opcode = Opcode::decode(word);
+
+            // The interpreter waits for the next interrupt before drawing, so
+            // sprites are only drawn at the start of a frame.
+            if !first && matches!(opcode, Some(Opcode::Draw { .. })) {
+                self.machine_cycles = 0;
+                break;
+            }
+
+            let cost = opcode.map_or(timing::FETCH_CYCLES, |opcode| {
+                timing::cycles(&opcode, &self.registers)
+            });
+            self.machine_cycles -= cost as i64;
+            self.step();
+            first = false;
+        }
+    }
+
+    /// Reads the instruction at the PC, or halts if the PC has left memory,
+    /// e.g. by running off the end of it.
+    fn fetch(&mut self) -> Option<[u8; 2]> {
+        let pc = self.pc as usize;
+        if pc + 1 >= MEMORY_SIZE {
+            self.halted = true;
+            error!(
+                "PC left memory at {pc:03X}, halting with {} calls nested: {}",
+                self.stack.len(),
+                self.backtrace_text()
+            );
+            return None;
+        }
+
+        Some([self.memory[pc], self.memory[pc + 1]])
+    }
+
+    /// Address `offset` bytes past I, which wraps around to the start of
+    /// memory past the end of it.
+    fn index_address(&self, offset: usize) -> usize {
+        (self.index as usize + offset) % MEMORY_SIZE
+    }
+
+    /// Runs a single instruction.
+    fn step(&mut self) {
+        let Some(word) = self.fetch() else {
+            return;
+        };
+        let Some(opcode) = Opcode::decode(word) else {
+            self.halted = true;
+            error!(
+                "Unknown opcode {:04X} at {:03X}, halting with {} calls nested: {}",
+                u16::from_be_bytes(word),
+                self.pc,
+                self.stack.len(),
+                self.backtrace_text()
+            );
+            return;
+        };
+
+        if let Some(trace) = self.trace.as_mut().filter(|trace| trace.traces(self.pc)) {
+            trace.record(&Step {
+                cycle: self.cycles,
+                pc: self.pc,
+                word: u16::from_be_bytes(word),
+                opcode: &opcode,
+                registers: &self.registers,
+                index: self.index,
+                delay_timer: self.delay_timer,
+                sound_timer: self.sound_timer,
+            });
+        }
+        self.cycles += 1;
+        if let Some(profile) = &mut self.profile {
+            profile.record(self.pc, word, &opcode);
+        }
+        if let Some(coverage) = &mut self.coverage {
+            coverage.execute(self.pc);
+        }
+
+        trace!("{:03X}: {opcode}", self.pc);
+
+        self.pc += 2;
+
+        match opcode {
+            Opcode::Sys { nnn } => match self.sys_handlers.get_mut(&nnn) {
+                Some(handler) => handler(&mut Machine {
+                    memory: &mut self.memory,
+                    registers: &mut self.registers,
+                    index: &mut self.index,
+                    delay_timer: &mut self.delay_timer,
+                    sound_timer: &mut self.sound_timer,
+                    screen: &mut self.screen,
+                }),
+                None => {
+                    if self.unhandled_sys.insert(nnn) {
+                        warn!(
+                            "Skipping SYS {nnn:03X} at {:03X}, as machine code isn't run",
+                            self.pc - 2
+                        );
                     }
                 }
-                Opcode::SkipNotEqualsConstant { vx, nn } => {
-                    let x = self.registers[vx as usize];
-                    if x != nn {
-                        self.pc += 2;
+            },
+            Opcode::Clear => {
+                self.screen.clear();
+            }
+            Opcode::Return => match self.stack.pop(&self.memory) {
+                Some(addr) => {
+                    self.pc = addr;
+                    if let Some(profile) = &mut self.profile {
+                        profile.ret();
                     }
                 }
-                Opcode::SkipEquals { vx, vy } => {
-                    let x = self.registers[vx as usize];
-                    let y = self.registers[vy as usize];
-                    if x == y {
-                        self.pc += 2;
+                None => error!("Ignoring return at {:03X} with an empty stack", self.pc - 2),
+            },
+            Opcode::Jump { nnn } => {
+                self.pc = nnn;
+            }
+            Opcode::Call { nnn } => match self.stack.push(&mut self.memory, self.pc) {
+                Push::Overflowed => {
+                    self.pc -= 2;
+                    self.halted = true;
+                    error!(
+                        "Stack overflow calling {nnn:03X} at {:03X}, halting with {} calls nested: {}",
+                        self.pc,
+                        self.stack.len(),
+                        self.backtrace_text()
+                    );
+                }
+                push => {
+                    if push == Push::Wrapped && !mem::replace(&mut self.warned_stack_wrap, true) {
+                        warn!(
+                            "Stack wrapped around calling {nnn:03X} at {:03X}, overwriting the oldest return address",
+                            self.pc - 2
+                        );
+                    }
+
+                    self.pc = nnn;
+                    if let Some(profile) = &mut self.profile {
+                        profile.call(nnn, self.stack.len());
                     }
                 }
-                Opcode::Load { vx, nn } => {
-                    self.registers[vx as usize] = nn;
-                }
-                Opcode::AddConstant { vx, nn } => {
-                    let x = self.registers[vx as usize];
-                    let (result, _) = x.overflowing_add(nn);
-                    self.registers[vx as usize] = result;
-                }
-                Opcode::LoadRegister { vx, vy } => {
-                    self.registers[vx as usize] = self.registers[vy as usize];
-                }
-                Opcode::Or { vx, vy } => {
-                    let x = self.registers[vx as usize];
-                    let y = self.registers[vy as usize];
-                    self.registers[vx as usize] = x | y;
-                }
-                Opcode::And { vx, vy } => {
-                    let x = self.registers[vx as usize];
-                    let y = self.registers[vy as usize];
-                    self.registers[vx as usize] = x & y;
+            },
+            Opcode::SkipEqualsConstant { vx, nn } => {
+                let x = self.registers[vx as usize];
+                if x == nn {
+                    self.pc += 2;
                 }
-                Opcode::Xor { vx, vy } => {
-                    let x = self.registers[vx as usize];
-                    let y = self.registers[vy as usize];
-                    self.registers[vx as usize] = x ^ y;
+            }
+            Opcode::SkipNotEqualsConstant { vx, nn } => {
+                let x = self.registers[vx as usize];
+                if x != nn {
+                    self.pc += 2;
                 }
-                Opcode::Add { vx, vy } => {
-                    let x = self.registers[vx as usize];
-                    let y = self.registers[vy as usize];
-                    let (result, carry) = x.overflowing_add(y);
-                    self.registers[0xF] = carry as u8;
-                    self.registers[vx as usize] = result;
+            }
+            Opcode::SkipEquals { vx, vy } => {
+                let x = self.registers[vx as usize];
+                let y = self.registers[vy as usize];
+                if x == y {
+                    self.pc += 2;
                 }
-                Opcode::Sub { vx, vy } => {
-                    let x = self.registers[vx as usize];
-                    let y = self.registers[vy as usize];
+            }
+            Opcode::Load { vx, nn } => {
+                self.registers[vx as usize] = nn;
+            }
+            Opcode::AddConstant { vx, nn } => {
+                let x = self.registers[vx as usize];
+                let (result, _) = x.overflowing_add(nn);
+                self.registers[vx as usize] = result;
+            }
+            Opcode::LoadRegister { vx, vy } => {
+                self.registers[vx as usize] = self.registers[vy as usize];
+            }
+            Opcode::Or { vx, vy } => {
+                let x = self.registers[vx as usize];
+                let y = self.registers[vy as usize];
+                self.registers[vx as usize] = x | y;
+            }
+            Opcode::And { vx, vy } => {
+                let x = self.registers[vx as usize];
+                let y = self.registers[vy as usize];
+                self.registers[vx as usize] = x & y;
+            }
+            Opcode::Xor { vx, vy } => {
+                let x = self.registers[vx as usize];
+                let y = self.registers[vy as usize];
+                self.registers[vx as usize] = x ^ y;
+            }
+            Opcode::Add { vx, vy } => {
+                let x = self.registers[vx as usize];
+                let y = self.registers[vy as usize];
+                let (result, carry) = x.overflowing_add(y);
+                self.registers[0xF] = carry as u8;
+                self.registers[vx as usize] = result;
+            }
+            Opcode::Sub { vx, vy } => {
+                let x = self.registers[vx as usize];
+                let y = self.registers[vy as usize];
 
-                    let (result, overflow) = x.overflowing_sub(y);
-                    self.registers[0xF] = !overflow as u8;
-                    self.registers[vx as usize] = result;
-                }
-                Opcode::ShiftRight { vx } => {
-                    let x = self.registers[vx as usize];
+                let (result, overflow) = x.overflowing_sub(y);
+                self.registers[0xF] = !overflow as u8;
+                self.registers[vx as usize] = result;
+            }
+            Opcode::ShiftRight { vx } => {
+                let x = self.registers[vx as usize];
 
-                    // LSB is set?
-                    self.registers[0xF] = (x & 1 == 1) as u8;
-                    self.registers[vx as usize] = x >> 1;
-                }
-                Opcode::Subn { vx, vy } => {
-                    let x = self.registers[vx as usize];
-                    let y = self.registers[vy as usize];
+                // LSB is set?
+                self.registers[0xF] = (x & 1 == 1) as u8;
+                self.registers[vx as usize] = x >> 1;
+            }
+            Opcode::Subn { vx, vy } => {
+                let x = self.registers[vx as usize];
+                let y = self.registers[vy as usize];
 
-                    let (result, overflow) = y.overflowing_sub(x);
-                    self.registers[0xF] = !overflow as u8;
-                    self.registers[vx as usize] = result;
-                }
-                Opcode::ShiftLeft { vx } => {
-                    let x = self.registers[vx as usize];
+                let (result, overflow) = y.overflowing_sub(x);
+                self.registers[0xF] = !overflow as u8;
+                self.registers[vx as usize] = result;
+            }
+            Opcode::ShiftLeft { vx } => {
+                let x = self.registers[vx as usize];
 
-                    // MSB is set?
-                    self.registers[0xF] = (x & (1 << (u8::BITS - 1)) > 0) as u8;
-                    self.registers[vx as usize] = x << 1;
-                }
-                Opcode::SkipNotEquals { vx, vy } => {
-                    let x = self.registers[vx as usize];
-                    let y = self.registers[vy as usize];
+                // MSB is set?
+                self.registers[0xF] = (x & (1 << (u8::BITS - 1)) > 0) as u8;
+                self.registers[vx as usize] = x << 1;
+            }
+            Opcode::SkipNotEquals { vx, vy } => {
+                let x = self.registers[vx as usize];
+                let y = self.registers[vy as usize];
 
-                    if x != y {
-                        self.pc += 2;
-                    }
-                }
-                Opcode::LoadIndex { nnn } => {
-                    self.index = nnn;
+                if x != y {
+                    self.pc += 2;
                 }
-                Opcode::JumpPlusV0 { nnn } => {
-                    self.pc = nnn + self.registers[0] as u16;
-                }
-                Opcode::Random { vx, nn } => {
-                    let random = self.rng.gen::<u8>();
-                    self.registers[vx as usize] = random & nn;
-                }
-                Opcode::Draw { vx, vy, n } => {
-                    let x = self.registers[vx as usize];
-                    let y = self.registers[vy as usize];
-
-                    let sprite =
-                        &self.memory[self.index as usize..(self.index as usize + n as usize)];
-
-                    let vf = &mut self.registers[0xF];
-                    *vf = 0;
-
-                    for (y_offset, row) in sprite.into_iter().enumerate() {
-                        for (x_offset, bit) in byte_to_bits(*row).into_iter().enumerate() {
-                            let turned_off =
-                                self.screen
-                                    .set(x as usize + x_offset, y as usize + y_offset, bit);
-                            *vf |= turned_off as u8;
-                        }
-                    }
+            }
+            Opcode::LoadIndex { nnn } => {
+                self.index = nnn;
+            }
+            Opcode::JumpPlusV0 { nnn } => {
+                self.pc = nnn + self.registers[0] as u16;
+            }
+            Opcode::Random { vx, nn } => {
+                let random = self.rng.gen::<u8>();
+                self.registers[vx as usize] = random & nn;
+            }
+            Opcode::Draw { vx, vy, n } => {
+                let x = self.registers[vx as usize];
+                let y = self.registers[vy as usize];
+
+                let mut sprite = [0; 0xF];
+                let sprite = &mut sprite[..n as usize];
+                for (i, byte) in sprite.iter_mut().enumerate() {
+                    *byte = self.memory[self.index_address(i)];
                 }
-                Opcode::KeyPressSkip { vx } => {
-                    let keypad = self.registers[vx as usize];
-                    if self.window.is_key_down(Key::from(keypad)) {
-                        self.pc += 2;
-                    }
+                if let Some(coverage) = &mut self.coverage {
+                    coverage.read(self.index as usize, n as usize);
                 }
-                Opcode::KeyNotPressSkip { vx } => {
-                    let keypad = self.registers[vx as usize];
-                    if self.window.is_key_up(Key::from(keypad)) {
-                        self.pc += 2;
-                    }
+
+                let collided_rows = self.screen.draw_sprite(
+                    x as usize,
+                    y as usize,
+                    sprite,
+                    self.quirks.wrap_sprites,
+                );
+
+                self.registers[0xF] = if self.quirks.count_collided_rows {
+                    collided_rows as u8
+                } else {
+                    (collided_rows > 0) as u8
+                };
+            }
+            Opcode::KeyPressSkip { vx } => {
+                let keypad = self.registers[vx as usize];
+                if self.window.is_key_down(Key::from(keypad)) {
+                    self.pc += 2;
                 }
-                Opcode::DelayTimerLoadFrom { vx } => {
-                    self.registers[vx as usize] = self.delay_timer;
+            }
+            Opcode::KeyNotPressSkip { vx } => {
+                let keypad = self.registers[vx as usize];
+                if self.window.is_key_up(Key::from(keypad)) {
+                    self.pc += 2;
                 }
-                Opcode::KeyLoad { vx } => {
-                    match self.window.wait_for_next_key() {
-                        // Move the PC back which should execute this instruction
-                        // again.
-                        None => self.pc -= 2,
-                        Some(key) => self.registers[vx as usize] = key.to_keypad(),
-                    }
+            }
+            Opcode::DelayTimerLoadFrom { vx } => {
+                self.registers[vx as usize] = self.delay_timer;
+            }
+            Opcode::KeyLoad { vx } => {
+                match self.window.wait_for_next_key() {
+                    // Move the PC back which should execute this instruction
+                    // again.
+                    None => self.pc -= 2,
+                    Some(key) => self.registers[vx as usize] = key.to_keypad(),
                 }
-                Opcode::DelayTimerLoadInto { vx } => {
-                    self.delay_timer = self.registers[vx as usize];
+            }
+            Opcode::DelayTimerLoadInto { vx } => {
+                self.delay_timer = self.registers[vx as usize];
+            }
+            Opcode::SoundLoad { vx } => {
+                self.sound_timer = self.registers[vx as usize];
+            }
+            Opcode::AddIndex { vx } => {
+                self.index = self.index.wrapping_add(self.registers[vx as usize] as u16);
+            }
+            Opcode::LocateSprite { vx } => {
+                let sprite_number = self.registers[vx as usize];
+                self.index = FONT_OFFSET as u16 + sprite_number as u16 * FONT_SIZE as u16;
+                if let Some(coverage) = &mut self.coverage {
+                    coverage.read(self.index as usize, FONT_SIZE);
                 }
-                Opcode::SoundLoad { vx } => {
-                    self.sound_timer = self.registers[vx as usize];
+            }
+            Opcode::LoadBcd { vx } => {
+                let x = self.registers[vx as usize];
+                let ones = x % 10;
+                let tens = x / 10 % 10;
+                let hundreds = x / 100 % 10;
+
+                for (i, digit) in [hundreds, tens, ones].into_iter().enumerate() {
+                    let address = self.index_address(i);
+                    self.memory[address] = digit;
                 }
-                Opcode::AddIndex { vx } => {
-                    self.index += self.registers[vx as usize] as u16;
+            }
+            Opcode::StoreRegisters { vx } => {
+                for i in 0..=vx as usize {
+                    let address = self.index_address(i);
+                    self.memory[address] = self.registers[i];
                 }
-                Opcode::LocateSprite { vx } => {
-                    let sprite_number = self.registers[vx as usize];
-                    self.index = FONT_OFFSET as u16 + sprite_number as u16 * FONT_SIZE as u16;
+            }
+            Opcode::ReadRegisters { vx } => {
+                for i in 0..=vx as usize {
+                    self.registers[i] = self.memory[self.index_address(i)];
                 }
-                Opcode::LoadBcd { vx } => {
-                    let x = self.registers[vx as usize];
-                    let ones = x % 10;
-                    let tens = x / 10 % 10;
-                    let hundreds = x / 100 % 10;
-
-                    self.memory[self.index as usize] = hundreds;
-                    self.memory[self.index as usize + 1] = tens;
-                    self.memory[self.index as usize + 2] = ones;
+                if let Some(coverage) = &mut self.coverage {
+                    coverage.read(self.index as usize, vx as usize + 1);
                 }
-                Opcode::StoreRegisters { vx } => {
-                    let index = self.index as usize;
-                    let vx = vx as usize;
-                    let upper = index + vx;
+            }
+        };
 
-                    self.memory[index..=upper].copy_from_slice(&self.registers[..=vx]);
-                }
-                Opcode::ReadRegisters { vx } => {
-                    let index = self.index as usize;
-                    let vx = vx as usize;
-                    let upper = index + vx;
+        self.instructions += 1;
+    }
 
-                    self.registers[..=vx].copy_from_slice(&self.memory[index..=upper]);
-                }
-            };
+    fn update_status(&mut self, speed: f64) {
+        self.status.paused = self.paused;
+        self.status.halted = self.halted;
+        self.status.backtrace = self.backtrace();
+        self.status.speed = speed;
+        self.status.sound = self.sound_timer > 0;
+
+        if self.second_start.elapsed() >= Duration::from_secs(1) {
+            self.status.instructions_per_second = self.instructions;
+            self.status.frames_per_second = self.frames;
+            self.status.frame_times = self.limiter.take_frame_times();
 
-            self.window.update(self.screen.framebuffer()).unwrap();
+            if self.overruns > 0 {
+                warn!(
+                    "{} frames overran in the last second, the worst by {:.1}ms",
+                    self.overruns,
+                    self.worst_overrun.as_secs_f64() * 1000.
+                );
+            }
 
-            self.delay_timer -= if self.delay_timer > 0 { 1 } else { 0 };
-            self.sound_timer -= if self.sound_timer > 0 { 1 } else { 0 };
+            self.second_start = Instant::now();
+            self.instructions = 0;
+            self.frames = 0;
+            self.overruns = 0;
+            self.worst_overrun = Duration::ZERO;
         }
     }
 }
 
-fn byte_to_bits(byte: u8) -> Vec<bool> {
-    return (0..u8::BITS).rev().map(|i| byte >> i & 1 == 1).collect();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logger::Logger;
+    use crate::pacing::INSTRUCTIONS_PER_FRAME;
+    use crate::window::headless::HeadlessWindow;
+    use log::LevelFilter;
+    use std::cell::Cell;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+    use std::sync::{Arc, Mutex, OnceLock};
+
+    /// An emulator which has loaded `rom`, with the default quirks, and runs
+    /// for `frames` frames when started.
+    fn emulator(rom: &[u8], frames: u64) -> Emulator {
+        let mut emulator = Emulator::new(
+            Box::new(HeadlessWindow::new(frames)),
+            Quirks::default(),
+            Pacing::default(),
+        );
+        emulator.load_rom("test", rom.to_vec()).unwrap();
+        emulator
+    }
+
+    /// An emulator running `JP 200` for 3 frames, which has had `hotkeys`
+    /// pressed before starting.
+    fn emulator_after_hotkeys(hotkeys: &[Hotkey]) -> Emulator {
+        let mut emulator = emulator(&[0x12, 0x00], 3);
+        for hotkey in hotkeys {
+            emulator.handle_hotkey(*hotkey);
+        }
+        emulator
+    }
+
+    #[test]
+    fn font_is_at_0x050() {
+        // LD V0, 0xA; LD F, V0
+        let mut emulator = emulator(&[0x60, 0x0A, 0xF0, 0x29], 1);
+        emulator.step();
+        emulator.step();
+
+        assert_eq!(emulator.index, 0x050 + 0xA * FONT_SIZE as u16);
+        assert_eq!(emulator.memory[0x050..0x050 + FONTS.len()], FONTS);
+    }
+
+    #[test]
+    fn unknown_opcode_halts() {
+        let mut emulator = emulator(&[0xFF, 0xFF], 1);
+        emulator.step();
+
+        assert!(emulator.halted);
+        assert_eq!(emulator.pc, 0x200);
+    }
+
+    #[test]
+    fn leaving_memory_halts() {
+        // JP FFF, where only one byte of an instruction fits.
+        let mut emulator = emulator(&[0x1F, 0xFF], 1);
+        emulator.step();
+        emulator.step();
+
+        assert!(emulator.halted);
+        assert_eq!(emulator.pc, 0xFFF);
+    }
+
+    #[test]
+    fn index_wraps_around_memory() {
+        // LD I, FFF; LD V0, 1; LD V1, 2; LD [I], V1; LD V0, 0; LD V0, [I]
+        let mut emulator = emulator(
+            &[
+                0xAF, 0xFF, 0x60, 0x01, 0x61, 0x02, 0xF1, 0x55, 0x60, 0x00, 0xF0, 0x65,
+            ],
+            1,
+        );
+        for _ in 0..6 {
+            emulator.step();
+        }
+
+        assert_eq!(emulator.memory[0xFFF], 1);
+        assert_eq!(emulator.memory[0x000], 2);
+        assert_eq!(emulator.registers[0], 1);
+        assert!(!emulator.halted);
+    }
+
+    #[test]
+    fn garbage_roms_run_without_panicking() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..100 {
+            let mut rom = vec![0; 3000];
+            rng.fill(&mut rom[..]);
+
+            for vip_timing in [false, true] {
+                let mut emulator = Emulator::new(
+                    Box::new(HeadlessWindow::new(200)),
+                    Quirks::default(),
+                    Pacing {
+                        vip_timing,
+                        ..Pacing::default()
+                    },
+                );
+                emulator.load_rom("garbage", rom.clone()).unwrap();
+                emulator.start();
+            }
+        }
+    }
+
+    #[test]
+    fn pause_stops_running_until_a_frame_is_advanced() {
+        // JP 200
+        let mut emulator = emulator_after_hotkeys(&[Hotkey::Pause]);
+        emulator.start();
+        assert_eq!(emulator.instructions(), 0);
+
+        let mut emulator = emulator_after_hotkeys(&[Hotkey::FrameAdvance]);
+        emulator.start();
+        assert!(emulator.paused);
+        assert_eq!(emulator.instructions(), INSTRUCTIONS_PER_FRAME as u64);
+
+        let mut emulator = emulator_after_hotkeys(&[Hotkey::Pause, Hotkey::Pause]);
+        emulator.start();
+        assert_eq!(emulator.instructions(), 3 * INSTRUCTIONS_PER_FRAME as u64);
+    }
+
+    #[test]
+    fn slow_motion_cycles_through_its_speeds() {
+        let mut emulator = emulator(&[0x12, 0x00], 1);
+        let mut speeds = vec![emulator.speed()];
+        for _ in 0..SLOW_MOTION.len() {
+            emulator.handle_hotkey(Hotkey::SlowMotion);
+            speeds.push(emulator.speed());
+        }
+
+        assert_eq!(speeds, [1., 0.5, 0.25, 1.]);
+    }
+
+    #[test]
+    fn check_rom_limits_the_size_to_free_memory() {
+        let free = MEMORY_SIZE - ROM_LOAD_OFFSET;
+
+        assert!(Emulator::check_rom("max", &vec![0; free], ROM_LOAD_OFFSET).is_ok());
+        assert_eq!(
+            Emulator::check_rom("big", &vec![0; free + 1], ROM_LOAD_OFFSET)
+                .unwrap_err()
+                .to_string(),
+            "ROM 'big' is 3585 bytes, but only 3584 bytes of memory are free after 0x200"
+        );
+        assert!(Emulator::check_rom("max", &[0; 0x200], 0xE00).is_ok());
+        assert!(Emulator::check_rom("big", &[0; 0x201], 0xE00).is_err());
+    }
+
+    #[test]
+    fn check_rom_rejects_empty_roms_and_bad_offsets() {
+        assert_eq!(
+            Emulator::check_rom("empty", &[], ROM_LOAD_OFFSET)
+                .unwrap_err()
+                .to_string(),
+            "ROM 'empty' is empty"
+        );
+
+        for offset in [0x201, 0x000, FONT_OFFSET + FONTS.len() - 2, MEMORY_SIZE] {
+            assert!(
+                Emulator::check_rom("rom", &[0x12, 0x00], offset).is_err(),
+                "{offset:#05X}"
+            );
+        }
+        assert!(Emulator::check_rom("rom", &[0x12, 0x00], FONT_OFFSET + FONTS.len()).is_ok());
+    }
+
+    /// A log shared by every test, of warnings and worse.
+    #[derive(Clone)]
+    struct Log(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Log {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// The log, which is started the first time it's asked for.
+    fn log() -> &'static Log {
+        static LOG: OnceLock<Log> = OnceLock::new();
+        LOG.get_or_init(|| {
+            let log = Log(Arc::default());
+            Logger::init(Box::new(log.clone()), LevelFilter::Warn).unwrap();
+            log
+        })
+    }
+
+    /// Lines logged so far containing `text`, from any test.
+    fn logged(text: &str) -> Vec<String> {
+        String::from_utf8_lossy(&log().0.lock().unwrap())
+            .lines()
+            .filter(|line| line.contains(text))
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn sys_runs_its_handler() {
+        // SYS 123; JP 200
+        let mut emulator = emulator(&[0x01, 0x23, 0x12, 0x00], 1);
+        let calls = Rc::new(Cell::new(0));
+        let handler_calls = calls.clone();
+        emulator.set_sys_handler(
+            0x123,
+            Box::new(move |machine| {
+                handler_calls.set(handler_calls.get() + 1);
+                machine.registers[0] = 0x42;
+                *machine.index = 0x300;
+            }),
+        );
+
+        emulator.step();
+        assert_eq!(calls.get(), 1);
+        assert_eq!(emulator.registers[0], 0x42);
+        assert_eq!(emulator.index, 0x300);
+        assert_eq!(emulator.pc, 0x202);
+
+        emulator.step();
+        emulator.step();
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn unhandled_sys_warns_once_per_address() {
+        log();
+        // SYS 3A7; SYS 3A8; JP 200
+        let mut emulator = emulator(&[0x03, 0xA7, 0x03, 0xA8, 0x12, 0x00], 1);
+        for _ in 0..9 {
+            emulator.step();
+        }
+
+        assert!(!emulator.halted);
+        let warnings = logged("SYS 3A7");
+        assert_eq!(warnings.len(), 1, "{warnings:?}");
+        assert!(warnings[0].ends_with(
+            "WARN  chip8::emulator: Skipping SYS 3A7 at 200, as machine code isn't run"
+        ));
+        assert_eq!(logged("SYS 3A8").len(), 1);
+    }
 }
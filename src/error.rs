@@ -1,4 +1,16 @@
+use std::fmt;
+
 #[derive(Debug)]
 pub enum Error {
     ErrorStr(String),
 }
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ErrorStr(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
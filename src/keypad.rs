@@ -1,4 +1,6 @@
-#[derive(PartialEq, Eq)]
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Key {
     Key1,
     Key2,
@@ -57,7 +59,7 @@ impl Key {
     }
 
     /// Inversion of `keypad_to_key`.
-    pub fn to_keypad(&self) -> u8 {
+    pub fn to_keypad(self) -> u8 {
         match self {
             Key::Key1 => 0x1,
             Key::Key2 => 0x2,
@@ -78,3 +80,29 @@ impl Key {
         }
     }
 }
+
+impl fmt::Display for Key {
+    /// The key on the keyboard.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Key::Key1 => "1",
+            Key::Key2 => "2",
+            Key::Key3 => "3",
+            Key::Key4 => "4",
+            Key::Q => "Q",
+            Key::W => "W",
+            Key::E => "E",
+            Key::R => "R",
+            Key::A => "A",
+            Key::S => "S",
+            Key::D => "D",
+            Key::F => "F",
+            Key::Z => "Z",
+            Key::X => "X",
+            Key::C => "C",
+            Key::V => "V",
+        };
+
+        write!(f, "{name}")
+    }
+}
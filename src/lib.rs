@@ -1,11 +1,49 @@
+mod analysis;
+mod cartridge;
 mod constant;
+mod coverage;
+mod database;
 mod emulator;
 mod error;
 mod keypad;
+mod logger;
+mod menu;
+mod octo;
 mod opcode;
+mod pacing;
+mod palette;
+mod profile;
+mod quirks;
+mod rom;
 mod screen;
+mod stack;
+mod sys;
+mod timing;
+mod trace;
 mod window;
 
+pub use analysis::{analyze, Analysis, Block, Edge, EdgeKind, SelfModification};
+pub use constant::{HEIGHT, ROM_LOAD_OFFSET, WIDTH};
+pub use coverage::Coverage;
+pub use database::{sha1, RomInfo};
 pub use emulator::Emulator;
+pub use error::Error;
+pub use keypad::Key;
+pub use logger::Logger;
+pub use menu::{Menu, MenuEntry, MenuLine};
+pub use octo::compile as compile_octo;
+pub use opcode::{disassemble, disassemble_word, Opcode};
+pub use pacing::{FrameLimiter, FrameTimes, Pacing, Turbo};
+pub use palette::{parse_color, Palette, Theme};
+pub use profile::Profile;
+pub use quirks::{Platform, Quirks, StackOverflow};
+pub use rom::{is_archive, Rom, RomSource};
+pub use screen::{Damage, Framebuffer, Rect, Screen};
+pub use sys::{Machine, SysHandler};
+pub use trace::Trace;
+pub use window::graphics::{Graphics, GraphicsMode};
+pub use window::headless::HeadlessWindow;
 pub use window::minifb::MinifbWindow;
-pub use window::terminal::TerminalWindow;
+pub use window::phosphor::Persistence;
+pub use window::terminal::{Glyphs, TerminalOptions, TerminalWindow};
+pub use window::{Hotkey, MenuKey, Window};
@@ -0,0 +1,81 @@
+//! A [log] sink for the events the emulator logs, like ROMs being loaded,
+//! unknown opcodes, and frames which took too long.
+//!
+//! Each line holds the seconds since the logger started, the level, where the
+//! event came from, and the message:
+//!
+//! ```text
+//!     0.012 INFO  chip8::emulator: Loaded 'maze.ch8', 34 bytes at 0x200
+//! ```
+
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+use crate::error::Error;
+
+pub struct Logger {
+    out: Mutex<Box<dyn Write + Send>>,
+    level: LevelFilter,
+    start: Instant,
+    /// Whether anything has been written, to tell users to look at the log.
+    logged: AtomicBool,
+}
+
+impl Logger {
+    /// Installs a logger writing events at `level` and above to `out`, which
+    /// is kept for the rest of the program.
+    pub fn init(out: Box<dyn Write + Send>, level: LevelFilter) -> Result<&'static Self, Error> {
+        let logger = Box::leak(Box::new(Self {
+            out: Mutex::new(out),
+            level,
+            start: Instant::now(),
+            logged: AtomicBool::new(false),
+        }));
+
+        log::set_logger(logger)
+            .map_err(|err| Error::ErrorStr(format!("Can't start logging: {err}")))?;
+        log::set_max_level(level);
+
+        Ok(logger)
+    }
+
+    pub fn logged(&self) -> bool {
+        self.logged.load(Ordering::Relaxed)
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let mut out = self.out.lock().unwrap_or_else(|err| err.into_inner());
+        // There's nowhere left to report failing to write the log.
+        let _ = writeln!(
+            out,
+            "{:>9.3} {:<5} {}: {}",
+            self.start.elapsed().as_secs_f64(),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        self.logged.store(true, Ordering::Relaxed);
+    }
+
+    fn flush(&self) {
+        let _ = self
+            .out
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .flush();
+    }
+}
@@ -1,56 +1,1308 @@
 use chip8::Emulator;
+use chip8::Error;
+use chip8::Glyphs;
+use chip8::Logger;
 use chip8::MinifbWindow;
+use chip8::Persistence;
 use chip8::TerminalWindow;
-use clap::CommandFactory;
-use clap::Parser;
-use std::{fs, process::exit};
+use chip8::{analyze, compile_octo, disassemble, Coverage, HeadlessWindow, Screen, Trace};
+use chip8::{is_archive, Rom, RomSource};
+use chip8::{parse_color, Theme};
+use chip8::{sha1, Menu, Palette, Platform, Quirks, StackOverflow, Window};
+use chip8::{GraphicsMode, TerminalOptions};
+use chip8::{Pacing, Profile, Turbo};
+use chip8::{HEIGHT, ROM_LOAD_OFFSET, WIDTH};
+use clap::parser::ValueSource;
+use clap::{ArgAction, Args, CommandFactory, FromArgMatches, Id, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use log::{info, LevelFilter, Log};
+use std::fs::File;
+use std::io::{self, BufWriter, ErrorKind, IsTerminal, Read, Write};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::time::Instant;
+use std::{env, fs, path::Path, process::exit};
 
-const EX_USAGE: i32 = 64;
+// Exit codes from `sysexits.h`.
+const EX_DATAERR: i32 = 65;
+const EX_NOINPUT: i32 = 66;
+const EX_CANTCREAT: i32 = 73;
+const EX_IOERR: i32 = 74;
+const EX_CONFIG: i32 = 78;
 
 /// Emulator for CHIP-8.
+///
+/// Without a subcommand, this runs a ROM as `run` does.
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
-struct Args {
-    /// Path to the rom to load
+#[command(author, version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    run: RunArgs,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+/// Options for logging what the emulator does, which apply to every
+/// subcommand.
+#[derive(Args, Debug)]
+struct LogArgs {
+    /// Log more of what the emulator does, e.g. which ROM and quirks were
+    /// chosen.
+    ///
+    /// Only problems are logged by default. `-vv` logs more detail, and
+    /// `-vvv` every instruction run.
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// File to write the log to.
+    ///
+    /// Defaults to stderr, except when that is the terminal the terminal
+    /// renderer draws in, where it's `chip8/chip8.log` in `$XDG_STATE_HOME`
+    /// or `~/.local/state`.
+    #[arg(long, global = true)]
+    log_file: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run a ROM, or choose one from a menu
+    Run(RunArgs),
+
+    /// Print the instructions of a ROM
+    Disasm {
+        /// Path to the rom, as for `run --rom`
+        #[arg(long)]
+        rom: String,
+
+        /// Address the ROM is loaded at, for the addresses shown
+        #[arg(long, value_parser = parse_address, default_value = "0x200")]
+        load_address: usize,
+    },
+
+    /// Print the control-flow graph of a ROM, found without running it
+    Analyze {
+        /// Path to the rom, as for `run --rom`
+        #[arg(long)]
+        rom: String,
+
+        /// Address the ROM is loaded at
+        #[arg(long, value_parser = parse_address, default_value = "0x200")]
+        load_address: usize,
+
+        /// What to print: a listing of the blocks, a Graphviz graph, or JSON
+        #[arg(long, value_enum, default_value_t = AnalysisFormat::Text)]
+        format: AnalysisFormat,
+
+        /// With `--format dot`, graph the calls between subroutines instead of
+        /// the blocks
+        #[arg(long, default_value_t = false)]
+        calls: bool,
+
+        /// Path to write to, instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Compile Octo source to a ROM
+    Asm {
+        /// Path to the Octo source, or `-` for stdin
+        source: String,
+
+        /// Path to write the ROM to, or `-` for stdout.
+        ///
+        /// Defaults to the source with a `.ch8` extension.
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+
+    /// Show what the ROM database knows about a ROM
+    Info {
+        /// Path to the rom, as for `run --rom`
+        #[arg(long)]
+        rom: String,
+    },
+
+    /// Measure how fast a ROM is emulated, without showing it
+    Bench {
+        /// Path to the rom, as for `run --rom`
+        #[arg(long)]
+        rom: String,
+
+        /// Frames to run, 60 of which are a second of emulated time
+        #[arg(long, default_value_t = 6000)]
+        frames: u64,
+
+        #[command(flatten)]
+        emulation: EmulationArgs,
+    },
+
+    /// Run a ROM without showing it, and print what it drew
+    Test {
+        /// Path to the rom, as for `run --rom`
+        #[arg(long)]
+        rom: String,
+
+        /// Frames to run before printing the screen
+        #[arg(long, default_value_t = 600)]
+        frames: u64,
+
+        /// File with the screen the ROM should draw, as printed by this
+        /// command, to exit with 1 if it differs
+        #[arg(long)]
+        expect: Option<PathBuf>,
+
+        #[command(flatten)]
+        emulation: EmulationArgs,
+
+        #[command(flatten)]
+        trace: TraceArgs,
+
+        #[command(flatten)]
+        profile: ProfileArgs,
+
+        #[command(flatten)]
+        coverage: CoverageArgs,
+    },
+
+    /// Print a completion script for a shell
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+}
+
+#[derive(Args, Debug)]
+struct RunArgs {
+    /// Path to the rom to load, or a directory to choose one from.
+    ///
+    /// Zip archives are opened like directories, unless they hold a single
+    /// ROM, and a ROM inside one can be given as e.g. `games.zip/pong.ch8`.
+    /// Octo source (`.8o`) and cartridges (`.gif`) are compiled, and `-` reads
+    /// the ROM from stdin. Defaults to choosing from the current directory.
     #[arg(long)]
-    rom: String,
+    rom: Option<String>,
+
+    /// The renderer for the UI
+    #[arg(long, value_enum, default_value_t = Renderer::Terminal)]
+    renderer: Renderer,
 
-    /// The renderer for the UI.
+    /// Colour palette.
     ///
-    /// Either `terminal` (default) or `window` can be passed.
+    /// The terminal renderer uses the terminal's own colours if no palette is
+    /// chosen.
+    #[arg(long, value_enum)]
+    palette: Option<Theme>,
+
+    /// Foreground colour as hex, e.g. `#33FF66`, overriding the palette
+    #[arg(long, value_parser = parse_color)]
+    fg: Option<u32>,
+
+    /// Background colour as hex, e.g. `#001A00`, overriding the palette
+    #[arg(long, value_parser = parse_color)]
+    bg: Option<u32>,
+
+    /// Characters used to draw pixels with the terminal renderer
+    #[arg(long, value_enum, default_value = "half-block")]
+    glyphs: Glyphs,
+
+    /// Draw the terminal renderer as an inline image
+    #[arg(long, value_enum, default_value = "none")]
+    graphics: GraphicsMode,
+
+    /// Integer factor to scale CHIP-8 pixels by in the terminal renderer.
+    ///
+    /// With glyphs, each glyph is drawn this many times in both directions.
+    /// With `--graphics`, this is the size of each pixel in image pixels, up to
+    /// the largest which fits the terminal. Defaults to the largest scale
+    /// which fits the terminal.
+    #[arg(long, value_parser = parse_scale)]
+    scale: Option<usize>,
+
+    /// Keep pixels lit for a while after they are turned off, to reduce
+    /// flickering.
+    ///
+    /// Either `two-frames`, to show pixels which were on in either of the last
+    /// two frames, or a number between 0 and 1, for the fraction of brightness
+    /// that pixels lose every frame as they fade out.
     #[arg(long)]
-    renderer: Option<String>,
+    persistence: Option<Persistence>,
+
+    #[command(flatten)]
+    emulation: EmulationArgs,
 
-    /// Verbose mode
+    #[command(flatten)]
+    trace: TraceArgs,
+
+    #[command(flatten)]
+    profile: ProfileArgs,
+
+    #[command(flatten)]
+    coverage: CoverageArgs,
+
+    /// Speed while Tab is held.
+    ///
+    /// Either `max` (default), to run as fast as possible, or how many times
+    /// faster than normal to run, e.g. `4x`.
+    #[arg(long, default_value = "max")]
+    turbo: Turbo,
+
+    /// Let the window renderer pace frames when it shows them, instead of the
+    /// emulator
     #[arg(long, default_value_t = false)]
-    verbose: bool,
+    vsync: bool,
+}
+
+/// Options for how ROMs are emulated, shared by the subcommands which run
+/// them.
+#[derive(Args, Debug)]
+struct EmulationArgs {
+    /// Address to load the ROM at, e.g. `0x600` for ETI-660 ROMs
+    #[arg(long, value_parser = parse_address)]
+    load_address: Option<usize>,
+
+    /// Platform the ROM was written for, which picks the quirks to emulate.
+    ///
+    /// Detected from the ROM database for known ROMs, and `chip-8` otherwise.
+    #[arg(long, value_enum)]
+    platform: Option<Platform>,
+
+    /// Wrap sprites around the edges of the screen instead of clipping them
+    #[arg(long, default_value_t = false)]
+    wrap_sprites: bool,
+
+    /// Set VF to the number of sprite rows which collided when drawing, as
+    /// SCHIP does in hi-res mode
+    #[arg(long, default_value_t = false)]
+    count_collided_rows: bool,
+
+    /// Return addresses the stack holds, which is 12 for `chip-8` and 16
+    /// for the other platforms by default
+    #[arg(long)]
+    stack_depth: Option<usize>,
+
+    /// What happens when a subroutine is called with the stack full, which is
+    /// `wrap` for `chip-8` and `halt` for the other platforms by default
+    #[arg(long, value_enum)]
+    stack_overflow: Option<StackOverflow>,
+
+    /// Keep the stack in memory at 0xEA0-0xECF, where programs can see it,
+    /// as the COSMAC VIP does
+    #[arg(long, default_value_t = false)]
+    stack_in_memory: bool,
+
+    /// Instructions run in each frame, 60 of which run every second.
+    ///
+    /// Detected from the ROM database for known ROMs, and 10 otherwise.
+    #[arg(long)]
+    instructions_per_frame: Option<usize>,
+
+    /// Run as fast as a COSMAC VIP, with each instruction taking as long as
+    /// it does there, instead of a number of instructions per frame
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with = "instructions_per_frame"
+    )]
+    vip_timing: bool,
+}
+
+/// Options for tracing the instructions run, shared by the subcommands which
+/// run ROMs.
+#[derive(Args, Debug)]
+struct TraceArgs {
+    /// Write a line for each instruction run to this file, to diff against
+    /// traces from other emulators
+    #[arg(long)]
+    trace: Option<PathBuf>,
+
+    /// Only trace instructions at addresses in this range, e.g. `0x200-0x2FF`.
+    ///
+    /// Can be given more than once to trace several ranges.
+    #[arg(long, value_parser = parse_address_range, requires = "trace")]
+    trace_range: Vec<RangeInclusive<u16>>,
+}
+
+impl TraceArgs {
+    /// Creates the trace file, if one was asked for.
+    fn open(&self) -> Result<Option<Trace>, Error> {
+        self.trace
+            .as_ref()
+            .map(|path| Trace::create(path, self.trace_range.clone()))
+            .transpose()
+    }
+}
+
+/// Options for profiling where a ROM spends its time, shared by the
+/// subcommands which run ROMs.
+#[derive(Args, Debug)]
+struct ProfileArgs {
+    /// Count the instructions run at each address, of each kind, and in each
+    /// subroutine, and print a report on exit
+    #[arg(long, default_value_t = false)]
+    profile: bool,
+
+    /// Write the whole profile as JSON to this file on exit
+    #[arg(long)]
+    profile_json: Option<PathBuf>,
+
+    /// How many of the most run addresses the report shows
+    #[arg(long, default_value_t = 20)]
+    profile_top: usize,
+}
+
+impl ProfileArgs {
+    /// Starts a profile of a ROM loaded at `load_address`, if one was asked
+    /// for.
+    fn start(&self, load_address: usize) -> Option<Profile> {
+        (self.profile || self.profile_json.is_some()).then(|| Profile::new(load_address as u16))
+    }
+
+    /// Prints the report for `profile` of the ROM `name`, and writes the JSON,
+    /// as asked for.
+    fn report(&self, name: &str, profile: &Profile) -> Result<(), Error> {
+        if self.profile {
+            print!("Profile of '{name}': {}", profile.report(self.profile_top));
+        }
+        if let Some(path) = &self.profile_json {
+            fs::write(path, profile.to_json()).map_err(|err| {
+                Error::ErrorStr(format!("Can't write profile '{}': {err}", path.display()))
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Options for recording which parts of a ROM are run, shared by the
+/// subcommands which run ROMs.
+#[derive(Args, Debug)]
+struct CoverageArgs {
+    /// Record which addresses of the ROM are run as instructions or read as
+    /// data, and print a summary on exit
+    #[arg(long, default_value_t = false)]
+    coverage: bool,
+
+    /// Write the ROM disassembled to this file on exit, with how many times
+    /// each instruction ran
+    #[arg(long)]
+    coverage_listing: Option<PathBuf>,
+
+    /// Write the coverage as an lcov tracefile to this file on exit, with the
+    /// lines being those of `--coverage-listing`
+    #[arg(long)]
+    coverage_lcov: Option<PathBuf>,
+}
+
+impl CoverageArgs {
+    /// Starts recording the coverage of `rom` loaded at `load_address`, if it
+    /// was asked for.
+    fn start(&self, load_address: usize, rom: &[u8]) -> Option<Coverage> {
+        (self.coverage || self.coverage_listing.is_some() || self.coverage_lcov.is_some())
+            .then(|| Coverage::new(load_address, rom.to_vec()))
+    }
+
+    /// Prints the summary of `coverage` of the ROM `name`, and writes the
+    /// listing and tracefile, as asked for.
+    fn report(&self, name: &str, coverage: &Coverage) -> Result<(), Error> {
+        if self.coverage {
+            print!("Coverage of '{name}': {}", coverage.summary());
+        }
+
+        let write = |path: &PathBuf, contents: String| {
+            fs::write(path, contents).map_err(|err| {
+                Error::ErrorStr(format!("Can't write coverage '{}': {err}", path.display()))
+            })
+        };
+        if let Some(path) = &self.coverage_listing {
+            write(path, coverage.listing())?;
+        }
+        if let Some(path) = &self.coverage_lcov {
+            // Tools find the source by the path in the tracefile.
+            let source = self
+                .coverage_listing
+                .as_ref()
+                .map_or(name.to_string(), |listing| listing.display().to_string());
+            write(path, coverage.lcov(&source))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// What `analyze` prints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum AnalysisFormat {
+    Text,
+    Dot,
+    Json,
+}
+
+/// Where the emulator is shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Renderer {
+    /// In the current terminal
+    Terminal,
+    /// In a separate window
+    Window,
 }
 
 fn main() {
-    let args = Args::parse();
+    let cli = parse_args();
+    let (logger, default_log) = start_logging(&cli);
+
+    match cli.command {
+        None => run(&cli.run),
+        Some(Command::Run(args)) => run(&args),
+        Some(Command::Disasm { rom, load_address }) => {
+            let rom = load_rom(&RomSource::parse(&rom));
+            for line in disassemble(&rom.bytes, load_address) {
+                println!("{line}");
+            }
+        }
+        Some(Command::Analyze {
+            rom,
+            load_address,
+            format,
+            calls,
+            output,
+        }) => {
+            let rom = load_rom(&RomSource::parse(&rom));
+            let analysis = analyze(&rom.bytes, load_address);
+            let text = match format {
+                AnalysisFormat::Text => analysis.to_string(),
+                AnalysisFormat::Dot if calls => analysis.to_call_graph_dot(),
+                AnalysisFormat::Dot => analysis.to_dot(),
+                AnalysisFormat::Json => analysis.to_json() + "\n",
+            };
+
+            match output {
+                Some(path) => fs::write(&path, text).unwrap_or_else(|err| {
+                    fail(
+                        Error::ErrorStr(format!("Can't write '{}': {err}", path.display())),
+                        EX_CANTCREAT,
+                    )
+                }),
+                None => print!("{text}"),
+            }
+        }
+        Some(Command::Asm { source, output }) => asm(&source, output),
+        Some(Command::Info { rom }) => {
+            let source = RomSource::parse(&rom);
+            let rom = load_rom(&source);
+            info(&source, &rom);
+        }
+        Some(Command::Bench {
+            rom,
+            frames,
+            emulation,
+        }) => bench(&rom, frames, &emulation),
+        Some(Command::Test {
+            rom,
+            frames,
+            expect,
+            emulation,
+            trace,
+            profile,
+            coverage,
+        }) => test(
+            &rom, frames, expect, &emulation, &trace, &profile, &coverage,
+        ),
+        Some(Command::Completions { shell }) => {
+            clap_complete::generate(shell, &mut Cli::command(), "chip8", &mut io::stdout());
+        }
+    }
+
+    logger.flush();
+    if let Some(path) = default_log.filter(|_| logger.logged()) {
+        eprintln!("Log written to {}", path.display());
+    }
+}
+
+/// Starts logging at the level chosen with `-v`, to the file chosen with
+/// `--log-file` or otherwise somewhere the terminal renderer doesn't draw,
+/// giving back the default log file if that's where it goes.
+fn start_logging(cli: &Cli) -> (&'static Logger, Option<PathBuf>) {
+    let level = match cli.log.verbose {
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+
+    let renderer = match &cli.command {
+        None => Some(cli.run.renderer),
+        Some(Command::Run(args)) => Some(args.renderer),
+        _ => None,
+    };
+    let default_log = (renderer == Some(Renderer::Terminal) && io::stderr().is_terminal())
+        .then(default_log_path)
+        .flatten();
 
-    let rom_path = args.rom;
+    let out: Box<dyn Write + Send> = match (&cli.log.log_file, &default_log) {
+        (Some(path), _) => Box::new(BufWriter::new(File::create(path).unwrap_or_else(|err| {
+            fail(
+                Error::ErrorStr(format!("Can't create log '{}': {err}", path.display())),
+                EX_CANTCREAT,
+            )
+        }))),
+        // Not being able to log isn't worth stopping the emulator for.
+        (None, Some(path)) => {
+            match fs::create_dir_all(path.parent().unwrap()).and_then(|_| File::create(path)) {
+                Ok(file) => Box::new(BufWriter::new(file)),
+                Err(_) => Box::new(io::sink()),
+            }
+        }
+        (None, None) => Box::new(io::stderr()),
+    };
+
+    let logger = Logger::init(out, level).unwrap_or_else(|err| fail(err, EX_CANTCREAT));
+    (logger, default_log.filter(|_| cli.log.log_file.is_none()))
+}
+
+fn default_log_path() -> Option<PathBuf> {
+    let state_dir = env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))?;
+
+    Some(state_dir.join("chip8").join("chip8.log"))
+}
+
+/// Parses the command line, with defaults for its flags from the config file.
+fn parse_args() -> Cli {
+    let mut command = Cli::command();
+    if let Some(path) = config_path() {
+        command = apply_config(command, &path).unwrap_or_else(|err| fail(err, EX_CONFIG));
+    }
+
+    try_parse_args(command, env::args_os()).unwrap_or_else(|err| err.exit())
+}
+
+/// Parses `args` with `command`, which may have defaults from the config file.
+fn try_parse_args<I, T>(mut command: clap::Command, args: I) -> Result<Cli, clap::Error>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let matches = command.try_get_matches_from_mut(args)?;
+
+    // Only the flags for logging apply to subcommands when given before them.
+    if let Some((name, _)) = matches.subcommand() {
+        let flag = command.get_arguments().find(|arg| {
+            !arg.is_global_set()
+                && matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+        });
+        if let Some(flag) = flag {
+            return Err(command.error(
+                clap::error::ErrorKind::ArgumentConflict,
+                format!("the subcommand '{name}' cannot be used with '{flag}'"),
+            ));
+        }
+    }
+
+    Cli::from_arg_matches(&matches)
+}
 
-    let rom = fs::read(rom_path).unwrap();
+/// `$XDG_CONFIG_HOME/chip8/config.toml`, where the config directory defaults
+/// to `~/.config`.
+fn config_path() -> Option<PathBuf> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
 
-    // Seems like clap doesn't let us use ValueEnums for options so we have to
-    // result to this manual parsing.
-    let mut emulator = match args.renderer {
-        Some(renderer) => {
-            if renderer == "terminal" {
-                Emulator::new(Box::new(TerminalWindow::new()), args.verbose)
-            } else if renderer == "window" {
-                Emulator::new(Box::new(MinifbWindow::new()), args.verbose)
+    Some(config_dir.join("chip8").join("config.toml"))
+}
+
+/// Makes each option in the config file at `path` the default of the flag
+/// with the same name, in every subcommand which has it, e.g.
+/// `renderer = "window"` for `--renderer`.
+fn apply_config(mut command: clap::Command, path: &Path) -> Result<clap::Command, Error> {
+    let config = match fs::read_to_string(path) {
+        Ok(config) => config,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(command),
+        Err(err) => {
+            return Err(Error::ErrorStr(format!(
+                "Can't read config '{}': {err}",
+                path.display()
+            )))
+        }
+    };
+    let invalid = |message: String| {
+        Error::ErrorStr(format!("Invalid config '{}': {message}", path.display()))
+    };
+
+    let table = config.parse::<toml::Table>().map_err(|err| {
+        let line = err
+            .span()
+            .map_or(1, |span| config[..span.start].split('\n').count());
+        invalid(format!(
+            "line {line}: {}",
+            err.message().replace('\n', ", ")
+        ))
+    })?;
+
+    for (key, value) in table {
+        let value = match value {
+            toml::Value::String(value) => value,
+            toml::Value::Integer(value) => value.to_string(),
+            toml::Value::Float(value) => value.to_string(),
+            toml::Value::Boolean(value) => value.to_string(),
+            _ => {
+                return Err(invalid(format!(
+                    "`{key}` should be a string, number or boolean"
+                )))
+            }
+        };
+
+        // The flag as found in each command, checking the value against it.
+        let mut found = false;
+        let mut check = |command: &clap::Command| -> Result<Option<Id>, Error> {
+            let Some(arg) = command
+                .get_arguments()
+                .find(|arg| arg.get_long() == Some(key.as_str()))
+            else {
+                return Ok(None);
+            };
+            found = true;
+
+            // Parse the value with the flag on its own, as clap would only
+            // check defaults once the command line is parsed, with errors
+            // which don't mention the config.
+            let valid = if arg.get_action().takes_values() {
+                clap::Command::new("config")
+                    .no_binary_name(true)
+                    .arg(arg.clone())
+                    .try_get_matches_from([format!("--{key}={value}")])
+                    .is_ok()
             } else {
-                let mut cmd = Args::command();
-                cmd.print_help().unwrap();
-                exit(EX_USAGE);
+                value == "true" || value == "false"
+            };
+            if !valid {
+                return Err(invalid(format!("`{key}` can't be \"{value}\"")));
+            }
+
+            Ok(Some(arg.get_id().clone()))
+        };
+
+        let mut targets = vec![];
+        if let Some(id) = check(&command)? {
+            targets.push((None, id));
+        }
+        for subcommand in command.get_subcommands() {
+            if let Some(id) = check(subcommand)? {
+                targets.push((Some(subcommand.get_name().to_string()), id));
+            }
+        }
+        if !found {
+            return Err(invalid(format!("there's no `--{key}` flag")));
+        }
+
+        for (subcommand, id) in targets {
+            let default = |arg: clap::Arg| arg.default_value(value.clone()).required(false);
+            command = match subcommand {
+                None => command.mut_arg(id, default),
+                Some(name) => command.mut_subcommand(name, |sub| sub.mut_arg(id, default)),
+            };
+        }
+    }
+
+    Ok(command)
+}
+
+/// Runs the ROM chosen with `args`, or shows a menu to choose one from.
+fn run(args: &RunArgs) {
+    let rom_arg = args.rom.clone().unwrap_or_else(|| ".".to_string());
+    let rom_path = Path::new(&rom_arg);
+
+    if rom_path.is_dir() {
+        let menu = Menu::new(rom_path).unwrap_or_else(|err| fail(err, EX_NOINPUT));
+        browse(args, menu);
+        return;
+    }
+
+    let source = if is_archive(rom_path) {
+        let menu = Menu::from_archive(rom_path).unwrap_or_else(|err| fail(err, EX_NOINPUT));
+        if menu.entries().len() > 1 {
+            browse(args, menu);
+            return;
+        }
+        menu.selected().source.clone()
+    } else {
+        RomSource::parse(&rom_arg)
+    };
+
+    // Check the ROM before the window is opened, so errors aren't hidden by it.
+    let rom = load_rom(&source);
+    let mut game = Game::new(&args.emulation, rom).unwrap_or_else(|err| fail(err, EX_DATAERR));
+    game.trace = args
+        .trace
+        .open()
+        .unwrap_or_else(|err| fail(err, EX_CANTCREAT));
+    game.profile = args.profile.start(game.load_address);
+    game.coverage = args.coverage.start(game.load_address, &game.rom);
+
+    let window = open_window(args, palette(args, game.palette));
+    let (window, mut finished) = game.run(window, args);
+
+    // Restore the terminal before reporting anything.
+    drop(window);
+    if let Some(err) = finished.trace_error.take() {
+        fail(err, EX_IOERR);
+    }
+    finished
+        .report(args)
+        .unwrap_or_else(|err| fail(err, EX_CANTCREAT));
+}
+
+/// Shows `menu`, and runs the ROMs chosen from it until it is closed.
+fn browse(args: &RunArgs, mut menu: Menu) {
+    // The window is shared by every ROM, so colours from the database aren't
+    // used.
+    let mut window = open_window(args, palette(args, None));
+    // Reported once the window is closed, so they aren't drawn over.
+    let mut finished_games = vec![];
+
+    loop {
+        let source = match menu.choose(window.as_mut()) {
+            Ok(Some(source)) => source,
+            Ok(None) => break,
+            Err(err) => {
+                // Restore the terminal before reporting the error.
+                drop(window);
+                fail(err, EX_IOERR);
+            }
+        };
+
+        let game = source.load().and_then(|rom| {
+            let mut game = Game::new(&args.emulation, rom)?;
+            game.trace = args.trace.open()?;
+            game.profile = args.profile.start(game.load_address);
+            game.coverage = args.coverage.start(game.load_address, &game.rom);
+            Ok(game)
+        });
+
+        match game {
+            Ok(game) => {
+                let mut finished;
+                (window, finished) = game.run(window, args);
+                menu.set_message(finished.trace_error.take().map(|err| err.to_string()));
+                finished_games.push(finished);
             }
+            Err(err) => menu.set_message(Some(err.to_string())),
+        }
+    }
+
+    drop(window);
+    for finished in finished_games {
+        finished
+            .report(args)
+            .unwrap_or_else(|err| fail(err, EX_CANTCREAT));
+    }
+}
+
+/// A ROM, and the settings to run it with.
+struct Game {
+    name: String,
+    rom: Vec<u8>,
+    load_address: usize,
+    quirks: Quirks,
+    pacing: Pacing,
+    palette: Option<Palette>,
+    trace: Option<Trace>,
+    profile: Option<Profile>,
+    coverage: Option<Coverage>,
+}
+
+/// What's left of a [Game] after it exits, to report once the window is
+/// closed.
+struct Finished {
+    name: String,
+    /// Why finishing the trace failed, if it did.
+    trace_error: Option<Error>,
+    profile: Option<Profile>,
+    coverage: Option<Coverage>,
+}
+
+impl Finished {
+    /// Reports the profile and coverage, as asked for with `args`.
+    fn report(&self, args: &RunArgs) -> Result<(), Error> {
+        if let Some(profile) = &self.profile {
+            args.profile.report(&self.name, profile)?;
+        }
+        if let Some(coverage) = &self.coverage {
+            args.coverage.report(&self.name, coverage)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Game {
+    /// Picks the settings from `args`, and then the Octo cartridge or ROM
+    /// database, and checks that the ROM can be loaded with them.
+    fn new(args: &EmulationArgs, rom: Rom) -> Result<Self, Error> {
+        let Rom {
+            name,
+            bytes: rom,
+            info: rom_info,
+        } = rom;
+
+        let platform = args
+            .platform
+            .or(rom_info.as_ref().and_then(|info| info.platform))
+            .unwrap_or_default();
+        info!("Running '{name}' as {}", platform.name());
+
+        let load_address = args.load_address.unwrap_or(ROM_LOAD_OFFSET);
+        Emulator::check_rom(&name, &rom, load_address)?;
+        platform.check_rom_size(&rom, load_address)?;
+
+        // Quirks which differ from the platform's only apply to that platform.
+        let mut quirks = match (&args.platform, &rom_info) {
+            (None, Some(info)) => info.quirks.unwrap_or(platform.quirks()),
+            _ => platform.quirks(),
+        };
+        quirks.wrap_sprites |= args.wrap_sprites;
+        quirks.count_collided_rows |= args.count_collided_rows;
+        quirks.stack_depth = args.stack_depth.unwrap_or(quirks.stack_depth);
+        quirks.stack_overflow = args.stack_overflow.unwrap_or(quirks.stack_overflow);
+        quirks.stack_in_memory |= args.stack_in_memory;
+        quirks.check()?;
+
+        let pacing = Pacing {
+            instructions_per_frame: args
+                .instructions_per_frame
+                .or(rom_info
+                    .as_ref()
+                    .and_then(|info| info.instructions_per_frame))
+                .unwrap_or(Pacing::default().instructions_per_frame),
+            vip_timing: args.vip_timing,
+            ..Pacing::default()
+        };
+
+        Ok(Self {
+            name,
+            rom,
+            load_address,
+            quirks,
+            pacing,
+            palette: rom_info.and_then(|info| info.palette),
+            trace: None,
+            profile: None,
+            coverage: None,
+        })
+    }
+
+    /// Runs the ROM in `window` until it exits, giving the window back along
+    /// with what's left to report.
+    fn run(mut self, window: Box<dyn Window>, args: &RunArgs) -> (Box<dyn Window>, Finished) {
+        self.pacing.turbo = args.turbo;
+
+        let name = self.name.clone();
+        let mut emulator = self.emulator(window);
+        emulator.start();
+
+        let finished = Finished {
+            name,
+            trace_error: emulator.take_trace().and_then(|trace| trace.finish().err()),
+            profile: emulator.take_profile(),
+            coverage: emulator.take_coverage(),
+        };
+        (emulator.into_window(), finished)
+    }
+
+    /// An emulator with the ROM loaded, ready to start.
+    fn emulator(self, window: Box<dyn Window>) -> Emulator {
+        let mut emulator = Emulator::new(window, self.quirks, self.pacing);
+        emulator
+            .load_rom_at(&self.name, self.rom, self.load_address)
+            .unwrap();
+        if let Some(trace) = self.trace {
+            emulator.set_trace(trace);
+        }
+        if let Some(profile) = self.profile {
+            emulator.set_profile(profile);
+        }
+        if let Some(coverage) = self.coverage {
+            emulator.set_coverage(coverage);
+        }
+
+        emulator
+    }
+}
+
+/// The palette chosen with `args`, falling back to `rom_palette` for anything
+/// which wasn't chosen.
+fn palette(args: &RunArgs, rom_palette: Option<Palette>) -> Option<Palette> {
+    if args.palette.is_none() && args.fg.is_none() && args.bg.is_none() {
+        return rom_palette;
+    }
+
+    let mut palette = args
+        .palette
+        .map(|theme| theme.palette())
+        .or(rom_palette)
+        .unwrap_or_default();
+    if let Some(fg) = args.fg {
+        palette = palette.with_foreground(fg);
+    }
+    if let Some(bg) = args.bg {
+        palette = palette.with_background(bg);
+    }
+
+    Some(palette)
+}
+
+fn open_window(args: &RunArgs, palette: Option<Palette>) -> Box<dyn Window> {
+    let terminal_options = TerminalOptions {
+        palette,
+        persistence: args.persistence,
+        glyphs: args.glyphs,
+        graphics: args.graphics,
+        scale: args.scale,
+    };
+
+    match args.renderer {
+        Renderer::Terminal => Box::new(TerminalWindow::new(terminal_options)),
+        Renderer::Window => Box::new(MinifbWindow::new(
+            palette.unwrap_or_default(),
+            args.persistence,
+            args.vsync,
+        )),
+    }
+}
+
+/// Compiles the Octo program at `source` into a ROM, written to `output`.
+fn asm(source: &str, output: Option<String>) {
+    let program = if source == "-" {
+        let mut program = String::new();
+        io::stdin().read_to_string(&mut program).map(|_| program)
+    } else {
+        fs::read_to_string(source)
+    };
+    let program = program.unwrap_or_else(|err| {
+        fail(
+            Error::ErrorStr(format!("Can't read '{source}': {err}")),
+            EX_NOINPUT,
+        )
+    });
+
+    let rom = compile_octo(&program).unwrap_or_else(|err| fail(err, EX_DATAERR));
+
+    let output = output.unwrap_or_else(|| {
+        if source == "-" {
+            "-".to_string()
+        } else {
+            Path::new(source)
+                .with_extension("ch8")
+                .display()
+                .to_string()
         }
-        None => Emulator::new(Box::new(TerminalWindow::new()), args.verbose),
+    });
+    let written = if output == "-" {
+        io::stdout().write_all(&rom)
+    } else {
+        fs::write(&output, &rom)
     };
+    written.unwrap_or_else(|err| {
+        fail(
+            Error::ErrorStr(format!("Can't write '{output}': {err}")),
+            EX_CANTCREAT,
+        )
+    });
+}
 
-    emulator.load_rom(rom);
+/// Runs the ROM at `rom` for `frames` frames as fast as possible, and prints
+/// how fast that was.
+fn bench(rom: &str, frames: u64, args: &EmulationArgs) {
+    let game = Game::new(args, load_rom(&RomSource::parse(rom)))
+        .unwrap_or_else(|err| fail(err, EX_DATAERR));
 
+    let mut emulator = game.emulator(Box::new(HeadlessWindow::new(frames)));
+    let start = Instant::now();
     emulator.start();
+    let elapsed = start.elapsed().as_secs_f64();
+    let instructions = emulator.instructions();
+
+    // Frames run at 60 a second.
+    let emulated = frames as f64 / 60.;
+    println!("Ran {frames} frames ({emulated:.1}s emulated) in {elapsed:.3}s");
+    println!(
+        "{:.0} instructions per second, {:.1}x real time",
+        instructions as f64 / elapsed,
+        emulated / elapsed
+    );
+}
+
+/// Runs the ROM at `rom` for `frames` frames, prints the screen, and checks
+/// it against the file at `expect`.
+fn test(
+    rom: &str,
+    frames: u64,
+    expect: Option<PathBuf>,
+    args: &EmulationArgs,
+    trace: &TraceArgs,
+    profile: &ProfileArgs,
+    coverage: &CoverageArgs,
+) {
+    let mut game = Game::new(args, load_rom(&RomSource::parse(rom)))
+        .unwrap_or_else(|err| fail(err, EX_DATAERR));
+    game.trace = trace.open().unwrap_or_else(|err| fail(err, EX_CANTCREAT));
+    game.profile = profile.start(game.load_address);
+    game.coverage = coverage.start(game.load_address, &game.rom);
+    let name = game.name.clone();
+
+    let mut emulator = game.emulator(Box::new(HeadlessWindow::new(frames)));
+    emulator.start();
+    if let Some(trace) = emulator.take_trace() {
+        trace.finish().unwrap_or_else(|err| fail(err, EX_IOERR));
+    }
+
+    let screen = screen_text(emulator.screen());
+    print!("{screen}");
+    if let Some(emulator_profile) = emulator.take_profile() {
+        profile
+            .report(&name, &emulator_profile)
+            .unwrap_or_else(|err| fail(err, EX_CANTCREAT));
+    }
+    if let Some(emulator_coverage) = emulator.take_coverage() {
+        coverage
+            .report(&name, &emulator_coverage)
+            .unwrap_or_else(|err| fail(err, EX_CANTCREAT));
+    }
+
+    let Some(expect) = expect else {
+        return;
+    };
+    let expected = fs::read_to_string(&expect).unwrap_or_else(|err| {
+        fail(
+            Error::ErrorStr(format!("Can't read '{}': {err}", expect.display())),
+            EX_NOINPUT,
+        )
+    });
+
+    let lines = |text: &str| {
+        text.lines()
+            .map(|line| line.trim_end().to_string())
+            .collect::<Vec<_>>()
+    };
+    if lines(&screen) != lines(&expected) {
+        eprintln!("error: screen differs from '{}'", expect.display());
+        exit(1);
+    }
+}
+
+/// Draws `screen` as text, with half blocks for two rows of pixels per line.
+fn screen_text(screen: &Screen) -> String {
+    let framebuffer = screen.framebuffer();
+
+    let mut text = String::new();
+    for y in (0..HEIGHT).step_by(2) {
+        for x in 0..WIDTH {
+            text.push(
+                match (framebuffer.pixel(x, y), framebuffer.pixel(x, y + 1)) {
+                    (false, false) => ' ',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (true, true) => '█',
+                },
+            );
+        }
+        text.push('\n');
+    }
+
+    text
+}
+
+/// Prints what the ROM database or Octo cartridge says about the ROM from
+/// `source`, and the settings it runs with by default.
+fn info(source: &RomSource, rom: &Rom) {
+    println!("File:     {source}");
+    println!("SHA-1:    {}", sha1(&rom.bytes));
+
+    let Some(rom_info) = &rom.info else {
+        println!("Not in the ROM database, so the defaults are used.");
+        return;
+    };
+
+    println!("Title:    {}", rom_info.title);
+    if !rom_info.authors.is_empty() {
+        println!("Authors:  {}", rom_info.authors.join(", "));
+    }
+    if let Some(description) = &rom_info.description {
+        println!("About:    {description}");
+    }
+
+    let platform = rom_info.platform.unwrap_or_default();
+    println!("Platform: {}", platform.name());
+    println!("Quirks:   {}", rom_info.quirks.unwrap_or(platform.quirks()));
+
+    let instructions_per_frame = rom_info
+        .instructions_per_frame
+        .unwrap_or(Pacing::default().instructions_per_frame);
+    println!("Speed:    {instructions_per_frame} instructions per frame");
+
+    if let Some(palette) = rom_info.palette {
+        println!(
+            "Colours:  #{:06X} on #{:06X}",
+            palette.foreground(),
+            palette.background()
+        );
+    }
+
+    if !rom_info.keys.is_empty() {
+        let keys = rom_info
+            .keys
+            .iter()
+            .map(|(action, key)| format!("{action} {key}"))
+            .collect::<Vec<_>>();
+        println!("Keys:     {}", keys.join(", "));
+    }
+}
+
+/// Loads the ROM from `source`, exiting if it can't be read or decoded.
+fn load_rom(source: &RomSource) -> Rom {
+    let bytes = source.read().unwrap_or_else(|err| fail(err, EX_NOINPUT));
+    source
+        .decode(bytes)
+        .unwrap_or_else(|err| fail(err, EX_DATAERR))
+}
+
+/// Parses an address as hex with a `0x` prefix, or as decimal.
+fn parse_address(s: &str) -> Result<usize, Error> {
+    let address = match s.strip_prefix("0x").or(s.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+
+    address.map_err(|_| Error::ErrorStr(format!("Invalid address '{s}', expected e.g. 0x600")))
+}
+
+/// Parses a range of addresses like `0x200-0x2FF`, including both ends.
+fn parse_address_range(s: &str) -> Result<RangeInclusive<u16>, Error> {
+    let invalid = || Error::ErrorStr(format!("Invalid range '{s}', expected e.g. 0x200-0x2FF"));
+
+    let (start, end) = s.split_once('-').ok_or_else(invalid)?;
+    let start = parse_address(start).map_err(|_| invalid())?;
+    let end = parse_address(end).map_err(|_| invalid())?;
+    if start > end || end > 0xFFFF {
+        return Err(invalid());
+    }
+
+    Ok(start as u16..=end as u16)
+}
+
+fn parse_scale(s: &str) -> Result<usize, Error> {
+    match s.parse::<usize>() {
+        Ok(scale) if scale >= 1 => Ok(scale),
+        _ => Err(Error::ErrorStr(format!(
+            "Invalid scale '{s}', expected a whole number of at least 1"
+        ))),
+    }
+}
+
+fn fail(err: Error, code: i32) -> ! {
+    log::logger().flush();
+    eprintln!("error: {err}");
+    exit(code);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `config` to a file named after the test, so tests running in
+    /// parallel don't share one.
+    fn config_file(name: &str, config: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("chip8-{}-{name}.toml", std::process::id()));
+        fs::write(&path, config).unwrap();
+        path
+    }
+
+    fn parse_with_config(name: &str, config: &str, args: &[&str]) -> Result<Cli, String> {
+        let path = config_file(name, config);
+        let command = apply_config(Cli::command(), &path);
+        fs::remove_file(&path).unwrap();
+
+        let command = command.map_err(|err| err.to_string())?;
+        try_parse_args(command, args).map_err(|err| err.kind().to_string())
+    }
+
+    fn run_args(cli: &Cli) -> &RunArgs {
+        match &cli.command {
+            Some(Command::Run(args)) => args,
+            None => &cli.run,
+            _ => panic!("not a run command"),
+        }
+    }
+
+    #[test]
+    fn config_sets_defaults() {
+        let config = "instructions-per-frame = 15\nrenderer = \"window\"\n";
+
+        for args in [&["chip8"][..], &["chip8", "run"]] {
+            let cli = parse_with_config("defaults", config, args).unwrap();
+            let args = run_args(&cli);
+
+            assert_eq!(args.emulation.instructions_per_frame, Some(15));
+            assert_eq!(args.renderer, Renderer::Window);
+        }
+    }
+
+    #[test]
+    fn command_line_overrides_config() {
+        let config = "instructions-per-frame = 15\n";
+        let cli = parse_with_config(
+            "overrides",
+            config,
+            &["chip8", "run", "--instructions-per-frame", "20"],
+        )
+        .unwrap();
+
+        assert_eq!(run_args(&cli).emulation.instructions_per_frame, Some(20));
+    }
+
+    #[test]
+    fn missing_config_changes_nothing() {
+        let path = env::temp_dir().join(format!("chip8-{}-missing.toml", std::process::id()));
+        let command = apply_config(Cli::command(), &path).unwrap();
+        let cli = try_parse_args(command, ["chip8"]).unwrap();
+
+        assert_eq!(cli.run.emulation.instructions_per_frame, None);
+        assert_eq!(cli.run.renderer, Renderer::Terminal);
+    }
+
+    #[test]
+    fn config_rejects_unknown_keys_and_invalid_values() {
+        let err = parse_with_config("unknown", "nope = 1\n", &["chip8"])
+            .err()
+            .unwrap();
+        assert!(err.ends_with("there's no `--nope` flag"), "{err}");
+
+        let config = "instructions-per-frame = \"fast\"\n";
+        let err = parse_with_config("invalid", config, &["chip8"])
+            .err()
+            .unwrap();
+        assert!(
+            err.ends_with("`instructions-per-frame` can't be \"fast\""),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn root_flags_dont_apply_to_subcommands() {
+        let err = try_parse_args(Cli::command(), ["chip8", "--rom", "a.ch8", "run"]).err();
+        assert_eq!(
+            err.map(|err| err.kind()),
+            Some(clap::error::ErrorKind::ArgumentConflict)
+        );
+
+        let cli = try_parse_args(Cli::command(), ["chip8", "-v", "run"]).unwrap();
+        assert_eq!(cli.log.verbose, 1);
+    }
 }
@@ -0,0 +1,458 @@
+//! Menu for picking a ROM from a directory or a zip archive, drawn by the
+//! [Window] backends.
+
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::constant::FPS;
+use crate::database::RomInfo;
+use crate::error::Error;
+use crate::pacing::FrameLimiter;
+use crate::rom::{self, Reader, RomSource};
+use crate::window::{MenuKey, Window};
+
+/// Extensions of the ROMs listed in the menu, including Octo source and
+/// cartridges.
+const ROM_EXTENSIONS: [&str; 5] = ["ch8", "sc8", "xo8", "8o", "gif"];
+
+/// Lines of the description of the selected ROM shown below the list.
+const DESCRIPTION_LINES: usize = 3;
+
+/// A ROM listed in the [Menu].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MenuEntry {
+    pub source: RomSource,
+    pub title: String,
+    pub description: Option<String>,
+}
+
+/// ROMs in a directory or zip archive, one of which is selected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Menu {
+    /// The directory or archive, as shown in the header.
+    location: String,
+    entries: Vec<MenuEntry>,
+    selected: usize,
+    /// Shown at the bottom of the menu, e.g. when a ROM couldn't be loaded.
+    message: Option<String>,
+}
+
+/// A line of the [Menu], as text for backends to draw.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MenuLine {
+    pub text: String,
+    /// Whether the line is the selected ROM, so should stand out.
+    pub selected: bool,
+}
+
+impl Menu {
+    /// Lists the ROMs in `directory`.
+    ///
+    /// Titles and descriptions are taken from a Markdown file next to each ROM
+    /// with the same name, like `maze.md` for `maze.ch8`, or otherwise the ROM
+    /// database.
+    pub fn new(directory: &Path) -> Result<Self, Error> {
+        let read_error = |err| {
+            Error::ErrorStr(format!(
+                "Can't read directory '{}': {err}",
+                directory.display()
+            ))
+        };
+
+        let mut paths = fs::read_dir(directory)
+            .map_err(read_error)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(read_error)?;
+        paths.sort();
+
+        Self::with_sources(directory, paths.into_iter().map(RomSource::File).collect())
+    }
+
+    /// Lists the ROMs in the zip `archive`, in the same way as [Menu::new].
+    pub fn from_archive(archive: &Path) -> Result<Self, Error> {
+        Self::with_sources(archive, rom::archive_entries(archive)?)
+    }
+
+    /// Lists the ROMs among `sources`, which were found in `location`.
+    fn with_sources(location: &Path, mut sources: Vec<RomSource>) -> Result<Self, Error> {
+        sources.retain(|source| {
+            source
+                .extension()
+                .is_some_and(|extension| ROM_EXTENSIONS.contains(&extension.as_str()))
+        });
+
+        if sources.is_empty() {
+            return Err(Error::ErrorStr(format!(
+                "No ROMs in '{}', expected files ending in {}",
+                location.display(),
+                ROM_EXTENSIONS
+                    .map(|extension| format!(".{extension}"))
+                    .join(", ")
+            )));
+        }
+
+        Ok(Self {
+            location: location.display().to_string(),
+            entries: {
+                let mut reader = Reader::default();
+                sources
+                    .into_iter()
+                    .map(|source| MenuEntry::new(source, &mut reader))
+                    .collect()
+            },
+            selected: 0,
+            message: None,
+        })
+    }
+
+    pub fn entries(&self) -> &[MenuEntry] {
+        &self.entries
+    }
+
+    pub fn selected(&self) -> &MenuEntry {
+        &self.entries[self.selected]
+    }
+
+    pub fn set_message(&mut self, message: Option<String>) {
+        self.message = message;
+    }
+
+    /// Shows the menu in `window` until a ROM is chosen, returning where it is,
+    /// or `None` if the menu or the window was closed.
+    pub fn choose(&mut self, window: &mut dyn Window) -> Result<Option<RomSource>, Error> {
+        let mut limiter = FrameLimiter::new();
+
+        while window.is_running() {
+            limiter.start_frame(Duration::from_secs_f64(FPS));
+
+            for key in window.menu_keys_pressed() {
+                match key {
+                    MenuKey::Up => self.selected = self.selected.saturating_sub(1),
+                    MenuKey::Down => {
+                        self.selected = (self.selected + 1).min(self.entries.len() - 1)
+                    }
+                    MenuKey::Select => return Ok(Some(self.selected().source.clone())),
+                    MenuKey::Back => return Ok(None),
+                }
+            }
+
+            window.draw_menu(self)?;
+            limiter.end_frame(!window.paces_frames());
+        }
+
+        Ok(None)
+    }
+
+    /// Lays the menu out as text, in a grid of `columns` by `rows` characters.
+    pub fn lines(&self, columns: usize, rows: usize) -> Vec<MenuLine> {
+        let line = |text: &str, selected| MenuLine {
+            text: text.chars().take(columns).collect(),
+            selected,
+        };
+
+        let mut header = vec![
+            line(&format!("ROMs in {}", self.location), false),
+            line("Up/Down to choose, Enter to play, Esc to go back", false),
+            line("", false),
+        ];
+
+        let mut footer = vec![line("", false)];
+        let selected = self.selected();
+        let description = match &selected.description {
+            Some(description) => format!("{} - {description}", selected.source.name()),
+            None => selected.source.name(),
+        };
+        footer.extend(
+            wrap(&description, columns)
+                .iter()
+                .take(DESCRIPTION_LINES)
+                .map(|text| line(text, false)),
+        );
+        if let Some(message) = &self.message {
+            footer.push(line(message, false));
+        }
+
+        // Scroll the list to keep the selected ROM in view, dropping the
+        // header and then the footer when there isn't room for either.
+        if header.len() + footer.len() >= rows {
+            header.clear();
+        }
+        if footer.len() >= rows {
+            footer.clear();
+        }
+        let list_rows = rows.saturating_sub(header.len() + footer.len()).max(1);
+
+        let first = self.selected.saturating_sub(list_rows - 1);
+        let list = self
+            .entries
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(list_rows)
+            .map(|(i, entry)| {
+                let marker = if i == self.selected { '>' } else { ' ' };
+                line(&format!("{marker} {}", entry.title), i == self.selected)
+            });
+
+        header.into_iter().chain(list).chain(footer).collect()
+    }
+}
+
+impl MenuEntry {
+    /// Describes `source`, with `reader` reading it and its Markdown file.
+    fn new(source: RomSource, reader: &mut Reader) -> Self {
+        let markdown = source
+            .with_extension("md")
+            .and_then(|markdown| reader.read(&markdown).ok())
+            .and_then(|markdown| String::from_utf8(markdown).ok());
+        let (title, description) = match markdown {
+            Some(markdown) => parse_markdown(&markdown),
+            None => (None, None),
+        };
+
+        // The ROM is only read to look it up in the database, which isn't
+        // needed when the Markdown file says everything.
+        let rom_info = if title.is_some() && description.is_some() {
+            None
+        } else {
+            reader
+                .read(&source)
+                .ok()
+                .and_then(|rom| RomInfo::lookup(&rom))
+        };
+
+        Self {
+            title: title
+                .or(rom_info.as_ref().map(|info| info.title.clone()))
+                .unwrap_or_else(|| source.name()),
+            description: description.or(rom_info.and_then(|info| info.description)),
+            source,
+        }
+    }
+}
+
+/// Title from the first heading, and description from the first paragraph
+/// which isn't just a link, of a Markdown file.
+fn parse_markdown(markdown: &str) -> (Option<String>, Option<String>) {
+    let title = markdown
+        .lines()
+        .find_map(|line| line.strip_prefix('#'))
+        .map(|title| title.trim_start_matches('#').trim().to_string());
+
+    let description = markdown
+        .split("\n\n")
+        .map(str::trim)
+        .find(|paragraph| {
+            !paragraph.is_empty() && !paragraph.starts_with('#') && !paragraph.starts_with('[')
+        })
+        .map(|paragraph| paragraph.split_whitespace().collect::<Vec<_>>().join(" "));
+
+    (title, description)
+}
+
+/// Splits `text` into lines of at most `width` characters, between words
+/// where possible.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = vec![];
+    let mut current = String::new();
+
+    for word in text.split_whitespace() {
+        if !current.is_empty() && current.chars().count() + 1 + word.chars().count() > width {
+            lines.push(current);
+            current = String::new();
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(word);
+
+        while current.chars().count() > width.max(1) {
+            let rest = current.chars().skip(width.max(1)).collect();
+            lines.push(current.chars().take(width.max(1)).collect());
+            current = rest;
+        }
+    }
+    if !current.is_empty() {
+        lines.push(current);
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keypad::Key;
+    use crate::screen::{Damage, Framebuffer};
+    use crate::window::{Hotkey, Status};
+    use std::path::PathBuf;
+
+    /// A [Window] where `frames` are the menu keys pressed in each frame,
+    /// which closes after the last one.
+    struct Keys {
+        frames: Vec<Vec<MenuKey>>,
+    }
+
+    impl Window for Keys {
+        fn is_running(&mut self) -> bool {
+            !self.frames.is_empty()
+        }
+
+        fn is_key_down(&self, _key: Key) -> bool {
+            false
+        }
+
+        fn is_key_up(&self, _key: Key) -> bool {
+            true
+        }
+
+        fn wait_for_next_key(&mut self) -> Option<Key> {
+            None
+        }
+
+        fn hotkeys_pressed(&self) -> Vec<Hotkey> {
+            vec![]
+        }
+
+        fn is_hotkey_down(&self, _hotkey: Hotkey) -> bool {
+            false
+        }
+
+        fn menu_keys_pressed(&self) -> Vec<MenuKey> {
+            self.frames[0].clone()
+        }
+
+        fn draw_menu(&mut self, _menu: &Menu) -> Result<(), Error> {
+            self.frames.remove(0);
+            Ok(())
+        }
+
+        fn paces_frames(&self) -> bool {
+            true
+        }
+
+        fn update(&mut self, _: Framebuffer, _: Damage, _: &Status) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    fn menu(titles: &[&str], selected: usize) -> Menu {
+        Menu {
+            location: "roms".to_string(),
+            entries: titles
+                .iter()
+                .map(|title| MenuEntry {
+                    source: RomSource::File(PathBuf::from(format!("roms/{title}.ch8"))),
+                    title: title.to_string(),
+                    description: None,
+                })
+                .collect(),
+            selected,
+            message: None,
+        }
+    }
+
+    fn texts(lines: &[MenuLine]) -> Vec<&str> {
+        lines.iter().map(|line| line.text.as_str()).collect()
+    }
+
+    #[test]
+    fn lists_every_rom_with_room() {
+        let mut menu = menu(&["maze", "pong"], 1);
+        menu.entries[1].description = Some("Two paddles".to_string());
+        let lines = menu.lines(60, 10);
+
+        assert_eq!(
+            texts(&lines),
+            [
+                "ROMs in roms",
+                "Up/Down to choose, Enter to play, Esc to go back",
+                "",
+                "  maze",
+                "> pong",
+                "",
+                "pong.ch8 - Two paddles",
+            ]
+        );
+        assert_eq!(
+            lines.iter().map(|line| line.selected).collect::<Vec<_>>(),
+            [false, false, false, false, true, false, false]
+        );
+    }
+
+    #[test]
+    fn scrolls_to_the_selected_rom() {
+        let menu = menu(&["a", "b", "c", "d", "e", "f"], 4);
+
+        // 3 header lines, 2 footer lines and 3 ROMs.
+        assert_eq!(texts(&menu.lines(60, 8))[3..6], ["  c", "  d", "> e"]);
+    }
+
+    #[test]
+    fn drops_the_header_and_then_the_footer() {
+        let menu = menu(&["a", "b", "c"], 1);
+
+        assert_eq!(texts(&menu.lines(60, 4)), ["  a", "> b", "", "b.ch8"]);
+        assert_eq!(texts(&menu.lines(60, 2)), ["  a", "> b"]);
+        assert_eq!(texts(&menu.lines(60, 0)), ["> b"]);
+    }
+
+    #[test]
+    fn wraps_the_description_and_truncates_lines() {
+        let mut menu = menu(&["maze"], 0);
+        menu.entries[0].description = Some("A random maze of diagonal lines".to_string());
+        menu.set_message(Some("Can't load maze.ch8".to_string()));
+
+        assert_eq!(
+            texts(&menu.lines(12, 20)),
+            [
+                "ROMs in roms",
+                "Up/Down to c",
+                "",
+                "> maze",
+                "",
+                "maze.ch8 - A",
+                "random maze",
+                "of diagonal",
+                "Can't load m",
+            ]
+        );
+    }
+
+    #[test]
+    fn choose_moves_within_the_list() {
+        let mut menu = menu(&["a", "b", "c"], 0);
+        let mut window = Keys {
+            frames: vec![
+                vec![MenuKey::Up],
+                vec![MenuKey::Down, MenuKey::Down, MenuKey::Down],
+                vec![MenuKey::Up],
+                vec![MenuKey::Select],
+            ],
+        };
+
+        assert_eq!(
+            menu.choose(&mut window).unwrap(),
+            Some(RomSource::File(PathBuf::from("roms/b.ch8")))
+        );
+        assert_eq!(menu.selected().title, "b");
+    }
+
+    #[test]
+    fn choose_stops_on_back_or_when_closed() {
+        let mut menu = menu(&["a", "b"], 0);
+
+        let mut window = Keys {
+            frames: vec![vec![MenuKey::Down], vec![MenuKey::Back, MenuKey::Select]],
+        };
+        assert_eq!(menu.choose(&mut window).unwrap(), None);
+
+        let mut window = Keys {
+            frames: vec![vec![], vec![]],
+        };
+        assert_eq!(menu.choose(&mut window).unwrap(), None);
+        assert!(window.frames.is_empty());
+    }
+}
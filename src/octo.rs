@@ -0,0 +1,1020 @@
+//! Compiler for the assembly language of [Octo](https://github.com/JohnEarnest/Octo),
+//! which Octo cartridges store their programs in.
+//!
+//! Only the CHIP-8 instructions are supported, not the ones added by
+//! SUPER-CHIP and XO-CHIP, and neither are `:stringmode` and `:assert`.
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::constant::{MEMORY_SIZE, ROM_LOAD_OFFSET};
+use crate::error::Error;
+
+/// Compiles Octo `source` to a ROM, to be loaded at [ROM_LOAD_OFFSET].
+pub fn compile(source: &str) -> Result<Vec<u8>, Error> {
+    let mut compiler = Compiler::new(source);
+
+    while let Some(token) = compiler.next() {
+        compiler.statement(token)?;
+    }
+
+    compiler.finish()
+}
+
+/// Instructions which Octo supports, but only for SUPER-CHIP or XO-CHIP.
+const UNSUPPORTED: [&str; 13] = [
+    "hires",
+    "lores",
+    "scroll-down",
+    "scroll-up",
+    "scroll-left",
+    "scroll-right",
+    "exit",
+    "saveflags",
+    "loadflags",
+    "plane",
+    "audio",
+    "pitch",
+    "bighex",
+];
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+/// A place where an address is written before it is known.
+#[derive(Debug, Clone, Copy)]
+enum Patch {
+    /// The low 12 bits of the instruction at this offset.
+    Instruction(usize),
+    /// The two `vX := NN` instructions of `:unpack` at this offset, where the
+    /// high nibble of the first is already set.
+    Unpack(usize),
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+    calls: usize,
+}
+
+struct Loop {
+    start: u16,
+    /// Jumps out of the loop from `while`, to patch at `again`.
+    exits: Vec<usize>,
+}
+
+/// Comparisons which can follow `if` and `while`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessOrEqual,
+    GreaterOrEqual,
+}
+
+#[derive(Clone, Copy)]
+enum Operand {
+    Register(u8),
+    Byte(u8),
+}
+
+enum Condition {
+    Compare(u8, Comparison, Operand),
+    Key(u8),
+    NotKey(u8),
+}
+
+struct Compiler {
+    tokens: VecDeque<Token>,
+    /// Line of the token last taken, for errors.
+    line: usize,
+
+    /// Memory from [ROM_LOAD_OFFSET].
+    memory: Vec<u8>,
+    /// Address which the next byte is written to.
+    here: usize,
+
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    /// Uses of labels which weren't defined yet.
+    forward: Vec<(String, Patch, usize)>,
+
+    /// Jumps past the blocks of `if ... begin`, to patch at `else` or `end`.
+    branches: Vec<usize>,
+    loops: Vec<Loop>,
+}
+
+impl Compiler {
+    fn new(source: &str) -> Self {
+        let tokens = source
+            .lines()
+            .enumerate()
+            .flat_map(|(i, line)| {
+                let code = line.split('#').next().unwrap_or_default();
+                code.split_whitespace().map(move |text| Token {
+                    text: text.to_string(),
+                    line: i + 1,
+                })
+            })
+            .collect();
+
+        Self {
+            tokens,
+            line: 0,
+            // Leave space for the jump to `main`.
+            memory: vec![0; 2],
+            here: ROM_LOAD_OFFSET + 2,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            forward: vec![],
+            branches: vec![],
+            loops: vec![],
+        }
+    }
+
+    fn error(&self, message: impl AsRef<str>) -> Error {
+        Error::ErrorStr(format!("line {}: {}", self.line, message.as_ref()))
+    }
+
+    fn next(&mut self) -> Option<String> {
+        let token = self.tokens.pop_front()?;
+        self.line = token.line;
+        Some(token.text)
+    }
+
+    fn expect_next(&mut self) -> Result<String, Error> {
+        self.next()
+            .ok_or_else(|| self.error("Unexpected end of program"))
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), Error> {
+        let token = self.expect_next()?;
+        if token != expected {
+            return Err(self.error(format!("Expected '{expected}', found '{token}'")));
+        }
+        Ok(())
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| token.text.as_str())
+    }
+
+    fn statement(&mut self, token: String) -> Result<(), Error> {
+        match token.as_str() {
+            ":" => {
+                let name = self.expect_next()?;
+                self.define_label(name, self.here)?;
+            }
+            ":next" => {
+                let name = self.expect_next()?;
+                self.define_label(name, self.here + 1)?;
+            }
+            ":const" => {
+                let name = self.expect_next()?;
+                let value = self.expect_next()?;
+                let value = self.number(&value)?;
+                self.constants.insert(name, value);
+            }
+            ":alias" => {
+                let name = self.expect_next()?;
+                let register = self.expect_next()?;
+                let register = self.register(&register)?;
+                self.aliases.insert(name, register);
+            }
+            ":unpack" => {
+                let nibble = self.expect_next()?;
+                if nibble == "long" {
+                    return Err(self.error("':unpack long' is only supported by XO-CHIP"));
+                }
+                let nibble = self.byte(&nibble)? & 0xF;
+                let name = self.expect_next()?;
+
+                let offset = self.offset();
+                self.emit_op(0x6000 | (nibble as u16) << 4);
+                self.emit_op(0x6100);
+                self.address_at(&name, Patch::Unpack(offset))?;
+            }
+            ":org" => {
+                let address = self.expect_next()?;
+                let address = self.number(&address)? as usize;
+                if !(ROM_LOAD_OFFSET..MEMORY_SIZE).contains(&address) {
+                    return Err(self.error(format!("Can't :org to {address:#05X}")));
+                }
+                self.here = address;
+            }
+            ":byte" => {
+                let value = if self.peek() == Some("{") {
+                    self.next();
+                    let value = self.expression()?;
+                    self.expect("}")?;
+                    value as i64 as u8
+                } else {
+                    let value = self.expect_next()?;
+                    self.byte(&value)?
+                };
+                self.emit(value);
+            }
+            ":call" => {
+                let name = self.expect_next()?;
+                self.call(&name)?;
+            }
+            ":macro" => self.define_macro()?,
+            ":calc" => {
+                let name = self.expect_next()?;
+                self.expect("{")?;
+                let value = self.expression()?;
+                self.expect("}")?;
+                self.constants.insert(name, value);
+            }
+            ":breakpoint" => {
+                self.expect_next()?;
+            }
+            ":monitor" => {
+                self.expect_next()?;
+                self.expect_next()?;
+            }
+            "return" | ";" => self.emit_op(0x00EE),
+            "clear" => self.emit_op(0x00E0),
+            "bcd" => {
+                let x = self.next_register()?;
+                self.emit_op(0xF033 | (x as u16) << 8);
+            }
+            "save" | "load" => {
+                let x = self.next_register()?;
+                if self.peek() == Some("-") {
+                    return Err(
+                        self.error(format!("'{token} vx - vy' is only supported by XO-CHIP"))
+                    );
+                }
+                let op = if token == "save" { 0xF055 } else { 0xF065 };
+                self.emit_op(op | (x as u16) << 8);
+            }
+            "sprite" => {
+                let x = self.next_register()?;
+                let y = self.next_register()?;
+                let n = self.expect_next()?;
+                let n = self.byte(&n)?;
+                if n > 0xF {
+                    return Err(self.error(format!("Sprites are at most 15 rows, not {n}")));
+                }
+                self.emit_op(0xD000 | (x as u16) << 8 | (y as u16) << 4 | n as u16);
+            }
+            "jump" | "jump0" | "native" => {
+                let op = match token.as_str() {
+                    "jump" => 0x1000,
+                    "jump0" => 0xB000,
+                    _ => 0x0000,
+                };
+                let name = self.expect_next()?;
+                let offset = self.offset();
+                self.emit_op(op);
+                self.address_at(&name, Patch::Instruction(offset))?;
+            }
+            "delay" | "buzzer" => {
+                self.expect(":=")?;
+                let x = self.next_register()?;
+                let op = if token == "delay" { 0xF015 } else { 0xF018 };
+                self.emit_op(op | (x as u16) << 8);
+            }
+            "i" => self.index()?,
+            "if" => self.conditional()?,
+            "else" => {
+                let branch = self
+                    .branches
+                    .pop()
+                    .ok_or_else(|| self.error("'else' without 'if'"))?;
+                let offset = self.offset();
+                self.emit_op(0x1000);
+                self.patch(Patch::Instruction(branch), self.here as u16);
+                self.branches.push(offset);
+            }
+            "end" => {
+                let branch = self
+                    .branches
+                    .pop()
+                    .ok_or_else(|| self.error("'end' without 'if'"))?;
+                self.patch(Patch::Instruction(branch), self.here as u16);
+            }
+            "loop" => self.loops.push(Loop {
+                start: self.here as u16,
+                exits: vec![],
+            }),
+            "while" => {
+                if self.loops.is_empty() {
+                    return Err(self.error("'while' outside of a loop"));
+                }
+                let condition = self.condition()?;
+                self.skip_if(condition, true);
+                let offset = self.offset();
+                self.emit_op(0x1000);
+                self.loops.last_mut().unwrap().exits.push(offset);
+            }
+            "again" => {
+                let lp = self
+                    .loops
+                    .pop()
+                    .ok_or_else(|| self.error("'again' without 'loop'"))?;
+                self.emit_op(0x1000 | lp.start);
+                for exit in lp.exits {
+                    self.patch(Patch::Instruction(exit), self.here as u16);
+                }
+            }
+            ":stringmode" | ":assert" | ":pointer" => {
+                return Err(self.error(format!("'{token}' isn't supported")));
+            }
+            token if UNSUPPORTED.contains(&token) => {
+                return Err(self.error(format!(
+                    "'{token}' is only supported by SUPER-CHIP and XO-CHIP"
+                )));
+            }
+            _ if self.is_register(&token) => {
+                let x = self.register(&token)?;
+                self.register_operation(x)?;
+            }
+            _ if self.macros.contains_key(&token) => self.expand_macro(&token)?,
+            // Labels are numbers too, but a bare one is a call.
+            _ if self.labels.contains_key(&token) => self.call(&token)?,
+            _ if self.try_number(&token).is_some() => {
+                let value = self.byte(&token)?;
+                self.emit(value);
+            }
+            _ => self.call(&token)?,
+        }
+
+        Ok(())
+    }
+
+    /// Statements starting with a register, like `v0 += 1`.
+    fn register_operation(&mut self, x: u8) -> Result<(), Error> {
+        let operator = self.expect_next()?;
+        let operand = self.expect_next()?;
+        let x = (x as u16) << 8;
+
+        let op = match (operator.as_str(), operand.as_str()) {
+            (":=", "random") => {
+                let mask = self.expect_next()?;
+                0xC000 | x | self.byte(&mask)? as u16
+            }
+            (":=", "key") => 0xF00A | x,
+            (":=", "delay") => 0xF007 | x,
+            (_, y) if self.is_register(y) => {
+                let y = (self.register(y)? as u16) << 4;
+                let op = match operator.as_str() {
+                    ":=" => 0x0,
+                    "|=" => 0x1,
+                    "&=" => 0x2,
+                    "^=" => 0x3,
+                    "+=" => 0x4,
+                    "-=" => 0x5,
+                    ">>=" => 0x6,
+                    "=-" => 0x7,
+                    "<<=" => 0xE,
+                    _ => return Err(self.error(format!("Unknown operator '{operator}'"))),
+                };
+                0x8000 | x | y | op
+            }
+            (":=", value) => 0x6000 | x | self.byte(value)? as u16,
+            ("+=", value) => 0x7000 | x | self.byte(value)? as u16,
+            ("-=", value) => 0x7000 | x | self.byte(value)?.wrapping_neg() as u16,
+            _ => return Err(self.error(format!("Can't use '{operator}' with '{operand}'"))),
+        };
+
+        self.emit_op(op);
+        Ok(())
+    }
+
+    /// Statements starting with `i`.
+    fn index(&mut self) -> Result<(), Error> {
+        let operator = self.expect_next()?;
+        let operand = self.expect_next()?;
+
+        match (operator.as_str(), operand.as_str()) {
+            (":=", "hex") => {
+                let x = self.next_register()?;
+                self.emit_op(0xF029 | (x as u16) << 8);
+            }
+            (":=", "bighex") | (":=", "long") => {
+                return Err(self.error(format!(
+                    "'i := {operand}' is only supported by SUPER-CHIP and XO-CHIP"
+                )));
+            }
+            (":=", name) => {
+                let offset = self.offset();
+                self.emit_op(0xA000);
+                self.address_at(name, Patch::Instruction(offset))?;
+            }
+            ("+=", x) => {
+                let x = self.register(x)?;
+                self.emit_op(0xF01E | (x as u16) << 8);
+            }
+            _ => return Err(self.error(format!("Can't use '{operator}' with 'i'"))),
+        }
+
+        Ok(())
+    }
+
+    /// `if ... then` and `if ... begin`.
+    fn conditional(&mut self) -> Result<(), Error> {
+        let condition = self.condition()?;
+
+        match self.expect_next()?.as_str() {
+            // The next statement is only run if the condition holds.
+            "then" => self.skip_if(condition, false),
+            "begin" => {
+                self.skip_if(condition, true);
+                self.branches.push(self.offset());
+                self.emit_op(0x1000);
+            }
+            token => return Err(self.error(format!("Expected 'then' or 'begin', found '{token}'"))),
+        }
+
+        Ok(())
+    }
+
+    fn condition(&mut self) -> Result<Condition, Error> {
+        let x = self.next_register()?;
+        let operator = self.expect_next()?;
+
+        let comparison = match operator.as_str() {
+            "key" => return Ok(Condition::Key(x)),
+            "-key" => return Ok(Condition::NotKey(x)),
+            "==" => Comparison::Equal,
+            "!=" => Comparison::NotEqual,
+            "<" => Comparison::Less,
+            ">" => Comparison::Greater,
+            "<=" => Comparison::LessOrEqual,
+            ">=" => Comparison::GreaterOrEqual,
+            _ => return Err(self.error(format!("Unknown comparison '{operator}'"))),
+        };
+
+        let operand = self.expect_next()?;
+        let operand = if self.is_register(&operand) {
+            Operand::Register(self.register(&operand)?)
+        } else {
+            Operand::Byte(self.byte(&operand)?)
+        };
+
+        if x == 0xF && !matches!(comparison, Comparison::Equal | Comparison::NotEqual) {
+            return Err(self.error("Can't compare vf with '<' and '>', as they use vf"));
+        }
+
+        Ok(Condition::Compare(x, comparison, operand))
+    }
+
+    /// Emits instructions which skip the next one if `condition` is `holds`.
+    fn skip_if(&mut self, condition: Condition, holds: bool) {
+        let (x, comparison, operand) = match condition {
+            Condition::Key(x) => {
+                let op = if holds { 0xE09E } else { 0xE0A1 };
+                return self.emit_op(op | (x as u16) << 8);
+            }
+            Condition::NotKey(x) => {
+                let op = if holds { 0xE0A1 } else { 0xE09E };
+                return self.emit_op(op | (x as u16) << 8);
+            }
+            Condition::Compare(x, comparison, operand) => (x, comparison, operand),
+        };
+
+        let x16 = (x as u16) << 8;
+
+        // Skip when equal if `holds` matches `==`, otherwise when not equal.
+        let skip_when_equal = holds == (comparison == Comparison::Equal);
+        match (comparison, operand) {
+            (Comparison::Equal | Comparison::NotEqual, Operand::Byte(nn)) => {
+                let op = if skip_when_equal { 0x3000 } else { 0x4000 };
+                return self.emit_op(op | x16 | nn as u16);
+            }
+            (Comparison::Equal | Comparison::NotEqual, Operand::Register(y)) => {
+                let op = if skip_when_equal { 0x5000 } else { 0x9000 };
+                return self.emit_op(op | x16 | (y as u16) << 4);
+            }
+            _ => {}
+        }
+
+        // The other comparisons subtract into vf, where the carry flag, which
+        // is written last, is 1 if there was no borrow. `vx < y` and `vx >= y`
+        // check `vx - y`, and `vx > y` and `vx <= y` check `y - vx`.
+        let x4 = (x as u16) << 4;
+        match (comparison, operand) {
+            (Comparison::Less | Comparison::GreaterOrEqual, Operand::Register(y)) => {
+                self.emit_op(0x8F00 | x4);
+                self.emit_op(0x8F05 | (y as u16) << 4);
+            }
+            (Comparison::Less | Comparison::GreaterOrEqual, Operand::Byte(nn)) => {
+                self.emit_op(0x6F00 | nn as u16);
+                self.emit_op(0x8F07 | x4);
+            }
+            (_, Operand::Register(y)) => {
+                self.emit_op(0x8F00 | (y as u16) << 4);
+                self.emit_op(0x8F05 | x4);
+            }
+            (_, Operand::Byte(nn)) => {
+                self.emit_op(0x6F00 | nn as u16);
+                self.emit_op(0x8F05 | x4);
+            }
+        }
+
+        // The value of vf when the comparison holds.
+        let flag = match comparison {
+            Comparison::Less | Comparison::Greater => 0,
+            _ => 1,
+        };
+        let op = if holds { 0x3F00 } else { 0x4F00 };
+        self.emit_op(op | flag);
+    }
+
+    fn define_macro(&mut self) -> Result<(), Error> {
+        let name = self.expect_next()?;
+
+        let mut params = vec![];
+        loop {
+            match self.expect_next()? {
+                brace if brace == "{" => break,
+                param => params.push(param),
+            }
+        }
+
+        let mut body = vec![];
+        let mut depth = 1;
+        loop {
+            let token = self
+                .tokens
+                .pop_front()
+                .ok_or_else(|| self.error(format!("Macro '{name}' is missing a '}}'")))?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                break;
+            }
+            body.push(token);
+        }
+
+        self.macros.insert(
+            name,
+            Macro {
+                params,
+                body,
+                calls: 0,
+            },
+        );
+
+        Ok(())
+    }
+
+    fn expand_macro(&mut self, name: &str) -> Result<(), Error> {
+        let count = self.macros[name].params.len();
+        let args = (0..count)
+            .map(|_| self.expect_next())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let definition = self.macros.get_mut(name).unwrap();
+        let calls = definition.calls;
+        definition.calls += 1;
+
+        let expanded = definition
+            .body
+            .iter()
+            .map(|token| {
+                let text = match definition.params.iter().position(|p| *p == token.text) {
+                    Some(i) => args[i].clone(),
+                    None if token.text == "CALLS" => calls.to_string(),
+                    None => token.text.clone(),
+                };
+                Token {
+                    text,
+                    line: token.line,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        for token in expanded.into_iter().rev() {
+            self.tokens.push_front(token);
+        }
+
+        Ok(())
+    }
+
+    /// Evaluates a `:calc` expression, up to but not including the closing
+    /// brace or parenthesis.
+    ///
+    /// As in Octo, operators have no precedence and are evaluated from right
+    /// to left, so `2 * 3 + 4` is 14.
+    fn expression(&mut self) -> Result<f64, Error> {
+        let left = self.term()?;
+
+        let Some(operator) = self.peek().map(str::to_string) else {
+            return Ok(left);
+        };
+        if operator == "}" || operator == ")" {
+            return Ok(left);
+        }
+        self.next();
+
+        let right = self.expression()?;
+        let (a, b) = (left as i64, right as i64);
+        let bool = |b: bool| b as i64 as f64;
+
+        Ok(match operator.as_str() {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<<" => (a << b) as f64,
+            ">>" => (a >> b) as f64,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => bool(left < right),
+            ">" => bool(left > right),
+            "<=" => bool(left <= right),
+            ">=" => bool(left >= right),
+            "==" => bool(left == right),
+            "!=" => bool(left != right),
+            _ => return Err(self.error(format!("Unknown operator '{operator}'"))),
+        })
+    }
+
+    fn term(&mut self) -> Result<f64, Error> {
+        let token = self.expect_next()?;
+
+        let unary = |f: fn(f64) -> f64, compiler: &mut Self| Ok(f(compiler.term()?));
+
+        match token.as_str() {
+            "(" => {
+                let value = self.expression()?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            "-" => unary(|x| -x, self),
+            "~" => unary(|x| !(x as i64) as f64, self),
+            "!" => unary(|x| (x == 0.) as i64 as f64, self),
+            "abs" => unary(f64::abs, self),
+            "sqrt" => unary(f64::sqrt, self),
+            "sin" => unary(f64::sin, self),
+            "cos" => unary(f64::cos, self),
+            "tan" => unary(f64::tan, self),
+            "exp" => unary(f64::exp, self),
+            "log" => unary(f64::ln, self),
+            "sign" => unary(f64::signum, self),
+            "ceil" => unary(f64::ceil, self),
+            "floor" => unary(f64::floor, self),
+            "@" => {
+                let address = self.term()? as usize;
+                Ok(address
+                    .checked_sub(ROM_LOAD_OFFSET)
+                    .and_then(|offset| self.memory.get(offset))
+                    .copied()
+                    .unwrap_or(0) as f64)
+            }
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            "HERE" => Ok(self.here as f64),
+            _ => self.number(&token),
+        }
+    }
+
+    /// Numbers, constants and defined labels.
+    fn try_number(&self, token: &str) -> Option<f64> {
+        let (negative, digits) = match token.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, token),
+        };
+
+        let parsed = if let Some(hex) = digits.strip_prefix("0x") {
+            i64::from_str_radix(hex, 16).ok().map(|n| n as f64)
+        } else if let Some(binary) = digits.strip_prefix("0b") {
+            i64::from_str_radix(binary, 2).ok().map(|n| n as f64)
+        } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+            digits.parse::<f64>().ok()
+        } else {
+            None
+        };
+
+        if let Some(n) = parsed {
+            return Some(if negative { -n } else { n });
+        }
+
+        self.constants
+            .get(token)
+            .copied()
+            .or_else(|| self.labels.get(token).map(|address| *address as f64))
+    }
+
+    fn number(&self, token: &str) -> Result<f64, Error> {
+        self.try_number(token)
+            .ok_or_else(|| self.error(format!("Expected a number, found '{token}'")))
+    }
+
+    fn byte(&self, token: &str) -> Result<u8, Error> {
+        let value = self.number(token)? as i64;
+        if !(-128..=255).contains(&value) {
+            return Err(self.error(format!("{value} doesn't fit in a byte")));
+        }
+        Ok(value as u8)
+    }
+
+    fn is_register(&self, token: &str) -> bool {
+        self.register(token).is_ok()
+    }
+
+    fn register(&self, token: &str) -> Result<u8, Error> {
+        if let Some(register) = self.aliases.get(token) {
+            return Ok(*register);
+        }
+
+        match token.strip_prefix(['v', 'V']) {
+            Some(digit) if digit.len() == 1 => u8::from_str_radix(digit, 16)
+                .map_err(|_| self.error(format!("Expected a register, found '{token}'"))),
+            _ => Err(self.error(format!("Expected a register, found '{token}'"))),
+        }
+    }
+
+    fn next_register(&mut self) -> Result<u8, Error> {
+        let token = self.expect_next()?;
+        self.register(&token)
+    }
+
+    fn call(&mut self, name: &str) -> Result<(), Error> {
+        let offset = self.offset();
+        self.emit_op(0x2000);
+        self.address_at(name, Patch::Instruction(offset))
+    }
+
+    /// Writes the address `name` refers to at `patch`, now or once the label
+    /// is defined.
+    fn address_at(&mut self, name: &str, patch: Patch) -> Result<(), Error> {
+        match self.try_number(name) {
+            Some(address) if (0.0..MEMORY_SIZE as f64).contains(&address) => {
+                self.patch(patch, address as u16);
+            }
+            Some(address) => {
+                return Err(self.error(format!("Address {address} is out of range")));
+            }
+            None if name.starts_with(|c: char| c.is_ascii_digit() || c == '-') => {
+                return Err(self.error(format!("Expected an address, found '{name}'")));
+            }
+            None => self.forward.push((name.to_string(), patch, self.line)),
+        }
+
+        Ok(())
+    }
+
+    fn define_label(&mut self, name: String, address: usize) -> Result<(), Error> {
+        if self.labels.contains_key(&name) {
+            return Err(self.error(format!("Label '{name}' is already defined")));
+        }
+
+        let (uses, rest) = self
+            .forward
+            .drain(..)
+            .partition::<Vec<_>, _>(|(label, _, _)| *label == name);
+        self.forward = rest;
+        for (_, patch, _) in uses {
+            self.patch(patch, address as u16);
+        }
+
+        self.labels.insert(name, address as u16);
+        Ok(())
+    }
+
+    fn patch(&mut self, patch: Patch, address: u16) {
+        let (high, low) = ((address >> 8) as u8 & 0xF, address as u8);
+
+        match patch {
+            Patch::Instruction(offset) => {
+                self.memory[offset] |= high;
+                self.memory[offset + 1] = low;
+            }
+            Patch::Unpack(offset) => {
+                self.memory[offset + 1] |= high;
+                self.memory[offset + 3] = low;
+            }
+        }
+    }
+
+    /// Offset in [Compiler::memory] of the next byte.
+    fn offset(&self) -> usize {
+        self.here - ROM_LOAD_OFFSET
+    }
+
+    fn emit(&mut self, byte: u8) {
+        let offset = self.offset();
+        if offset >= self.memory.len() {
+            self.memory.resize(offset + 1, 0);
+        }
+        self.memory[offset] = byte;
+        self.here += 1;
+    }
+
+    fn emit_op(&mut self, op: u16) {
+        self.emit((op >> 8) as u8);
+        self.emit(op as u8);
+    }
+
+    fn finish(mut self) -> Result<Vec<u8>, Error> {
+        if let Some((name, _, line)) = self.forward.first() {
+            self.line = *line;
+            return Err(self.error(format!("Undefined label '{name}'")));
+        }
+        if !self.branches.is_empty() {
+            return Err(self.error("'if ... begin' is missing an 'end'"));
+        }
+        if !self.loops.is_empty() {
+            return Err(self.error("'loop' is missing an 'again'"));
+        }
+        if self.here > MEMORY_SIZE {
+            return Err(self.error("Program is too large to fit in memory"));
+        }
+
+        let main = *self
+            .labels
+            .get("main")
+            .ok_or_else(|| Error::ErrorStr("Program has no 'main' label".to_string()))?;
+        self.memory[0] = 0x10 | (main >> 8) as u8;
+        self.memory[1] = main as u8;
+
+        Ok(self.memory)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(source: &str) -> String {
+        compile(source).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn labels_and_forward_references() {
+        let rom = compile(
+            ": main
+               jump later # forward
+             : later
+               i := data
+               sprite v0 v1 1
+             : data
+               0xFF",
+        )
+        .unwrap();
+
+        assert_eq!(rom, [0x12, 0x02, 0x12, 0x04, 0xA2, 0x08, 0xD0, 0x11, 0xFF]);
+    }
+
+    #[test]
+    fn bare_label_is_a_call() {
+        let rom = compile(
+            ": sub
+               return
+             : main
+               sub
+               later
+               loop again
+             : later
+               ;",
+        )
+        .unwrap();
+
+        assert_eq!(
+            rom,
+            [0x12, 0x04, 0x00, 0xEE, 0x22, 0x02, 0x22, 0x0A, 0x12, 0x08, 0x00, 0xEE]
+        );
+    }
+
+    #[test]
+    fn if_then() {
+        let rom = compile(
+            ": main
+               if v0 == 5 then v1 := 2
+               if v0 != v2 then v1 := 3
+               if v1 < 5 then v2 := 0",
+        )
+        .unwrap();
+
+        assert_eq!(
+            rom,
+            [
+                0x12, 0x02, 0x40, 0x05, 0x61, 0x02, 0x50, 0x20, 0x61, 0x03, 0x6F, 0x05, 0x8F, 0x17,
+                0x4F, 0x00, 0x62, 0x00
+            ]
+        );
+    }
+
+    #[test]
+    fn if_begin_else_end() {
+        let rom = compile(
+            ": main
+               if v0 == 1 begin
+                 v1 := 1
+               else
+                 v1 := 2
+               end",
+        )
+        .unwrap();
+
+        assert_eq!(
+            rom,
+            [0x12, 0x02, 0x30, 0x01, 0x12, 0x0A, 0x61, 0x01, 0x12, 0x0C, 0x61, 0x02]
+        );
+    }
+
+    #[test]
+    fn loop_while_again() {
+        let rom = compile(
+            ": main
+               loop
+                 v0 += 1
+                 while v0 != 10
+               again",
+        )
+        .unwrap();
+
+        assert_eq!(
+            rom,
+            [0x12, 0x02, 0x70, 0x01, 0x40, 0x0A, 0x12, 0x0A, 0x12, 0x02]
+        );
+    }
+
+    #[test]
+    fn unpack() {
+        let rom = compile(
+            ": main
+               :unpack 0xA data
+               i := data
+             : data
+               0x01",
+        )
+        .unwrap();
+
+        assert_eq!(rom, [0x12, 0x02, 0x60, 0xA2, 0x61, 0x08, 0xA2, 0x08, 0x01]);
+    }
+
+    #[test]
+    fn const_and_alias() {
+        let rom = compile(
+            ":const SPEED 3
+             :alias x v4
+             : main
+               x := SPEED
+               x += x",
+        )
+        .unwrap();
+
+        assert_eq!(rom, [0x12, 0x02, 0x64, 0x03, 0x84, 0x44]);
+    }
+
+    #[test]
+    fn macros() {
+        let rom = compile(
+            ":macro twice reg {
+               reg += 1
+               reg += 1
+             }
+             : main
+               twice v3",
+        )
+        .unwrap();
+
+        assert_eq!(rom, [0x12, 0x02, 0x73, 0x01, 0x73, 0x01]);
+    }
+
+    #[test]
+    fn errors_have_lines() {
+        assert_eq!(
+            error(": main\n  v0 := 300"),
+            "line 2: 300 doesn't fit in a byte"
+        );
+        assert_eq!(
+            error(": main\n\n  jump nowhere"),
+            "line 3: Undefined label 'nowhere'"
+        );
+        assert_eq!(
+            error(": main\n  if v0 ~ 1 then v1 := 0"),
+            "line 2: Unknown comparison '~'"
+        );
+        assert_eq!(
+            error(": main\n  hires"),
+            "line 2: 'hires' is only supported by SUPER-CHIP and XO-CHIP"
+        );
+        assert_eq!(
+            error(": main\n  if v0 == 1 begin\n  v1 := 2"),
+            "line 3: 'if ... begin' is missing an 'end'"
+        );
+        assert_eq!(error(": start\n  clear"), "Program has no 'main' label");
+    }
+}
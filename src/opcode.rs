@@ -1,3 +1,5 @@
+use std::fmt;
+
 #[derive(Debug)]
 pub enum Opcode {
     /// 0NNN
@@ -83,17 +85,19 @@ pub enum Opcode {
 }
 
 impl Opcode {
-    pub fn from(buf: [u8; 2]) -> Self {
+    /// Decodes an instruction, or returns `None` if `buf` isn't one, e.g.
+    /// when it is data.
+    pub fn decode(buf: [u8; 2]) -> Option<Self> {
         let num = u16::from_be_bytes(buf);
 
         // Don't polute the namespace, and let match arms below always read from
         // the matched pattern.
-        match {
+        let parts = {
             // Nibbles from `num` (from most significant to least).
-            let ins: u8 = ((num >> 16 / 4 * 3) & 0xF) as u8;
-            let x: u8 = ((num >> 16 / 4 * 2) & 0xF) as u8;
-            let y: u8 = ((num >> 16 / 4 * 1) & 0xF) as u8;
-            let n: u8 = ((num >> 16 / 4 * 0) & 0xF) as u8;
+            let ins: u8 = ((num >> 12) & 0xF) as u8;
+            let x: u8 = ((num >> 8) & 0xF) as u8;
+            let y: u8 = ((num >> 4) & 0xF) as u8;
+            let n: u8 = (num & 0xF) as u8;
 
             // Lower byte.
             let nn: u8 = (num & 0xFF) as u8;
@@ -102,7 +106,9 @@ impl Opcode {
             let nnn: u16 = num << 4 >> 4;
 
             (ins, x, y, n, nn, nnn)
-        } {
+        };
+
+        let opcode = match parts {
             (0x0, 0, 0xE, 0, _, _) => Opcode::Clear,
             (0x0, 0, 0xE, 0xE, _, _) => Opcode::Return,
             (0x0, _, _, _, _, nnn) => Opcode::Sys { nnn },
@@ -139,7 +145,129 @@ impl Opcode {
             (0xF, vx, 5, 5, _, _) => Opcode::StoreRegisters { vx },
             (0xF, vx, 6, 5, _, _) => Opcode::ReadRegisters { vx },
 
-            _ => unimplemented!("Cannot parse `{num:02X?}` into opcode"),
+            _ => return None,
+        };
+
+        Some(opcode)
+    }
+}
+
+impl Opcode {
+    /// The kind of instruction, as its mnemonic with placeholders for the
+    /// operands, e.g. `LD Vx, nn`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Opcode::Sys { .. } => "SYS nnn",
+            Opcode::Clear => "CLS",
+            Opcode::Return => "RET",
+            Opcode::Jump { .. } => "JP nnn",
+            Opcode::Call { .. } => "CALL nnn",
+            Opcode::SkipEqualsConstant { .. } => "SE Vx, nn",
+            Opcode::SkipNotEqualsConstant { .. } => "SNE Vx, nn",
+            Opcode::SkipEquals { .. } => "SE Vx, Vy",
+            Opcode::Load { .. } => "LD Vx, nn",
+            Opcode::AddConstant { .. } => "ADD Vx, nn",
+            Opcode::LoadRegister { .. } => "LD Vx, Vy",
+            Opcode::Or { .. } => "OR Vx, Vy",
+            Opcode::And { .. } => "AND Vx, Vy",
+            Opcode::Xor { .. } => "XOR Vx, Vy",
+            Opcode::Add { .. } => "ADD Vx, Vy",
+            Opcode::Sub { .. } => "SUB Vx, Vy",
+            Opcode::ShiftRight { .. } => "SHR Vx",
+            Opcode::Subn { .. } => "SUBN Vx, Vy",
+            Opcode::ShiftLeft { .. } => "SHL Vx",
+            Opcode::SkipNotEquals { .. } => "SNE Vx, Vy",
+            Opcode::LoadIndex { .. } => "LD I, nnn",
+            Opcode::JumpPlusV0 { .. } => "JP V0, nnn",
+            Opcode::Random { .. } => "RND Vx, nn",
+            Opcode::Draw { .. } => "DRW Vx, Vy, n",
+            Opcode::KeyPressSkip { .. } => "SKP Vx",
+            Opcode::KeyNotPressSkip { .. } => "SKNP Vx",
+            Opcode::DelayTimerLoadFrom { .. } => "LD Vx, DT",
+            Opcode::KeyLoad { .. } => "LD Vx, K",
+            Opcode::DelayTimerLoadInto { .. } => "LD DT, Vx",
+            Opcode::SoundLoad { .. } => "LD ST, Vx",
+            Opcode::AddIndex { .. } => "ADD I, Vx",
+            Opcode::LocateSprite { .. } => "LD F, Vx",
+            Opcode::LoadBcd { .. } => "LD B, Vx",
+            Opcode::StoreRegisters { .. } => "LD [I], Vx",
+            Opcode::ReadRegisters { .. } => "LD Vx, [I]",
+        }
+    }
+}
+
+impl fmt::Display for Opcode {
+    /// Formats the instruction with the mnemonics of Cowgod's Chip-8
+    /// Technical Reference, e.g. `LD V1, 0x0A`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Opcode::Sys { nnn } => write!(f, "SYS {nnn:#05X}"),
+            Opcode::Clear => write!(f, "CLS"),
+            Opcode::Return => write!(f, "RET"),
+            Opcode::Jump { nnn } => write!(f, "JP {nnn:#05X}"),
+            Opcode::Call { nnn } => write!(f, "CALL {nnn:#05X}"),
+            Opcode::SkipEqualsConstant { vx, nn } => write!(f, "SE V{vx:X}, {nn:#04X}"),
+            Opcode::SkipNotEqualsConstant { vx, nn } => write!(f, "SNE V{vx:X}, {nn:#04X}"),
+            Opcode::SkipEquals { vx, vy } => write!(f, "SE V{vx:X}, V{vy:X}"),
+            Opcode::Load { vx, nn } => write!(f, "LD V{vx:X}, {nn:#04X}"),
+            Opcode::AddConstant { vx, nn } => write!(f, "ADD V{vx:X}, {nn:#04X}"),
+            Opcode::LoadRegister { vx, vy } => write!(f, "LD V{vx:X}, V{vy:X}"),
+            Opcode::Or { vx, vy } => write!(f, "OR V{vx:X}, V{vy:X}"),
+            Opcode::And { vx, vy } => write!(f, "AND V{vx:X}, V{vy:X}"),
+            Opcode::Xor { vx, vy } => write!(f, "XOR V{vx:X}, V{vy:X}"),
+            Opcode::Add { vx, vy } => write!(f, "ADD V{vx:X}, V{vy:X}"),
+            Opcode::Sub { vx, vy } => write!(f, "SUB V{vx:X}, V{vy:X}"),
+            Opcode::ShiftRight { vx } => write!(f, "SHR V{vx:X}"),
+            Opcode::Subn { vx, vy } => write!(f, "SUBN V{vx:X}, V{vy:X}"),
+            Opcode::ShiftLeft { vx } => write!(f, "SHL V{vx:X}"),
+            Opcode::SkipNotEquals { vx, vy } => write!(f, "SNE V{vx:X}, V{vy:X}"),
+            Opcode::LoadIndex { nnn } => write!(f, "LD I, {nnn:#05X}"),
+            Opcode::JumpPlusV0 { nnn } => write!(f, "JP V0, {nnn:#05X}"),
+            Opcode::Random { vx, nn } => write!(f, "RND V{vx:X}, {nn:#04X}"),
+            Opcode::Draw { vx, vy, n } => write!(f, "DRW V{vx:X}, V{vy:X}, {n}"),
+            Opcode::KeyPressSkip { vx } => write!(f, "SKP V{vx:X}"),
+            Opcode::KeyNotPressSkip { vx } => write!(f, "SKNP V{vx:X}"),
+            Opcode::DelayTimerLoadFrom { vx } => write!(f, "LD V{vx:X}, DT"),
+            Opcode::KeyLoad { vx } => write!(f, "LD V{vx:X}, K"),
+            Opcode::DelayTimerLoadInto { vx } => write!(f, "LD DT, V{vx:X}"),
+            Opcode::SoundLoad { vx } => write!(f, "LD ST, V{vx:X}"),
+            Opcode::AddIndex { vx } => write!(f, "ADD I, V{vx:X}"),
+            Opcode::LocateSprite { vx } => write!(f, "LD F, V{vx:X}"),
+            Opcode::LoadBcd { vx } => write!(f, "LD B, V{vx:X}"),
+            Opcode::StoreRegisters { vx } => write!(f, "LD [I], V{vx:X}"),
+            Opcode::ReadRegisters { vx } => write!(f, "LD V{vx:X}, [I]"),
         }
     }
 }
+
+/// Lists the instructions of `rom` when loaded at `offset`, a line each with
+/// the address, the bytes and the instruction.
+///
+/// Every pair of bytes is decoded, so data shows up as instructions too, and
+/// pairs which aren't instructions are shown as `DW`.
+pub fn disassemble(rom: &[u8], offset: usize) -> Vec<String> {
+    rom.chunks(2)
+        .enumerate()
+        .map(|(i, bytes)| {
+            let address = offset + i * 2;
+            match *bytes {
+                [high, low] => disassemble_word(address, [high, low]),
+                [byte] => format!("{address:03X}: {byte:02X}    DB {byte:#04X}"),
+                _ => unreachable!(),
+            }
+        })
+        .collect()
+}
+
+/// The line [disassemble] shows for the instruction `word` at `address`.
+pub fn disassemble_word(address: usize, word: [u8; 2]) -> String {
+    let instruction = match Opcode::decode(word) {
+        Some(opcode) => opcode.to_string(),
+        None => format!("DW {:#06X}", u16::from_be_bytes(word)),
+    };
+
+    format!(
+        "{address:03X}: {:02X}{:02X}  {instruction}",
+        word[0], word[1]
+    )
+}
@@ -0,0 +1,335 @@
+//! How fast the [crate::Emulator] runs.
+
+use std::hint;
+use std::mem;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::constant::FPS;
+use crate::error::Error;
+
+/// Instructions run per frame by default, for about 600 a second, which most
+/// ROMs written for the COSMAC VIP expect.
+pub const INSTRUCTIONS_PER_FRAME: usize = 10;
+
+/// Speeds cycled through by [crate::window::Hotkey::SlowMotion], as fractions
+/// of normal speed.
+pub const SLOW_MOTION: [f64; 3] = [1., 0.5, 0.25];
+
+/// How the [crate::Emulator] is paced.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pacing {
+    /// Instructions run in each 60 Hz frame, between timer ticks.
+    pub instructions_per_frame: usize,
+    /// Speed while [crate::window::Hotkey::Turbo] is held.
+    pub turbo: Turbo,
+    /// Whether to run as many instructions each frame as fit in the COSMAC
+    /// VIP's machine cycles, given what each costs, instead of
+    /// `instructions_per_frame`.
+    pub vip_timing: bool,
+}
+
+impl Default for Pacing {
+    fn default() -> Self {
+        Self {
+            instructions_per_frame: INSTRUCTIONS_PER_FRAME,
+            turbo: Turbo::default(),
+            vip_timing: false,
+        }
+    }
+}
+
+/// Speed while [crate::window::Hotkey::Turbo] is held.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Turbo {
+    /// As fast as the host allows, still showing about 60 frames a second.
+    #[default]
+    Unthrottled,
+    /// This many frames are emulated for every frame shown.
+    Times(u32),
+}
+
+impl Turbo {
+    /// How many times faster than normal this is, which is infinite when
+    /// unthrottled.
+    pub fn speed(&self) -> f64 {
+        match self {
+            Turbo::Unthrottled => f64::INFINITY,
+            Turbo::Times(times) => *times as f64,
+        }
+    }
+}
+
+impl FromStr for Turbo {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "max" {
+            return Ok(Turbo::Unthrottled);
+        }
+
+        match s.strip_suffix('x').unwrap_or(s).parse::<u32>() {
+            Ok(times) if times >= 1 => Ok(Turbo::Times(times)),
+            _ => Err(Error::ErrorStr(format!(
+                "Invalid turbo '{s}', expected `max` or a whole number of times, e.g. `4x`"
+            ))),
+        }
+    }
+}
+
+/// How long each frame shown at `speed` times normal speed lasts.
+///
+/// Frames shorter than normal would be shown faster than the display
+/// refreshes, so speeding up runs more frames between the ones shown instead,
+/// see [frames_per_shown].
+pub(crate) fn frame_period(speed: f64) -> Duration {
+    Duration::from_secs_f64(FPS / speed.min(1.))
+}
+
+/// Frames run for each one shown at `speed` times normal speed, or `None` to
+/// run as many as fit before it's shown, when unthrottled.
+pub(crate) fn frames_per_shown(speed: f64) -> Option<u32> {
+    (!speed.is_infinite()).then(|| speed.max(1.) as u32)
+}
+
+/// How long before a deadline [FrameLimiter] stops sleeping and spins instead,
+/// as sleeps tend to overshoot by up to about this much.
+const SPIN_MARGIN: Duration = Duration::from_millis(1);
+
+/// Schedules frames against a monotonic clock.
+///
+/// Each deadline is a period after the previous one, rather than after the
+/// previous frame finished, so time spent emulating and rendering, and any
+/// oversleep, is taken out of the next wait instead of adding up.
+pub struct FrameLimiter {
+    /// When the current frame should end.
+    deadline: Instant,
+    /// When the previous frame ended.
+    last_frame: Instant,
+    times: FrameTimesBuilder,
+}
+
+impl FrameLimiter {
+    pub fn new() -> Self {
+        let now = Instant::now();
+
+        Self {
+            deadline: now,
+            last_frame: now,
+            times: FrameTimesBuilder::default(),
+        }
+    }
+
+    /// Starts a frame which should last `period`, returning when it should end.
+    pub fn start_frame(&mut self, period: Duration) -> Instant {
+        self.start_frame_at(Instant::now(), period)
+    }
+
+    /// [FrameLimiter::start_frame], at `now`.
+    fn start_frame_at(&mut self, now: Instant, period: Duration) -> Instant {
+        // After falling more than a frame behind, e.g. when the process was
+        // suspended, start again from now rather than rushing to catch up.
+        self.deadline = if now > self.deadline + period {
+            now + period
+        } else {
+            self.deadline + period
+        };
+
+        self.deadline
+    }
+
+    /// Waits until the end of the frame, unless `wait` is false because
+    /// something else paces frames, and records how long the frame took.
+    pub fn end_frame(&mut self, wait: bool) {
+        if wait {
+            let remaining = self.deadline.saturating_duration_since(Instant::now());
+            if remaining > SPIN_MARGIN {
+                thread::sleep(remaining - SPIN_MARGIN);
+            }
+            while Instant::now() < self.deadline {
+                hint::spin_loop();
+            }
+        }
+
+        let now = Instant::now();
+        self.times.add(now - self.last_frame);
+        self.last_frame = now;
+    }
+
+    /// Statistics of the frames since the last call.
+    pub fn take_frame_times(&mut self) -> FrameTimes {
+        mem::take(&mut self.times).build()
+    }
+}
+
+impl Default for FrameLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Statistics of measured frame times.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct FrameTimes {
+    pub mean: Duration,
+    pub min: Duration,
+    pub max: Duration,
+    /// Standard deviation, for how much frame times vary.
+    pub jitter: Duration,
+}
+
+#[derive(Default)]
+struct FrameTimesBuilder {
+    count: u32,
+    /// Sum of the frame times in seconds, and of their squares.
+    sum: f64,
+    sum_squares: f64,
+    min: Option<Duration>,
+    max: Duration,
+}
+
+impl FrameTimesBuilder {
+    fn add(&mut self, time: Duration) {
+        let seconds = time.as_secs_f64();
+
+        self.count += 1;
+        self.sum += seconds;
+        self.sum_squares += seconds * seconds;
+        self.min = Some(self.min.map_or(time, |min| min.min(time)));
+        self.max = self.max.max(time);
+    }
+
+    fn build(self) -> FrameTimes {
+        if self.count == 0 {
+            return FrameTimes::default();
+        }
+
+        let mean = self.sum / self.count as f64;
+        let variance = (self.sum_squares / self.count as f64 - mean * mean).max(0.);
+
+        FrameTimes {
+            mean: Duration::from_secs_f64(mean),
+            min: self.min.unwrap_or_default(),
+            max: self.max,
+            jitter: Duration::from_secs_f64(variance.sqrt()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_turbo() {
+        assert_eq!("max".parse::<Turbo>().unwrap(), Turbo::Unthrottled);
+        assert_eq!("4x".parse::<Turbo>().unwrap(), Turbo::Times(4));
+        assert_eq!("2".parse::<Turbo>().unwrap(), Turbo::Times(2));
+
+        for invalid in ["0x", "x", "fast", "-1"] {
+            assert!(invalid.parse::<Turbo>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn normal_speed_shows_every_frame() {
+        assert_eq!(frame_period(1.), Duration::from_secs_f64(FPS));
+        assert_eq!(frames_per_shown(1.), Some(1));
+    }
+
+    #[test]
+    fn slow_motion_shows_frames_for_longer() {
+        assert_eq!(frame_period(0.5), Duration::from_secs_f64(2. * FPS));
+        assert_eq!(frame_period(0.25), Duration::from_secs_f64(4. * FPS));
+        assert_eq!(frames_per_shown(0.25), Some(1));
+    }
+
+    #[test]
+    fn turbo_runs_more_frames_per_frame_shown() {
+        let speed = Turbo::Times(4).speed();
+        assert_eq!(frame_period(speed), Duration::from_secs_f64(FPS));
+        assert_eq!(frames_per_shown(speed), Some(4));
+
+        let speed = Turbo::Unthrottled.speed();
+        assert_eq!(frame_period(speed), Duration::from_secs_f64(FPS));
+        assert_eq!(frames_per_shown(speed), None);
+    }
+
+    const PERIOD: Duration = Duration::from_millis(16);
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn deadlines_follow_each_other() {
+        let mut limiter = FrameLimiter::new();
+        let start = limiter.deadline;
+
+        assert_eq!(
+            limiter.start_frame_at(start + ms(5), PERIOD),
+            start + ms(16)
+        );
+        // A frame which ran late is made up for by the next, as long as it's
+        // less than a frame behind.
+        assert_eq!(
+            limiter.start_frame_at(start + ms(20), PERIOD),
+            start + ms(32)
+        );
+        assert_eq!(
+            limiter.start_frame_at(start + ms(32), PERIOD),
+            start + ms(48)
+        );
+    }
+
+    #[test]
+    fn deadlines_start_again_after_a_stall() {
+        let mut limiter = FrameLimiter::new();
+        let start = limiter.deadline;
+        limiter.start_frame_at(start, PERIOD);
+
+        assert_eq!(
+            limiter.start_frame_at(start + ms(100), PERIOD),
+            start + ms(116)
+        );
+        assert_eq!(
+            limiter.start_frame_at(start + ms(110), PERIOD),
+            start + ms(132)
+        );
+    }
+
+    #[test]
+    fn end_frame_waits_for_the_deadline() {
+        let mut limiter = FrameLimiter::new();
+        let deadline = limiter.start_frame(ms(5));
+        limiter.end_frame(true);
+
+        assert!(Instant::now() >= deadline);
+        assert!(limiter.take_frame_times().min >= ms(5));
+    }
+
+    #[test]
+    fn frame_times_summarise_the_frames() {
+        let mut times = FrameTimesBuilder::default();
+        for time in [10, 20, 30] {
+            times.add(ms(time));
+        }
+        let times = times.build();
+
+        assert_eq!(times.min, ms(10));
+        assert_eq!(times.max, ms(30));
+        assert!(times.mean.abs_diff(ms(20)) < Duration::from_micros(1));
+        // The standard deviation of 10, 20 and 30 is 10 * sqrt(2/3).
+        assert!(times.jitter.abs_diff(Duration::from_micros(8165)) < Duration::from_micros(1));
+    }
+
+    #[test]
+    fn no_frames_have_no_times() {
+        assert_eq!(FrameTimesBuilder::default().build(), FrameTimes::default());
+        assert_eq!(
+            FrameLimiter::new().take_frame_times(),
+            FrameTimes::default()
+        );
+    }
+}
@@ -0,0 +1,135 @@
+use std::str::FromStr;
+
+use clap::ValueEnum;
+
+use crate::error::Error;
+
+/// Colours used to draw pixels, each as `0x00RRGGBB`.
+///
+/// Colours are indexed by which planes a pixel is set on, so index 0 is the
+/// background and index 1 the foreground. Indexes 2 and 3 are for pixels set
+/// on only the second plane or on both planes, in multi-plane modes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette([u32; 4]);
+
+impl Palette {
+    pub const fn new(colors: [u32; 4]) -> Self {
+        Self(colors)
+    }
+
+    pub fn background(&self) -> u32 {
+        self.0[0]
+    }
+
+    pub fn foreground(&self) -> u32 {
+        self.0[1]
+    }
+
+    /// Colour of a pixel which is set on the planes in the bitmask `planes`.
+    pub fn color(&self, planes: usize) -> u32 {
+        self.0[planes & 0b11]
+    }
+
+    /// Colour between the background and foreground, where `level` 0 is the
+    /// background and [u8::MAX] the foreground.
+    pub fn shade(&self, level: u8) -> u32 {
+        let mix = |background: u8, foreground: u8| {
+            let level = level as u32;
+            ((background as u32 * (255 - level) + foreground as u32 * level) / 255) as u8
+        };
+
+        let [_, br, bg, bb] = self.background().to_be_bytes();
+        let [_, fr, fg, fb] = self.foreground().to_be_bytes();
+
+        u32::from_be_bytes([0, mix(br, fr), mix(bg, fg), mix(bb, fb)])
+    }
+
+    pub fn with_background(mut self, color: u32) -> Self {
+        self.0[0] = color;
+        self
+    }
+
+    pub fn with_foreground(mut self, color: u32) -> Self {
+        self.0[1] = color;
+        self
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Theme::default().palette()
+    }
+}
+
+/// Built-in palettes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Theme {
+    /// White on black.
+    #[default]
+    Mono,
+    /// Green phosphor, like an old monochrome monitor.
+    Green,
+    /// Amber phosphor.
+    Amber,
+    /// Dark pixels on a greenish LCD.
+    Lcd,
+    /// Yellow on black.
+    HighContrast,
+}
+
+impl Theme {
+    pub const ALL: [Theme; 5] = [
+        Theme::Mono,
+        Theme::Green,
+        Theme::Amber,
+        Theme::Lcd,
+        Theme::HighContrast,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Theme::Mono => "mono",
+            Theme::Green => "green",
+            Theme::Amber => "amber",
+            Theme::Lcd => "lcd",
+            Theme::HighContrast => "high-contrast",
+        }
+    }
+
+    pub fn palette(&self) -> Palette {
+        match self {
+            Theme::Mono => Palette::new([0x000000, 0xFFFFFF, 0xAAAAAA, 0x555555]),
+            Theme::Green => Palette::new([0x001A00, 0x33FF66, 0x1A8033, 0x99FFB3]),
+            Theme::Amber => Palette::new([0x1A0F00, 0xFFB000, 0x805800, 0xFFD780]),
+            Theme::Lcd => Palette::new([0x9BBC0F, 0x0F380F, 0x306230, 0x8BAC0F]),
+            Theme::HighContrast => Palette::new([0x000000, 0xFFFF00, 0x00FFFF, 0xFFFFFF]),
+        }
+    }
+}
+
+impl FromStr for Theme {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Theme::ALL
+            .into_iter()
+            .find(|theme| theme.name() == s)
+            .ok_or_else(|| {
+                let names = Theme::ALL.map(|theme| theme.name()).join(", ");
+                Error::ErrorStr(format!("Unknown palette '{s}', expected one of: {names}"))
+            })
+    }
+}
+
+/// Parses a colour written as `RRGGBB` or `#RRGGBB`.
+pub fn parse_color(s: &str) -> Result<u32, Error> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(Error::ErrorStr(format!(
+            "Invalid colour '{s}', expected a hex colour like #33FF66"
+        )));
+    }
+
+    Ok(u32::from_str_radix(hex, 16).unwrap())
+}
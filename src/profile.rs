@@ -0,0 +1,440 @@
+//! Counts where the [crate::Emulator] spends its time, to find what's worth
+//! optimising in a ROM.
+//!
+//! Time is counted in instructions run rather than measured, so a profile is
+//! the same on every run and every host.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use serde::Serialize;
+
+use crate::opcode::{disassemble_word, Opcode};
+
+pub struct Profile {
+    /// Where the ROM starts, which counts as the subroutine running before
+    /// any are called.
+    entry: u16,
+    instructions: u64,
+    /// Times the instruction at each address was run, with the last
+    /// instruction seen there.
+    addresses: BTreeMap<u16, (u64, [u8; 2])>,
+    kinds: HashMap<&'static str, u64>,
+    subroutines: BTreeMap<u16, Subroutine>,
+    /// Calls from one subroutine to another, by caller and callee.
+    calls: BTreeMap<(u16, u16), Calls>,
+    /// Subroutines which haven't returned yet, innermost last, starting with
+    /// the entry.
+    stack: Vec<Frame>,
+}
+
+/// A subroutine which hasn't returned yet.
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    subroutine: u16,
+    /// The subroutine which called it, kept here as the frame below may have
+    /// been dropped, see [Profile::call].
+    caller: u16,
+    /// Instructions run before it was called.
+    called_at: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Subroutine {
+    calls: u64,
+    /// Instructions run in the subroutine and those it calls.
+    inclusive: u64,
+    /// Instructions run in the subroutine itself.
+    exclusive: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Calls {
+    count: u64,
+    /// Instructions run in the callee and those it calls, for these calls.
+    inclusive: u64,
+}
+
+impl Profile {
+    /// Starts a profile of a ROM which starts running at `entry`.
+    pub fn new(entry: u16) -> Self {
+        Self {
+            entry,
+            instructions: 0,
+            addresses: BTreeMap::new(),
+            kinds: HashMap::new(),
+            subroutines: BTreeMap::from([(entry, Subroutine::default())]),
+            calls: BTreeMap::new(),
+            stack: vec![Frame {
+                subroutine: entry,
+                caller: entry,
+                called_at: 0,
+            }],
+        }
+    }
+
+    /// Counts the instruction `word` at `pc`, which is about to run.
+    pub(crate) fn record(&mut self, pc: u16, word: [u8; 2], opcode: &Opcode) {
+        self.instructions += 1;
+
+        let address = self.addresses.entry(pc).or_insert((0, word));
+        *address = (address.0 + 1, word);
+        *self.kinds.entry(opcode.kind()).or_default() += 1;
+
+        let current = self.stack.last().unwrap().subroutine;
+        self.subroutines.get_mut(&current).unwrap().exclusive += 1;
+    }
+
+    /// Notes that the subroutine at `target` was just called, leaving `depth`
+    /// return addresses on the emulator's stack.
+    ///
+    /// When the emulator's stack wraps around, the oldest calls can no longer
+    /// return, so they are counted as having returned now and dropped, to
+    /// keep to the same depth.
+    pub(crate) fn call(&mut self, target: u16, depth: usize) {
+        let caller = self.stack.last().unwrap().subroutine;
+
+        self.subroutines.entry(target).or_default().calls += 1;
+        self.calls.entry((caller, target)).or_default().count += 1;
+        self.stack.push(Frame {
+            subroutine: target,
+            caller,
+            called_at: self.instructions,
+        });
+
+        while self.stack.len() > depth + 1 {
+            let frame = self.stack.remove(1);
+            self.finish(frame);
+        }
+    }
+
+    /// Notes that the innermost subroutine just returned.
+    pub(crate) fn ret(&mut self) {
+        // The entry never returns, so this is a return without a call.
+        if self.stack.len() > 1 {
+            let frame = self.stack.pop().unwrap();
+            self.finish(frame);
+        }
+    }
+
+    /// Counts the instructions run since `frame` was called, which is no
+    /// longer on the stack.
+    fn finish(&mut self, frame: Frame) {
+        Self::add_inclusive(
+            &mut self.subroutines,
+            &mut self.calls,
+            &self.stack,
+            frame,
+            self.instructions,
+        );
+    }
+
+    /// Adds the instructions run in the call of `frame`, up to `now`, to the
+    /// subroutine and the call, unless the subroutine is still running
+    /// further out, in which case they are counted when that returns.
+    fn add_inclusive(
+        subroutines: &mut BTreeMap<u16, Subroutine>,
+        calls: &mut BTreeMap<(u16, u16), Calls>,
+        stack: &[Frame],
+        frame: Frame,
+        now: u64,
+    ) {
+        if stack
+            .iter()
+            .any(|outer| outer.subroutine == frame.subroutine)
+        {
+            return;
+        }
+
+        let instructions = now - frame.called_at;
+        subroutines.get_mut(&frame.subroutine).unwrap().inclusive += instructions;
+        calls
+            .get_mut(&(frame.caller, frame.subroutine))
+            .unwrap()
+            .inclusive += instructions;
+    }
+
+    /// The subroutines and calls, with the ones still running counted as if
+    /// they had just returned.
+    fn totals(&self) -> (BTreeMap<u16, Subroutine>, BTreeMap<(u16, u16), Calls>) {
+        let mut subroutines = self.subroutines.clone();
+        let mut calls = self.calls.clone();
+        let mut stack = self.stack.clone();
+
+        while let Some(frame) = stack.pop() {
+            if stack.is_empty() {
+                subroutines.get_mut(&frame.subroutine).unwrap().inclusive =
+                    self.instructions - frame.called_at;
+            } else {
+                Self::add_inclusive(
+                    &mut subroutines,
+                    &mut calls,
+                    &stack,
+                    frame,
+                    self.instructions,
+                );
+            }
+        }
+
+        (subroutines, calls)
+    }
+
+    /// A report for people, with the `top` most run addresses annotated with
+    /// their instructions, the opcode kinds run, and the subroutines and calls
+    /// between them.
+    pub fn report(&self, top: usize) -> String {
+        let percent = |count: u64| 100. * count as f64 / self.instructions.max(1) as f64;
+        let mut report = format!("{} instructions run\n", self.instructions);
+
+        let mut addresses = self.addresses.iter().collect::<Vec<_>>();
+        addresses.sort_by_key(|(_, (count, _))| std::cmp::Reverse(*count));
+        report += "\nHottest addresses:\n      count       %  instruction\n";
+        for (&pc, &(count, word)) in addresses.into_iter().take(top) {
+            let instruction = disassemble_word(pc as usize, word);
+            let _ = writeln!(
+                report,
+                "{count:>11} {:>6.2}%  {instruction}",
+                percent(count)
+            );
+        }
+
+        let mut kinds = self.kinds.iter().collect::<Vec<_>>();
+        kinds.sort_by_key(|(kind, count)| (std::cmp::Reverse(**count), **kind));
+        report += "\nOpcode kinds:\n      count       %  kind\n";
+        for (kind, &count) in kinds {
+            let _ = writeln!(report, "{count:>11} {:>6.2}%  {kind}", percent(count));
+        }
+
+        let (subroutines, calls) = self.totals();
+        let mut subroutines = subroutines.into_iter().collect::<Vec<_>>();
+        subroutines.sort_by_key(|(_, subroutine)| std::cmp::Reverse(subroutine.inclusive));
+        report += "\nSubroutines:\n      calls   inclusive       %   exclusive       %  address\n";
+        for (address, subroutine) in subroutines {
+            let entry = if address == self.entry {
+                " (entry)"
+            } else {
+                ""
+            };
+            let _ = writeln!(
+                report,
+                "{:>11} {:>11} {:>6.2}% {:>11} {:>6.2}%  {address:03X}{entry}",
+                subroutine.calls,
+                subroutine.inclusive,
+                percent(subroutine.inclusive),
+                subroutine.exclusive,
+                percent(subroutine.exclusive),
+            );
+        }
+
+        report += "\nCall graph:\n      calls   inclusive       %  caller -> callee\n";
+        for ((caller, callee), calls) in calls {
+            let _ = writeln!(
+                report,
+                "{:>11} {:>11} {:>6.2}%  {caller:03X} -> {callee:03X}",
+                calls.count,
+                calls.inclusive,
+                percent(calls.inclusive),
+            );
+        }
+
+        report
+    }
+
+    /// The whole profile as JSON, for other tools.
+    pub fn to_json(&self) -> String {
+        #[derive(Serialize)]
+        struct Json<'a> {
+            entry: u16,
+            instructions: u64,
+            addresses: Vec<AddressJson>,
+            kinds: BTreeMap<&'a str, u64>,
+            subroutines: Vec<SubroutineJson>,
+            calls: Vec<CallsJson>,
+        }
+        #[derive(Serialize)]
+        struct AddressJson {
+            address: u16,
+            count: u64,
+            instruction: String,
+        }
+        #[derive(Serialize)]
+        struct SubroutineJson {
+            address: u16,
+            calls: u64,
+            inclusive: u64,
+            exclusive: u64,
+        }
+        #[derive(Serialize)]
+        struct CallsJson {
+            caller: u16,
+            callee: u16,
+            count: u64,
+            inclusive: u64,
+        }
+
+        let (subroutines, calls) = self.totals();
+        let json = Json {
+            entry: self.entry,
+            instructions: self.instructions,
+            addresses: self
+                .addresses
+                .iter()
+                .map(|(&address, &(count, word))| AddressJson {
+                    address,
+                    count,
+                    instruction: Opcode::decode(word)
+                        .map_or(format!("DW {:#06X}", u16::from_be_bytes(word)), |opcode| {
+                            opcode.to_string()
+                        }),
+                })
+                .collect(),
+            kinds: self
+                .kinds
+                .iter()
+                .map(|(&kind, &count)| (kind, count))
+                .collect(),
+            subroutines: subroutines
+                .into_iter()
+                .map(|(address, subroutine)| SubroutineJson {
+                    address,
+                    calls: subroutine.calls,
+                    inclusive: subroutine.inclusive,
+                    exclusive: subroutine.exclusive,
+                })
+                .collect(),
+            calls: calls
+                .into_iter()
+                .map(|((caller, callee), calls)| CallsJson {
+                    caller,
+                    callee,
+                    count: calls.count,
+                    inclusive: calls.inclusive,
+                })
+                .collect(),
+        };
+
+        serde_json::to_string_pretty(&json).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::Emulator;
+    use crate::pacing::Pacing;
+    use crate::quirks::Quirks;
+    use crate::window::headless::HeadlessWindow;
+
+    /// Counts an instruction at `pc`, which isn't a call or return.
+    fn run(profile: &mut Profile, pc: u16) {
+        profile.record(pc, [0x60, 0x00], &Opcode::Load { vx: 0, nn: 0 });
+    }
+
+    fn subroutine(profile: &Profile, address: u16) -> (u64, u64, u64) {
+        let subroutine = profile.totals().0[&address];
+        (subroutine.calls, subroutine.inclusive, subroutine.exclusive)
+    }
+
+    fn calls(profile: &Profile, caller: u16, callee: u16) -> (u64, u64) {
+        let calls = profile.totals().1[&(caller, callee)];
+        (calls.count, calls.inclusive)
+    }
+
+    #[test]
+    fn calls_are_counted_in_their_callers() {
+        let mut emulator = Emulator::new(
+            Box::new(HeadlessWindow::new(1)),
+            Quirks::default(),
+            Pacing {
+                instructions_per_frame: 10,
+                ..Pacing::default()
+            },
+        );
+        // 200: CALL 206; CALL 206; JP 204
+        // 206: LD V0, 0x01; CALL 20C; RET
+        // 20C: RET
+        let rom = [
+            0x22, 0x06, 0x22, 0x06, 0x12, 0x04, 0x60, 0x01, 0x22, 0x0C, 0x00, 0xEE, 0x00, 0xEE,
+        ];
+        emulator.load_rom("test", rom.to_vec()).unwrap();
+        emulator.set_profile(Profile::new(0x200));
+        emulator.start();
+        let profile = emulator.take_profile().unwrap();
+
+        assert_eq!(profile.instructions, 10);
+        assert_eq!(subroutine(&profile, 0x200), (0, 10, 2));
+        assert_eq!(subroutine(&profile, 0x206), (2, 8, 6));
+        assert_eq!(subroutine(&profile, 0x20C), (2, 2, 2));
+        assert_eq!(calls(&profile, 0x200, 0x206), (2, 8));
+        assert_eq!(calls(&profile, 0x206, 0x20C), (2, 2));
+        assert_eq!(profile.addresses[&0x206], (2, [0x60, 0x01]));
+        assert_eq!(profile.kinds["CALL nnn"], 4);
+    }
+
+    #[test]
+    fn recursion_is_only_counted_once() {
+        let mut profile = Profile::new(0x200);
+        run(&mut profile, 0x200);
+        profile.call(0x300, 1);
+        run(&mut profile, 0x300);
+        profile.call(0x300, 2);
+        run(&mut profile, 0x300);
+        profile.ret();
+        profile.ret();
+
+        assert_eq!(subroutine(&profile, 0x300), (2, 2, 2));
+        assert_eq!(calls(&profile, 0x200, 0x300), (1, 2));
+        assert_eq!(calls(&profile, 0x300, 0x300), (1, 0));
+    }
+
+    #[test]
+    fn calls_still_running_are_counted_up_to_now() {
+        let mut profile = Profile::new(0x200);
+        run(&mut profile, 0x200);
+        profile.call(0x300, 1);
+        run(&mut profile, 0x300);
+        run(&mut profile, 0x302);
+
+        assert_eq!(subroutine(&profile, 0x200), (0, 3, 1));
+        assert_eq!(subroutine(&profile, 0x300), (1, 2, 2));
+    }
+
+    #[test]
+    fn the_stack_keeps_to_the_emulators_depth() {
+        let mut profile = Profile::new(0x200);
+        run(&mut profile, 0x200);
+        profile.call(0x300, 1);
+        run(&mut profile, 0x300);
+        profile.call(0x400, 2);
+        run(&mut profile, 0x400);
+        // The emulator's 2-level stack wraps, forgetting the call of 300.
+        profile.call(0x500, 2);
+        run(&mut profile, 0x500);
+
+        assert_eq!(profile.stack.len(), 3);
+        assert_eq!(subroutine(&profile, 0x300), (1, 2, 1));
+        assert_eq!(calls(&profile, 0x200, 0x300), (1, 2));
+
+        // Returning from 500 then 400 goes back to 300's caller, counting 400
+        // as called by 300, where it was called from.
+        profile.ret();
+        profile.ret();
+        run(&mut profile, 0x302);
+
+        assert_eq!(profile.stack.len(), 1);
+        assert_eq!(calls(&profile, 0x300, 0x400), (1, 2));
+        assert_eq!(calls(&profile, 0x400, 0x500), (1, 1));
+        assert_eq!(subroutine(&profile, 0x200), (0, 5, 2));
+    }
+
+    #[test]
+    fn returns_without_a_call_are_ignored() {
+        let mut profile = Profile::new(0x200);
+        run(&mut profile, 0x200);
+        profile.ret();
+        run(&mut profile, 0x202);
+
+        assert_eq!(profile.stack.len(), 1);
+        assert_eq!(subroutine(&profile, 0x200), (0, 2, 2));
+    }
+}
@@ -0,0 +1,285 @@
+use std::fmt;
+use std::str::FromStr;
+
+use clap::ValueEnum;
+
+use crate::constant::{STACK_END, STACK_OFFSET};
+use crate::error::Error;
+
+/// Behaviours which differ between CHIP-8 interpreters.
+///
+/// The defaults follow the original COSMAC VIP interpreter where the
+/// interpreters disagree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// Sprites which extend past the right or bottom edge of the screen wrap
+    /// around to the opposite edge, instead of being clipped.
+    ///
+    /// The starting coordinate of a sprite always wraps.
+    pub wrap_sprites: bool,
+
+    /// `Draw` sets VF to the number of sprite rows which collided with a pixel
+    /// that was already on, instead of just 0 or 1.
+    ///
+    /// This is what SCHIP does in hi-res mode.
+    pub count_collided_rows: bool,
+
+    /// How many return addresses the stack holds.
+    pub stack_depth: usize,
+
+    /// What happens when a subroutine is called with the stack full.
+    pub stack_overflow: StackOverflow,
+
+    /// The stack is kept in memory just below [crate::Platform::memory_end]
+    /// of the COSMAC VIP, where programs can see it, instead of apart from
+    /// memory.
+    pub stack_in_memory: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self {
+            wrap_sprites: false,
+            count_collided_rows: false,
+            stack_depth: 12,
+            stack_overflow: StackOverflow::Wrap,
+            stack_in_memory: false,
+        }
+    }
+}
+
+impl Quirks {
+    /// Checks that the stack has room for a return address, and fits where
+    /// it's kept.
+    pub fn check(&self) -> Result<(), Error> {
+        let room = (STACK_END - STACK_OFFSET) / 2;
+
+        if self.stack_depth == 0 {
+            return Err(Error::ErrorStr(
+                "The stack needs room for at least 1 return address".to_string(),
+            ));
+        }
+        if self.stack_in_memory && self.stack_depth > room {
+            return Err(Error::ErrorStr(format!(
+                "A stack of {} return addresses doesn't fit in memory at {STACK_OFFSET:#05X}-{:#05X}, which has room for {room}",
+                self.stack_depth,
+                STACK_END - 1
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// What happens when a subroutine is called with the stack full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum StackOverflow {
+    /// The emulator stops at the call, as the program can't go on correctly.
+    Halt,
+    /// The oldest return address is overwritten and the program goes on, as
+    /// it does on interpreters which don't check for room, like the COSMAC
+    /// VIP's.
+    Wrap,
+}
+
+impl fmt::Display for Quirks {
+    /// Lists the quirks which are enabled, and the size of the stack.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut quirks = [
+            (self.wrap_sprites, "wrap-sprites"),
+            (self.count_collided_rows, "count-collided-rows"),
+            (self.stack_in_memory, "stack-in-memory"),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, name)| enabled.then_some(name.to_string()))
+        .collect::<Vec<_>>();
+
+        quirks.push(match self.stack_overflow {
+            StackOverflow::Halt => format!("{}-level stack", self.stack_depth),
+            StackOverflow::Wrap => format!("{}-level wrapping stack", self.stack_depth),
+        });
+
+        write!(f, "{}", quirks.join(", "))
+    }
+}
+
+/// Families of CHIP-8 interpreters, which each come with their own [Quirks].
+///
+/// Only the CHIP-8 instructions are emulated, so this just picks the quirks
+/// that programs written for each platform expect.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Platform {
+    /// The original interpreter for the COSMAC VIP.
+    #[default]
+    #[value(name = "chip-8")]
+    Chip8,
+    /// CHIP-48, for the HP 48 calculators.
+    #[value(name = "chip-48")]
+    Chip48,
+    /// SUPER-CHIP 1.1, for the HP 48 calculators.
+    Superchip,
+    /// XO-CHIP, from Octo.
+    XoChip,
+}
+
+impl Platform {
+    pub const ALL: [Platform; 4] = [
+        Platform::Chip8,
+        Platform::Chip48,
+        Platform::Superchip,
+        Platform::XoChip,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::Chip8 => "chip-8",
+            Platform::Chip48 => "chip-48",
+            Platform::Superchip => "superchip",
+            Platform::XoChip => "xo-chip",
+        }
+    }
+
+    /// End of the memory which programs can use, as the interpreter of the
+    /// COSMAC VIP reserves the top of memory for the stack, its variables and
+    /// the display.
+    pub fn memory_end(&self) -> usize {
+        match self {
+            Platform::Chip8 => 0xEA0,
+            Platform::Chip48 | Platform::Superchip => 0x1000,
+            Platform::XoChip => 0x10000,
+        }
+    }
+
+    /// Checks that `rom` fits in the memory of the platform when loaded at
+    /// `offset`.
+    pub fn check_rom_size(&self, rom: &[u8], offset: usize) -> Result<(), Error> {
+        let free = self.memory_end().saturating_sub(offset);
+
+        if rom.len() > free {
+            return Err(Error::ErrorStr(format!(
+                "ROM is {} bytes, but {} only has {free} bytes free after {offset:#05X}",
+                rom.len(),
+                self.name()
+            )));
+        }
+
+        Ok(())
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        match self {
+            Platform::Chip8 => Quirks {
+                stack_overflow: StackOverflow::Wrap,
+                ..Quirks::default()
+            },
+            Platform::Chip48 => Quirks {
+                stack_depth: 16,
+                stack_overflow: StackOverflow::Halt,
+                ..Quirks::default()
+            },
+            // SCHIP only counts collided rows in hi-res mode, which isn't
+            // emulated, so `--count-collided-rows` has to ask for it.
+            Platform::Superchip => Quirks {
+                stack_depth: 16,
+                stack_overflow: StackOverflow::Halt,
+                ..Quirks::default()
+            },
+            // Octo stops with an error when the stack overflows.
+            Platform::XoChip => Quirks {
+                wrap_sprites: true,
+                stack_depth: 16,
+                stack_overflow: StackOverflow::Halt,
+                ..Quirks::default()
+            },
+        }
+    }
+}
+
+impl FromStr for Platform {
+    type Err = Error;
+
+    /// Parses the names from [Platform::name], and the platform ids of the
+    /// community CHIP-8 database.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "chip-8" | "originalChip8" | "hybridVIP" | "modernChip8" => Ok(Platform::Chip8),
+            "chip-48" | "chip48" => Ok(Platform::Chip48),
+            "superchip" | "superchip1" => Ok(Platform::Superchip),
+            "xo-chip" | "xochip" => Ok(Platform::XoChip),
+            _ => Err(Error::ErrorStr(format!(
+                "Unknown platform '{s}', expected one of: {}",
+                Platform::ALL.map(|platform| platform.name()).join(", ")
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chip8_wraps_a_12_level_stack() {
+        let quirks = Platform::Chip8.quirks();
+
+        assert_eq!(quirks, Quirks::default());
+        assert_eq!(quirks.stack_depth, 12);
+        assert_eq!(quirks.stack_overflow, StackOverflow::Wrap);
+    }
+
+    #[test]
+    fn chip48_halts_on_overflow() {
+        let quirks = Platform::Chip48.quirks();
+
+        assert_eq!(quirks.stack_depth, 16);
+        assert_eq!(quirks.stack_overflow, StackOverflow::Halt);
+    }
+
+    #[test]
+    fn superchip_halts_on_overflow() {
+        let quirks = Platform::Superchip.quirks();
+
+        assert_eq!(quirks.stack_depth, 16);
+        assert_eq!(quirks.stack_overflow, StackOverflow::Halt);
+        assert!(!quirks.count_collided_rows);
+    }
+
+    #[test]
+    fn xo_chip_halts_on_overflow() {
+        let quirks = Platform::XoChip.quirks();
+
+        assert_eq!(quirks.stack_depth, 16);
+        assert_eq!(quirks.stack_overflow, StackOverflow::Halt);
+        assert!(quirks.wrap_sprites);
+    }
+
+    #[test]
+    fn every_platform_fits_its_stack() {
+        for platform in Platform::ALL {
+            assert!(platform.quirks().check().is_ok(), "{platform:?}");
+        }
+    }
+
+    #[test]
+    fn rom_size_is_limited_by_the_platform() {
+        let free = 0xEA0 - 0x200;
+        assert!(Platform::Chip8
+            .check_rom_size(&vec![0; free], 0x200)
+            .is_ok());
+        assert_eq!(
+            Platform::Chip8
+                .check_rom_size(&vec![0; free + 1], 0x200)
+                .unwrap_err()
+                .to_string(),
+            "ROM is 3233 bytes, but chip-8 only has 3232 bytes free after 0x200"
+        );
+
+        let free = 0x1000 - 0x200;
+        assert!(Platform::Superchip
+            .check_rom_size(&vec![0; free], 0x200)
+            .is_ok());
+        assert!(Platform::Superchip
+            .check_rom_size(&vec![0; free + 1], 0x200)
+            .is_err());
+    }
+}
@@ -0,0 +1,389 @@
+//! Where ROMs are loaded from, and how they are turned into bytes to run.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+
+use log::{debug, info};
+
+use crate::cartridge::{self, Cartridge};
+use crate::database::RomInfo;
+use crate::error::Error;
+use crate::octo;
+
+/// Extension of Octo source files, which are compiled when loaded.
+const OCTO_EXTENSION: &str = "8o";
+
+/// Largest file read as a ROM, which is far more than any program with its
+/// Octo source or cartridge needs, but stops e.g. `/dev/zero` being read
+/// until memory runs out.
+const MAX_FILE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Where a ROM is read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RomSource {
+    Stdin,
+    File(PathBuf),
+    /// A file inside a zip archive, with `entry` being its path there.
+    Zip {
+        archive: PathBuf,
+        entry: String,
+    },
+}
+
+/// A ROM ready to run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rom {
+    pub name: String,
+    pub bytes: Vec<u8>,
+    /// Settings from an Octo cartridge, or otherwise the ROM database.
+    pub info: Option<RomInfo>,
+}
+
+impl RomSource {
+    /// Parses a ROM argument, where `-` is stdin, and a path which goes
+    /// through a zip archive, like `games.zip/pong.ch8`, is a file inside it.
+    pub fn parse(s: &str) -> Self {
+        if s == "-" {
+            return RomSource::Stdin;
+        }
+
+        let path = Path::new(s);
+        let archive = path
+            .ancestors()
+            .skip(1)
+            .find(|ancestor| is_archive(ancestor));
+
+        match archive {
+            Some(archive) => RomSource::Zip {
+                archive: archive.to_path_buf(),
+                entry: path
+                    .strip_prefix(archive)
+                    .unwrap()
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/"),
+            },
+            None => RomSource::File(path.to_path_buf()),
+        }
+    }
+
+    /// File name of the ROM, without the directories or archive it is in.
+    pub fn name(&self) -> String {
+        match self {
+            RomSource::Stdin => "stdin".to_string(),
+            RomSource::File(path) => path.file_name().map_or(path.display().to_string(), |name| {
+                name.to_string_lossy().to_string()
+            }),
+            RomSource::Zip { entry, .. } => entry.rsplit('/').next().unwrap_or(entry).to_string(),
+        }
+    }
+
+    pub fn extension(&self) -> Option<String> {
+        let name = self.name();
+        let (stem, extension) = name.rsplit_once('.')?;
+        (!stem.is_empty()).then(|| extension.to_ascii_lowercase())
+    }
+
+    /// The file next to this one with the same name but `extension`, like
+    /// `maze.md` for `maze.ch8`.
+    pub fn with_extension(&self, extension: &str) -> Option<RomSource> {
+        match self {
+            RomSource::Stdin => None,
+            RomSource::File(path) => Some(RomSource::File(path.with_extension(extension))),
+            RomSource::Zip { archive, entry } => {
+                let stem = match entry.rsplit_once('.') {
+                    Some((stem, _)) if !stem.ends_with('/') => stem,
+                    _ => entry,
+                };
+                Some(RomSource::Zip {
+                    archive: archive.clone(),
+                    entry: format!("{stem}.{extension}"),
+                })
+            }
+        }
+    }
+
+    /// Reads the bytes of the file, as they are.
+    pub fn read(&self) -> Result<Vec<u8>, Error> {
+        match self {
+            RomSource::Stdin => read_all(io::stdin())
+                .map_err(|err| Error::ErrorStr(format!("Can't read ROM from stdin: {err}"))),
+            RomSource::File(path) => {
+                File::open(path)
+                    .and_then(read_all)
+                    .map_err(|err| match err.kind() {
+                        ErrorKind::NotFound => {
+                            Error::ErrorStr(format!("ROM '{}' not found", path.display()))
+                        }
+                        _ => Error::ErrorStr(format!("Can't read ROM '{}': {err}", path.display())),
+                    })
+            }
+            RomSource::Zip { archive, entry } => {
+                read_entry(&mut open_archive(archive)?, archive, entry)
+            }
+        }
+    }
+
+    /// Reads the ROM, decoding Octo cartridges and compiling Octo source.
+    pub fn load(&self) -> Result<Rom, Error> {
+        self.decode(self.read()?)
+    }
+
+    /// Turns the `bytes` read from this source into a ROM, decoding Octo
+    /// cartridges and compiling Octo source.
+    pub fn decode(&self, bytes: Vec<u8>) -> Result<Rom, Error> {
+        let name = self.name();
+
+        if bytes.starts_with(cartridge::MAGIC) {
+            let title = name
+                .rsplit_once('.')
+                .map_or(name.as_str(), |(stem, _)| stem);
+            let cartridge = Cartridge::decode(title, &bytes)?;
+            debug!("Decoded '{name}' as an Octo cartridge");
+
+            return Ok(Rom {
+                name,
+                bytes: cartridge.rom,
+                info: Some(cartridge.info),
+            });
+        }
+
+        let bytes = if self.extension().as_deref() == Some(OCTO_EXTENSION) {
+            let source = String::from_utf8(bytes)
+                .map_err(|_| Error::ErrorStr(format!("'{name}' isn't valid UTF-8")))?;
+            let bytes = octo::compile(&source)
+                .map_err(|err| Error::ErrorStr(format!("Can't compile '{name}': {err}")))?;
+            debug!("Compiled '{name}' from Octo source");
+            bytes
+        } else {
+            bytes
+        };
+
+        let info = RomInfo::lookup(&bytes);
+        if let Some(info) = &info {
+            info!("Found '{name}' in the ROM database as '{}'", info.title);
+        }
+
+        Ok(Rom { info, name, bytes })
+    }
+}
+
+impl fmt::Display for RomSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RomSource::Stdin => write!(f, "stdin"),
+            RomSource::File(path) => write!(f, "{}", path.display()),
+            RomSource::Zip { archive, entry } => write!(f, "{}/{entry}", archive.display()),
+        }
+    }
+}
+
+/// Whether `path` is a zip archive, judging by its extension.
+pub fn is_archive(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("zip"))
+        && path.is_file()
+}
+
+/// Lists the files in the zip `archive`, sorted by their path.
+pub fn archive_entries(archive: &Path) -> Result<Vec<RomSource>, Error> {
+    let zip = open_archive(archive)?;
+
+    let mut names = zip
+        .file_names()
+        .filter(|name| !name.ends_with('/'))
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    names.sort();
+
+    Ok(names
+        .into_iter()
+        .map(|entry| RomSource::Zip {
+            archive: archive.to_path_buf(),
+            entry,
+        })
+        .collect())
+}
+
+/// Reads [RomSource]s one after another, keeping the zip archive the last
+/// one was in open for the next, e.g. to list every ROM in it.
+#[derive(Default)]
+pub(crate) struct Reader {
+    archive: Option<(PathBuf, zip::ZipArchive<File>)>,
+}
+
+impl Reader {
+    pub fn read(&mut self, source: &RomSource) -> Result<Vec<u8>, Error> {
+        let RomSource::Zip { archive, entry } = source else {
+            return source.read();
+        };
+
+        let zip = match &mut self.archive {
+            Some((path, zip)) if path == archive => zip,
+            open => &mut open.insert((archive.clone(), open_archive(archive)?)).1,
+        };
+        read_entry(zip, archive, entry)
+    }
+}
+
+fn read_entry(
+    zip: &mut zip::ZipArchive<File>,
+    archive: &Path,
+    entry: &str,
+) -> Result<Vec<u8>, Error> {
+    let file = zip.by_name(entry).map_err(|_| {
+        Error::ErrorStr(format!(
+            "ROM '{entry}' not found in '{}'",
+            archive.display()
+        ))
+    })?;
+
+    read_all(file).map_err(|err| {
+        Error::ErrorStr(format!(
+            "Can't read ROM '{entry}' from '{}': {err}",
+            archive.display()
+        ))
+    })
+}
+
+/// Reads the rest of `reader`, unless it's more than [MAX_FILE_SIZE] bytes.
+fn read_all(reader: impl Read) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![];
+    reader.take(MAX_FILE_SIZE + 1).read_to_end(&mut bytes)?;
+
+    if bytes.len() as u64 > MAX_FILE_SIZE {
+        return Err(io::Error::other(format!(
+            "it's more than {} MiB",
+            MAX_FILE_SIZE / 1024 / 1024
+        )));
+    }
+
+    Ok(bytes)
+}
+
+fn open_archive(archive: &Path) -> Result<zip::ZipArchive<File>, Error> {
+    let read_error = |err: &dyn fmt::Display| {
+        Error::ErrorStr(format!("Can't read archive '{}': {err}", archive.display()))
+    };
+
+    let file = File::open(archive).map_err(|err| read_error(&err))?;
+    zip::ZipArchive::new(file).map_err(|err| read_error(&err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::menu::Menu;
+    use std::fs;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    /// Writes a zip archive named after the test holding `files`, where
+    /// directories end in `/`.
+    fn archive(name: &str, files: &[(&str, &[u8])]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("chip8-{}-{name}.zip", std::process::id()));
+        let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+        for (name, bytes) in files {
+            if name.ends_with('/') {
+                zip.add_directory(*name, SimpleFileOptions::default())
+                    .unwrap();
+            } else {
+                zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+                zip.write_all(bytes).unwrap();
+            }
+        }
+        zip.finish().unwrap();
+        path
+    }
+
+    fn entry(archive: &Path, entry: &str) -> RomSource {
+        RomSource::Zip {
+            archive: archive.to_path_buf(),
+            entry: entry.to_string(),
+        }
+    }
+
+    #[test]
+    fn archive_without_roms_is_an_error() {
+        let path = archive("empty", &[("readme.txt", b"Nothing here")]);
+        let menu = Menu::from_archive(&path);
+        fs::remove_file(&path).unwrap();
+
+        let err = menu.unwrap_err().to_string();
+        assert!(err.starts_with("No ROMs in"), "{err}");
+    }
+
+    #[test]
+    fn archive_with_one_rom() {
+        let path = archive(
+            "one",
+            &[
+                ("games/", b""),
+                ("games/maze.ch8", &[0x12, 0x00]),
+                ("readme.txt", b"Maze"),
+            ],
+        );
+        let menu = Menu::from_archive(&path).unwrap();
+        let source = RomSource::parse(&format!("{}/games/maze.ch8", path.display()));
+        let bytes = source.read();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(menu.entries().len(), 1);
+        assert_eq!(menu.selected().source, entry(&path, "games/maze.ch8"));
+        assert_eq!(source, entry(&path, "games/maze.ch8"));
+        assert_eq!(bytes.unwrap(), [0x12, 0x00]);
+    }
+
+    #[test]
+    fn archive_with_several_roms() {
+        let path = archive(
+            "several",
+            &[
+                ("pong.ch8", &[0x12, 0x02]),
+                ("maze.ch8", &[0x12, 0x00]),
+                ("maze.md", b"# Mazes\n\nDiagonal lines."),
+            ],
+        );
+        let entries = archive_entries(&path).unwrap();
+        let menu = Menu::from_archive(&path).unwrap();
+        let mut reader = Reader::default();
+        let pong = reader.read(&entry(&path, "pong.ch8"));
+        let missing = reader.read(&entry(&path, "tetris.ch8"));
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            entries,
+            [
+                entry(&path, "maze.ch8"),
+                entry(&path, "maze.md"),
+                entry(&path, "pong.ch8")
+            ]
+        );
+        assert_eq!(
+            menu.entries()
+                .iter()
+                .map(|entry| entry.title.as_str())
+                .collect::<Vec<_>>(),
+            ["Mazes", "pong.ch8"]
+        );
+        assert_eq!(pong.unwrap(), [0x12, 0x02]);
+        assert_eq!(
+            missing.unwrap_err().to_string(),
+            format!("ROM 'tetris.ch8' not found in '{}'", path.display())
+        );
+    }
+
+    #[test]
+    fn oversized_input_is_an_error() {
+        assert_eq!(
+            read_all(io::repeat(1).take(MAX_FILE_SIZE)).unwrap().len() as u64,
+            MAX_FILE_SIZE
+        );
+
+        let err = read_all(io::repeat(1)).unwrap_err();
+        assert_eq!(err.to_string(), "it's more than 16 MiB");
+    }
+}
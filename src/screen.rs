@@ -1,41 +1,382 @@
 use crate::constant::*;
 
-pub struct Screen([bool; WIDTH * HEIGHT]);
+/// Each row of pixels is packed into the bits of a `u64`, with the most
+/// significant bit being the leftmost pixel.
+///
+/// This lets a row of a sprite be drawn with a single shift and XOR, and
+/// collisions be detected with a single AND.
+type Row = u64;
+
+const _: () = assert!(WIDTH == Row::BITS as usize);
+const _: () = assert!(HEIGHT <= u64::BITS as usize);
+
+/// Bitmask of every row, in the layout used to track which rows changed.
+pub(crate) const ALL_ROWS: u64 = u64::MAX >> (u64::BITS as usize - HEIGHT);
+
+pub struct Screen {
+    rows: [Row; HEIGHT],
+
+    /// Number of times the screen has been presented.
+    frame: u64,
+
+    /// Bit `y` is set if row `y` changed since the last present.
+    dirty_rows: u64,
+    /// Pixels in columns with a set bit changed since the last present, using
+    /// the same layout as a [Row].
+    dirty_columns: Row,
+}
 
 impl Screen {
     pub fn new() -> Self {
-        Self([false; WIDTH * HEIGHT])
+        Self {
+            rows: [0; HEIGHT],
+            frame: 0,
+            // Nothing has been presented yet, so everything needs to be drawn.
+            dirty_rows: ALL_ROWS,
+            dirty_columns: Row::MAX,
+        }
     }
 
-    /// Sets the pixel value.
+    /// XORs a sprite onto the screen, with its top left corner at `x`, `y`.
+    ///
+    /// The starting coordinate wraps around the screen. Pixels past the right
+    /// or bottom edge are then clipped, unless `wrap` is set, in which case
+    /// they wrap around to the opposite edge.
     ///
-    /// Returns whether the pixel value at the current location was turned off.
-    pub fn set(&mut self, x: usize, y: usize, bit: bool) -> bool {
-        let x = x % WIDTH;
+    /// Returns the number of sprite rows where a pixel was turned off.
+    pub fn draw_sprite(&mut self, x: usize, y: usize, sprite: &[u8], wrap: bool) -> usize {
+        let x = (x % WIDTH) as u32;
         let y = y % HEIGHT;
 
-        let i = y * WIDTH + x;
+        let mut collided_rows = 0;
+
+        for (y_offset, byte) in sprite.iter().enumerate() {
+            let y = y + y_offset;
+            if y >= HEIGHT && !wrap {
+                break;
+            }
 
-        let prev = self.0[i];
+            // Align the byte to the left edge of the row, then move it to `x`.
+            // Shifting drops the bits past the right edge, while rotating
+            // brings them back in on the left.
+            let bits = (*byte as Row) << (Row::BITS - u8::BITS);
+            let bits = if wrap {
+                bits.rotate_right(x)
+            } else {
+                bits >> x
+            };
 
-        // a b result  turned_off
-        // 1 1 0       1
-        // 1 0 1       0
-        // 0 1 1       0
-        // 0 0 0       0
+            let y = y % HEIGHT;
+            let row = &mut self.rows[y];
 
-        self.0[i] = prev ^ bit;
+            // a b result  turned_off
+            // 1 1 0       1
+            // 1 0 1       0
+            // 0 1 1       0
+            // 0 0 0       0
+            collided_rows += (*row & bits != 0) as usize;
+            *row ^= bits;
+
+            if bits != 0 {
+                self.dirty_rows |= 1 << y;
+                self.dirty_columns |= bits;
+            }
+        }
 
-        prev & bit
+        collided_rows
     }
 
     pub fn clear(&mut self) {
-        for x in &mut self.0 {
-            *x = false;
+        for (y, row) in self.rows.iter_mut().enumerate() {
+            if *row != 0 {
+                self.dirty_rows |= 1 << y;
+                self.dirty_columns |= *row;
+            }
+            *row = 0;
         }
     }
 
-    pub fn framebuffer(&self) -> &[bool; WIDTH * HEIGHT] {
-        &self.0
+    pub fn framebuffer(&self) -> Framebuffer<'_> {
+        Framebuffer(&self.rows)
+    }
+
+    /// What changed since the last call to [Screen::present].
+    pub fn damage(&self) -> Damage {
+        Damage {
+            frame: self.frame,
+            rows: self.dirty_rows,
+            columns: self.dirty_columns,
+        }
+    }
+
+    /// Marks the current contents as shown, starting a new frame.
+    pub fn present(&mut self) {
+        self.frame += 1;
+        self.dirty_rows = 0;
+        self.dirty_columns = 0;
+    }
+}
+
+impl Default for Screen {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Read-only view of the pixels on a [Screen].
+#[derive(Clone, Copy)]
+pub struct Framebuffer<'a>(&'a [Row; HEIGHT]);
+
+impl Framebuffer<'_> {
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        self.0[y] >> (WIDTH - 1 - x) & 1 == 1
+    }
+
+    /// Pixels in row-major order, starting from the top left.
+    pub fn iter(&self) -> impl Iterator<Item = bool> + '_ {
+        (0..HEIGHT).flat_map(move |y| (0..WIDTH).map(move |x| self.pixel(x, y)))
+    }
+}
+
+/// The parts of a [Screen] which changed since it was last presented.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Damage {
+    /// Number of frames presented before this one.
+    pub frame: u64,
+    rows: u64,
+    columns: Row,
+}
+
+impl Damage {
+    /// Whether nothing changed, so presenting can be skipped entirely.
+    pub fn is_empty(&self) -> bool {
+        self.rows == 0
+    }
+
+    pub fn is_row_dirty(&self, y: usize) -> bool {
+        self.rows >> y & 1 == 1
+    }
+
+    /// Indexes of the rows which changed, from top to bottom.
+    pub fn rows(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..HEIGHT).filter(|y| self.is_row_dirty(*y))
+    }
+
+    /// Also marks every pixel in the rows of the bitmask `rows` as changed.
+    pub(crate) fn with_rows(self, rows: u64) -> Self {
+        Self {
+            rows: self.rows | rows,
+            columns: if rows == 0 { self.columns } else { Row::MAX },
+            ..self
+        }
+    }
+
+    /// Smallest rectangle containing every pixel which changed.
+    pub fn rect(&self) -> Option<Rect> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let x = self.columns.leading_zeros() as usize;
+        let y = self.rows.trailing_zeros() as usize;
+
+        Some(Rect {
+            x,
+            y,
+            width: WIDTH - self.columns.trailing_zeros() as usize - x,
+            height: u64::BITS as usize - self.rows.leading_zeros() as usize - y,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Coordinates of every pixel which is on.
+    fn lit(screen: &Screen) -> Vec<(usize, usize)> {
+        let framebuffer = screen.framebuffer();
+        (0..HEIGHT)
+            .flat_map(|y| (0..WIDTH).map(move |x| (x, y)))
+            .filter(|&(x, y)| framebuffer.pixel(x, y))
+            .collect()
+    }
+
+    #[test]
+    fn rows_are_packed_leftmost_pixel_first() {
+        let mut screen = Screen::new();
+        screen.draw_sprite(0, 0, &[0xF0], false);
+        screen.draw_sprite(60, 1, &[0xF0], false);
+
+        assert_eq!(screen.rows[0], 0xF000_0000_0000_0000);
+        assert_eq!(screen.rows[1], 0x0000_0000_0000_000F);
+        assert_eq!(
+            lit(&screen),
+            [
+                (0, 0),
+                (1, 0),
+                (2, 0),
+                (3, 0),
+                (60, 1),
+                (61, 1),
+                (62, 1),
+                (63, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn start_wraps_around() {
+        let mut screen = Screen::new();
+        screen.draw_sprite(WIDTH + 2, HEIGHT + 1, &[0x80], false);
+
+        assert_eq!(lit(&screen), [(2, 1)]);
+    }
+
+    #[test]
+    fn clips_at_right_edge() {
+        let mut screen = Screen::new();
+        screen.draw_sprite(62, 0, &[0xFF], false);
+
+        assert_eq!(lit(&screen), [(62, 0), (63, 0)]);
+    }
+
+    #[test]
+    fn wraps_at_right_edge() {
+        let mut screen = Screen::new();
+        screen.draw_sprite(62, 0, &[0xFF], true);
+
+        assert_eq!(
+            lit(&screen),
+            [
+                (0, 0),
+                (1, 0),
+                (2, 0),
+                (3, 0),
+                (4, 0),
+                (5, 0),
+                (62, 0),
+                (63, 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn clips_at_bottom_edge() {
+        let mut screen = Screen::new();
+        screen.draw_sprite(0, 30, &[0x80, 0x80, 0x80], false);
+
+        assert_eq!(lit(&screen), [(0, 30), (0, 31)]);
+    }
+
+    #[test]
+    fn wraps_at_bottom_edge() {
+        let mut screen = Screen::new();
+        screen.draw_sprite(0, 30, &[0x80, 0x80, 0x80], true);
+
+        assert_eq!(lit(&screen), [(0, 0), (0, 30), (0, 31)]);
+    }
+
+    #[test]
+    fn counts_collided_rows() {
+        let mut screen = Screen::new();
+        assert_eq!(screen.draw_sprite(0, 0, &[0x80, 0x00, 0x80], false), 0);
+
+        // Only the rows where a pixel was turned off count.
+        assert_eq!(screen.draw_sprite(0, 0, &[0xC0, 0xC0, 0xC0], false), 2);
+        assert_eq!(lit(&screen), [(1, 0), (0, 1), (1, 1), (1, 2)]);
+    }
+
+    #[test]
+    fn clipped_pixels_dont_collide() {
+        let mut screen = Screen::new();
+        screen.draw_sprite(0, 0, &[0x80], false);
+
+        assert_eq!(screen.draw_sprite(63, 0, &[0xFF], false), 0);
+        assert_eq!(screen.draw_sprite(63, 0, &[0xFF], true), 1);
+    }
+
+    #[test]
+    fn new_screen_is_all_damaged() {
+        let screen = Screen::new();
+
+        assert_eq!(
+            screen.damage().rect(),
+            Some(Rect {
+                x: 0,
+                y: 0,
+                width: WIDTH,
+                height: HEIGHT
+            })
+        );
+    }
+
+    #[test]
+    fn damage_covers_what_changed_since_present() {
+        let mut screen = Screen::new();
+        screen.present();
+        assert!(screen.damage().is_empty());
+        assert_eq!(screen.damage().rect(), None);
+
+        screen.draw_sprite(10, 5, &[0x80, 0xC0], false);
+        let damage = screen.damage();
+
+        assert_eq!(damage.frame, 1);
+        assert_eq!(damage.rows().collect::<Vec<_>>(), [5, 6]);
+        assert_eq!(
+            damage.rect(),
+            Some(Rect {
+                x: 10,
+                y: 5,
+                width: 2,
+                height: 2
+            })
+        );
+    }
+
+    #[test]
+    fn clear_damages_rows_which_were_lit() {
+        let mut screen = Screen::new();
+        screen.draw_sprite(4, 3, &[0x80], false);
+        screen.present();
+        screen.clear();
+
+        assert_eq!(screen.damage().rows().collect::<Vec<_>>(), [3]);
+        assert_eq!(
+            screen.damage().rect(),
+            Some(Rect {
+                x: 4,
+                y: 3,
+                width: 1,
+                height: 1
+            })
+        );
+    }
+
+    #[test]
+    fn with_rows_damages_whole_rows() {
+        let mut screen = Screen::new();
+        screen.present();
+        screen.draw_sprite(10, 5, &[0x80], false);
+        let damage = screen.damage();
+
+        assert_eq!(damage.with_rows(0), damage);
+        assert_eq!(
+            damage.with_rows(1 << 8).rect(),
+            Some(Rect {
+                x: 0,
+                y: 5,
+                width: WIDTH,
+                height: 4
+            })
+        );
     }
 }
@@ -0,0 +1,171 @@
+//! The stack of return addresses pushed by calls, with room for as many as
+//! the platform has.
+
+use crate::constant::STACK_END;
+use crate::quirks::{Quirks, StackOverflow};
+
+/// What happened to a return address pushed with [Stack::push].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Push {
+    Pushed,
+    /// The stack was full, so the oldest return address was overwritten.
+    Wrapped,
+    /// The stack was full, so the return address wasn't pushed.
+    Overflowed,
+}
+
+pub(crate) struct Stack {
+    depth: usize,
+    overflow: StackOverflow,
+    /// Whether return addresses are kept in memory, growing down from
+    /// [STACK_END], instead of in `slots`.
+    in_memory: bool,
+    slots: Vec<u16>,
+    /// Slot the next return address goes in, which wraps around to the
+    /// first after the last.
+    pointer: usize,
+    len: usize,
+}
+
+impl Stack {
+    pub fn new(quirks: &Quirks) -> Self {
+        Self {
+            depth: quirks.stack_depth,
+            overflow: quirks.stack_overflow,
+            in_memory: quirks.stack_in_memory,
+            slots: vec![0; quirks.stack_depth],
+            pointer: 0,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn push(&mut self, memory: &mut [u8], address: u16) -> Push {
+        let push = if self.len < self.depth {
+            self.len += 1;
+            Push::Pushed
+        } else {
+            match self.overflow {
+                StackOverflow::Halt => return Push::Overflowed,
+                StackOverflow::Wrap => Push::Wrapped,
+            }
+        };
+
+        self.write(memory, self.pointer, address);
+        self.pointer = (self.pointer + 1) % self.depth;
+
+        push
+    }
+
+    pub fn pop(&mut self, memory: &[u8]) -> Option<u16> {
+        if self.len == 0 {
+            return None;
+        }
+
+        self.len -= 1;
+        self.pointer = (self.pointer + self.depth - 1) % self.depth;
+        Some(self.read(memory, self.pointer))
+    }
+
+    /// The return addresses on the stack, the most recently pushed first.
+    pub fn return_addresses(&self, memory: &[u8]) -> Vec<u16> {
+        (1..=self.len)
+            .map(|i| self.read(memory, (self.pointer + self.depth - i) % self.depth))
+            .collect()
+    }
+
+    fn read(&self, memory: &[u8], slot: usize) -> u16 {
+        if self.in_memory {
+            let address = STACK_END - 2 * (slot + 1);
+            u16::from_be_bytes([memory[address], memory[address + 1]])
+        } else {
+            self.slots[slot]
+        }
+    }
+
+    fn write(&mut self, memory: &mut [u8], slot: usize, value: u16) {
+        if self.in_memory {
+            let address = STACK_END - 2 * (slot + 1);
+            memory[address..address + 2].copy_from_slice(&value.to_be_bytes());
+        } else {
+            self.slots[slot] = value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constant::MEMORY_SIZE;
+
+    fn stack(depth: usize, overflow: StackOverflow, in_memory: bool) -> Stack {
+        Stack::new(&Quirks {
+            stack_depth: depth,
+            stack_overflow: overflow,
+            stack_in_memory: in_memory,
+            ..Quirks::default()
+        })
+    }
+
+    #[test]
+    fn pops_in_reverse_order() {
+        let mut memory = [0; MEMORY_SIZE];
+        let mut stack = stack(4, StackOverflow::Halt, false);
+
+        assert_eq!(stack.pop(&memory), None);
+        assert_eq!(stack.push(&mut memory, 0x202), Push::Pushed);
+        assert_eq!(stack.push(&mut memory, 0x304), Push::Pushed);
+        assert_eq!(stack.return_addresses(&memory), [0x304, 0x202]);
+
+        assert_eq!(stack.pop(&memory), Some(0x304));
+        assert_eq!(stack.pop(&memory), Some(0x202));
+        assert_eq!(stack.pop(&memory), None);
+        assert_eq!(stack.len(), 0);
+    }
+
+    #[test]
+    fn halt_refuses_to_overflow() {
+        let mut memory = [0; MEMORY_SIZE];
+        let mut stack = stack(2, StackOverflow::Halt, false);
+        stack.push(&mut memory, 0x202);
+        stack.push(&mut memory, 0x204);
+
+        assert_eq!(stack.push(&mut memory, 0x206), Push::Overflowed);
+        assert_eq!(stack.return_addresses(&memory), [0x204, 0x202]);
+    }
+
+    #[test]
+    fn wrap_overwrites_the_oldest() {
+        let mut memory = [0; MEMORY_SIZE];
+        let mut stack = stack(2, StackOverflow::Wrap, false);
+        stack.push(&mut memory, 0x202);
+        stack.push(&mut memory, 0x204);
+
+        assert_eq!(stack.push(&mut memory, 0x206), Push::Wrapped);
+        assert_eq!(stack.len(), 2);
+        assert_eq!(stack.return_addresses(&memory), [0x206, 0x204]);
+        assert_eq!(stack.pop(&memory), Some(0x206));
+        assert_eq!(stack.pop(&memory), Some(0x204));
+        assert_eq!(stack.pop(&memory), None);
+    }
+
+    #[test]
+    fn in_memory_grows_down_from_the_end() {
+        let mut memory = [0; MEMORY_SIZE];
+        let mut stack = stack(12, StackOverflow::Halt, true);
+        stack.push(&mut memory, 0x0123);
+        stack.push(&mut memory, 0x0456);
+
+        assert_eq!(memory[STACK_END - 2..STACK_END], [0x01, 0x23]);
+        assert_eq!(memory[STACK_END - 4..STACK_END - 2], [0x04, 0x56]);
+        assert_eq!(stack.slots, [0; 12]);
+
+        // Return addresses are read back from memory, so writes by the ROM
+        // change where it returns to.
+        memory[STACK_END - 3] = 0x78;
+        assert_eq!(stack.pop(&memory), Some(0x0478));
+    }
+}
@@ -0,0 +1,18 @@
+//! Handlers for `SYS nnn`, which on the COSMAC VIP runs the machine code at
+//! `nnn`, so that host code can stand in for the routines some games call.
+
+use crate::screen::Screen;
+
+/// What a [SysHandler] can see and change of the emulated machine.
+pub struct Machine<'a> {
+    pub memory: &'a mut [u8],
+    pub registers: &'a mut [u8; 16],
+    pub index: &'a mut u16,
+    pub delay_timer: &'a mut u8,
+    pub sound_timer: &'a mut u8,
+    pub screen: &'a mut Screen,
+}
+
+/// Runs in place of the machine code routine at an address, when a program
+/// calls it with `SYS nnn`.
+pub type SysHandler = Box<dyn FnMut(&mut Machine)>;
@@ -0,0 +1,125 @@
+//! How long instructions take on the COSMAC VIP, for emulating its speed
+//! instead of running a fixed number of instructions each frame.
+//!
+//! The VIP's CDP1802 runs a machine cycle every 8 clock cycles, and the
+//! CDP1861 video chip interrupts it 60 times a second to show the display,
+//! taking some of the cycles in between for itself. The costs here are in
+//! machine cycles, and approximate those of the original interpreter as
+//! measured in Jackson Sommerich's "Chip-8 Instruction Scheduling and
+//! Frequency" (2019), rounded to a single figure where they vary with the
+//! data.
+
+use crate::opcode::Opcode;
+
+/// Machine cycles from one interrupt to the next, which is 262 lines of
+/// 14 cycles each, or a 60th of a second at the VIP's 1.7609 MHz clock.
+pub(crate) const FRAME_CYCLES: i64 = 262 * 14;
+
+/// Machine cycles of each frame taken by the video chip reading the display
+/// from memory, and by the interrupt routine which sets that up and counts
+/// down the timers, which the interpreter can't use.
+pub(crate) const DISPLAY_CYCLES: i64 = 128 * 8 + 46;
+
+/// Machine cycles the interpreter takes to fetch and decode an instruction,
+/// before running it.
+pub(crate) const FETCH_CYCLES: u32 = 40;
+
+/// Machine cycles to run `CLS`, after fetching it.
+const CLEAR_CYCLES: u32 = 3078;
+
+/// Machine cycles to run `opcode`, including fetching it, with `registers`
+/// as they are before it runs.
+pub(crate) fn cycles(opcode: &Opcode, registers: &[u8; 16]) -> u32 {
+    let run = match *opcode {
+        Opcode::Sys { .. } => 23,
+        // The interpreter clears each of the 256 bytes of the display in a
+        // loop.
+        Opcode::Clear => CLEAR_CYCLES,
+        Opcode::Return => 23,
+        Opcode::Jump { .. } => 23,
+        Opcode::Call { .. } => 23,
+        Opcode::SkipEqualsConstant { .. } | Opcode::SkipNotEqualsConstant { .. } => 12,
+        Opcode::SkipEquals { .. } | Opcode::SkipNotEquals { .. } => 16,
+        Opcode::Load { .. } => 6,
+        Opcode::AddConstant { .. } => 10,
+        // The interpreter runs each of these as a small machine code routine
+        // it builds in memory, so they cost the same.
+        Opcode::LoadRegister { .. }
+        | Opcode::Or { .. }
+        | Opcode::And { .. }
+        | Opcode::Xor { .. }
+        | Opcode::Add { .. }
+        | Opcode::Sub { .. }
+        | Opcode::ShiftRight { .. }
+        | Opcode::Subn { .. }
+        | Opcode::ShiftLeft { .. } => 44,
+        Opcode::LoadIndex { .. } => 12,
+        Opcode::JumpPlusV0 { .. } => 23,
+        Opcode::Random { .. } => 36,
+        Opcode::Draw { vx, n, .. } => {
+            // Sprites not starting on a byte of the display are shifted
+            // across two bytes of it, row by row.
+            let per_row = if registers[vx as usize].is_multiple_of(8) {
+                14
+            } else {
+                28
+            };
+            26 + per_row * n as u32
+        }
+        Opcode::KeyPressSkip { .. } | Opcode::KeyNotPressSkip { .. } => 16,
+        Opcode::DelayTimerLoadFrom { .. } => 10,
+        Opcode::KeyLoad { .. } => 10,
+        Opcode::DelayTimerLoadInto { .. } | Opcode::SoundLoad { .. } => 10,
+        Opcode::AddIndex { .. } => 19,
+        Opcode::LocateSprite { .. } => 20,
+        Opcode::LoadBcd { .. } => 204,
+        Opcode::StoreRegisters { vx } | Opcode::ReadRegisters { vx } => 14 + 14 * (vx as u32 + 1),
+    };
+
+    FETCH_CYCLES + run
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn includes_fetching() {
+        let registers = [0; 16];
+
+        assert_eq!(cycles(&Opcode::Load { vx: 0, nn: 1 }, &registers), 46);
+        assert_eq!(cycles(&Opcode::Add { vx: 0, vy: 1 }, &registers), 84);
+    }
+
+    #[test]
+    fn clear_clears_every_byte_of_the_display() {
+        assert_eq!(cycles(&Opcode::Clear, &[0; 16]), 40 + 3078);
+    }
+
+    #[test]
+    fn draw_depends_on_alignment_and_height() {
+        let mut registers = [0; 16];
+        let draw = |n| Opcode::Draw { vx: 2, vy: 3, n };
+
+        registers[2] = 16;
+        assert_eq!(cycles(&draw(1), &registers), 40 + 26 + 14);
+        assert_eq!(cycles(&draw(5), &registers), 40 + 26 + 5 * 14);
+
+        registers[2] = 17;
+        assert_eq!(cycles(&draw(5), &registers), 40 + 26 + 5 * 28);
+    }
+
+    #[test]
+    fn register_transfers_depend_on_count() {
+        let registers = [0; 16];
+
+        assert_eq!(
+            cycles(&Opcode::StoreRegisters { vx: 0 }, &registers),
+            40 + 14 + 14
+        );
+        assert_eq!(
+            cycles(&Opcode::ReadRegisters { vx: 15 }, &registers),
+            40 + 14 + 16 * 14
+        );
+    }
+}
@@ -0,0 +1,242 @@
+//! Logs every instruction the [crate::Emulator] runs to a file, so runs can be
+//! compared with each other or with other emulators.
+//!
+//! Each line holds the state before the instruction runs, in fixed-width
+//! columns, with the instruction itself at the end so it can be cut off when
+//! diffing against emulators which disassemble differently:
+//!
+//! ```text
+//! 0000000000 0200 A21E V 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 I 0000 DT FF ST FF ; LD I, 0x21E
+//! ```
+//!
+//! That is the cycle (instructions run before this one), the PC, the raw
+//! instruction, V0 to VF, I, and the delay and sound timers, all in hex except
+//! the cycle.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use crate::error::Error;
+use crate::opcode::Opcode;
+
+/// Written at the top of every trace, naming the columns.
+const HEADER: &str = "# cycle pc opcode v0-vf i dt st ; instruction";
+
+/// The state of the emulator just before an instruction runs.
+pub(crate) struct Step<'a> {
+    pub cycle: u64,
+    pub pc: u16,
+    pub word: u16,
+    pub opcode: &'a Opcode,
+    pub registers: &'a [u8; 16],
+    pub index: u16,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+/// Where instructions are traced to, and which of them.
+pub struct Trace {
+    out: Box<dyn Write>,
+    /// Only instructions at these addresses are traced, or all of them if
+    /// there are none.
+    ranges: Vec<RangeInclusive<u16>>,
+    /// The first write which failed, after which nothing more is written.
+    error: Option<io::Error>,
+}
+
+impl Trace {
+    /// Traces to `out`, only the instructions at addresses in `ranges` if
+    /// there are any.
+    pub fn new(out: Box<dyn Write>, ranges: Vec<RangeInclusive<u16>>) -> Self {
+        let mut trace = Self {
+            out,
+            ranges,
+            error: None,
+        };
+        trace.write(HEADER);
+
+        trace
+    }
+
+    /// Traces to a new file at `path`, replacing any which is there.
+    pub fn create(path: &Path, ranges: Vec<RangeInclusive<u16>>) -> Result<Self, Error> {
+        let file = File::create(path).map_err(|err| {
+            Error::ErrorStr(format!("Can't create trace '{}': {err}", path.display()))
+        })?;
+
+        Ok(Self::new(Box::new(BufWriter::new(file)), ranges))
+    }
+
+    /// Whether the instruction at `pc` should be traced.
+    pub(crate) fn traces(&self, pc: u16) -> bool {
+        self.error.is_none()
+            && (self.ranges.is_empty() || self.ranges.iter().any(|range| range.contains(&pc)))
+    }
+
+    pub(crate) fn record(&mut self, step: &Step) {
+        let registers = step
+            .registers
+            .iter()
+            .map(|register| format!("{register:02X}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        self.write(&format!(
+            "{:010} {:04X} {:04X} V {registers} I {:04X} DT {:02X} ST {:02X} ; {}",
+            step.cycle,
+            step.pc,
+            step.word,
+            step.index,
+            step.delay_timer,
+            step.sound_timer,
+            step.opcode
+        ));
+    }
+
+    /// Writes out anything buffered, returning the first error from writing
+    /// the trace.
+    pub fn finish(mut self) -> Result<(), Error> {
+        if self.error.is_none() {
+            if let Err(err) = self.out.flush() {
+                self.error = Some(err);
+            }
+        }
+
+        match self.error {
+            Some(err) => Err(Error::ErrorStr(format!("Can't write trace: {err}"))),
+            None => Ok(()),
+        }
+    }
+
+    fn write(&mut self, line: &str) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(err) = writeln!(self.out, "{line}") {
+            self.error = Some(err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::Emulator;
+    use crate::pacing::Pacing;
+    use crate::quirks::Quirks;
+    use crate::window::headless::HeadlessWindow;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Output shared with the test, which can fail every write instead.
+    #[derive(Clone, Default)]
+    struct Output {
+        bytes: Rc<RefCell<Vec<u8>>>,
+        fail: bool,
+    }
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.fail {
+                return Err(io::Error::other("disk full"));
+            }
+            self.bytes.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Output {
+        fn text(&self) -> String {
+            String::from_utf8(self.bytes.borrow().clone()).unwrap()
+        }
+    }
+
+    fn step<'a>(registers: &'a [u8; 16], opcode: &'a Opcode) -> Step<'a> {
+        Step {
+            cycle: 42,
+            pc: 0x204,
+            word: 0xA21E,
+            opcode,
+            registers,
+            index: 0x0F00,
+            delay_timer: 0x3C,
+            sound_timer: 0,
+        }
+    }
+
+    #[test]
+    fn lines_hold_the_state_before_each_instruction() {
+        let output = Output::default();
+        let mut trace = Trace::new(Box::new(output.clone()), vec![]);
+        let mut registers = [0; 16];
+        registers[0] = 0x12;
+        registers[0xF] = 1;
+        trace.record(&step(&registers, &Opcode::LoadIndex { nnn: 0x21E }));
+        trace.finish().unwrap();
+
+        assert_eq!(
+            output.text(),
+            "# cycle pc opcode v0-vf i dt st ; instruction\n\
+             0000000042 0204 A21E V 12 00 00 00 00 00 00 00 00 00 00 00 00 00 00 01 I 0F00 DT 3C ST 00 ; LD I, 0x21E\n"
+        );
+    }
+
+    #[test]
+    fn only_addresses_in_the_ranges_are_traced() {
+        let trace = Trace::new(Box::new(io::sink()), vec![0x200..=0x2FF, 0x400..=0x400]);
+        assert!(trace.traces(0x200));
+        assert!(trace.traces(0x2FF));
+        assert!(!trace.traces(0x300));
+        assert!(trace.traces(0x400));
+
+        let trace = Trace::new(Box::new(io::sink()), vec![]);
+        assert!(trace.traces(0x000));
+        assert!(trace.traces(0xFFE));
+    }
+
+    #[test]
+    fn tracing_stops_at_the_first_error() {
+        let output = Output {
+            fail: true,
+            ..Output::default()
+        };
+        let mut trace = Trace::new(Box::new(output), vec![]);
+        trace.record(&step(&[0; 16], &Opcode::Clear));
+
+        assert!(!trace.traces(0x200));
+        assert_eq!(
+            trace.finish().unwrap_err().to_string(),
+            "Can't write trace: disk full"
+        );
+    }
+
+    #[test]
+    fn traces_a_running_rom() {
+        let output = Output::default();
+        let mut emulator = Emulator::new(
+            Box::new(HeadlessWindow::new(1)),
+            Quirks::default(),
+            Pacing::default(),
+        );
+        // LD I, 0x21E; LD V0, 0x05; JP 0x204
+        emulator
+            .load_rom("test", vec![0xA2, 0x1E, 0x60, 0x05, 0x12, 0x04])
+            .unwrap();
+        emulator.set_trace(Trace::new(Box::new(output.clone()), vec![0x200..=0x203]));
+        emulator.start();
+        emulator.take_trace().unwrap().finish().unwrap();
+
+        assert_eq!(
+            output.text(),
+            "# cycle pc opcode v0-vf i dt st ; instruction\n\
+             0000000000 0200 A21E V 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 I 0000 DT FF ST FF ; LD I, 0x21E\n\
+             0000000001 0202 6005 V 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 I 021E DT FF ST FF ; LD V0, 0x05\n"
+        );
+    }
+}
@@ -0,0 +1,476 @@
+//! Encoders for the image protocols of terminals, used by
+//! [crate::window::terminal::TerminalWindow] to show the screen as an inline
+//! image instead of with characters.
+
+use std::io::Write;
+use std::str::FromStr;
+use std::time::Duration;
+
+use clap::builder::PossibleValue;
+use clap::ValueEnum;
+
+use crate::error::Error;
+
+/// Protocols for showing images in a terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Graphics {
+    /// DEC Sixel, supported by xterm, foot, WezTerm, mlterm and others.
+    Sixel,
+    /// The Kitty graphics protocol, supported by Kitty, WezTerm and Ghostty.
+    Kitty,
+}
+
+/// Which [Graphics] to use, if any.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum GraphicsMode {
+    /// Always draw with characters.
+    #[default]
+    None,
+    /// Query the terminal for a supported protocol, falling back to characters.
+    Auto,
+    /// Use this protocol without checking if the terminal supports it.
+    Force(Graphics),
+}
+
+impl FromStr for GraphicsMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        <Self as ValueEnum>::from_str(s, false).map_err(|_| {
+            Error::ErrorStr(format!(
+                "Unknown graphics '{s}', expected one of: none, auto, sixel, kitty"
+            ))
+        })
+    }
+}
+
+impl ValueEnum for GraphicsMode {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[
+            GraphicsMode::None,
+            GraphicsMode::Auto,
+            GraphicsMode::Force(Graphics::Sixel),
+            GraphicsMode::Force(Graphics::Kitty),
+        ]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            GraphicsMode::None => PossibleValue::new("none").help("Always draw with characters"),
+            GraphicsMode::Auto => PossibleValue::new("auto")
+                .help("Ask the terminal which it supports, falling back to characters"),
+            GraphicsMode::Force(Graphics::Sixel) => PossibleValue::new("sixel").help("DEC Sixel"),
+            GraphicsMode::Force(Graphics::Kitty) => {
+                PossibleValue::new("kitty").help("The Kitty graphics protocol")
+            }
+        })
+    }
+}
+
+/// An image to show, where each pixel is a `0x00RRGGBB` colour.
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u32>,
+}
+
+impl Graphics {
+    /// Writes the escape sequence which draws `image` at the cursor.
+    pub fn encode(&self, image: &Image, out: &mut Vec<u8>) {
+        match self {
+            Graphics::Sixel => encode_sixel(image, out),
+            Graphics::Kitty => encode_kitty(image, out),
+        }
+    }
+
+    /// Writes the escape sequence which removes images drawn with
+    /// [Graphics::encode], if the protocol needs it.
+    pub fn clear(&self, out: &mut Vec<u8>) {
+        match self {
+            // Sixels are just pixels in cells, so are cleared with the cells.
+            Graphics::Sixel => {}
+            Graphics::Kitty => out.extend_from_slice(b"\x1b_Ga=d,d=I,i=1,q=2\x1b\\"),
+        }
+    }
+}
+
+/// Colour registers used for sixel images. Colours are taken from the image
+/// in the order they appear, and any past this share the last register.
+const SIXEL_MAX_COLORS: usize = 16;
+
+fn encode_sixel(image: &Image, out: &mut Vec<u8>) {
+    let mut colors: Vec<u32> = vec![];
+    let registers = image
+        .pixels
+        .iter()
+        .map(
+            |pixel| match colors.iter().position(|color| color == pixel) {
+                Some(register) => register,
+                None if colors.len() < SIXEL_MAX_COLORS => {
+                    colors.push(*pixel);
+                    colors.len() - 1
+                }
+                None => SIXEL_MAX_COLORS - 1,
+            },
+        )
+        .collect::<Vec<usize>>();
+
+    // Start the sixel sequence, where `P2 = 1` keeps pixels which aren't drawn
+    // unchanged, and the raster attributes give square pixels and the size.
+    out.extend_from_slice(b"\x1bP0;1q");
+    write!(out, "\"1;1;{};{}", image.width, image.height).unwrap();
+
+    for (register, color) in colors.iter().enumerate() {
+        let [_, r, g, b] = color.to_be_bytes();
+        let percent = |c: u8| c as u32 * 100 / 255;
+        write!(
+            out,
+            "#{register};2;{};{};{}",
+            percent(r),
+            percent(g),
+            percent(b)
+        )
+        .unwrap();
+    }
+
+    // Each sixel character is a column of 6 pixels in a band, so draw each
+    // band once for every colour in it, returning to the start of the band in
+    // between.
+    for band in (0..image.height).step_by(6) {
+        let rows = band..(band + 6).min(image.height);
+
+        for register in 0..colors.len() {
+            let mut sixels = (0..image.width)
+                .map(|x| {
+                    rows.clone()
+                        .filter(|y| registers[y * image.width + x] == register)
+                        .fold(0, |bits, y| bits | 1 << (y - band))
+                })
+                .collect::<Vec<u8>>();
+
+            // Empty sixels at the end of the band don't need to be drawn.
+            while sixels.last() == Some(&0) {
+                sixels.pop();
+            }
+            if sixels.is_empty() {
+                continue;
+            }
+
+            write!(out, "#{register}").unwrap();
+            for run in sixels.chunk_by(|a, b| a == b) {
+                let sixel = (b'?' + run[0]) as char;
+                match run.len() {
+                    1..=3 => (0..run.len()).for_each(|_| out.push(sixel as u8)),
+                    len => write!(out, "!{len}{sixel}").unwrap(),
+                }
+            }
+            out.push(b'$');
+        }
+
+        out.push(b'-');
+    }
+
+    out.extend_from_slice(b"\x1b\\");
+}
+
+/// Largest payload in a single escape sequence of the Kitty protocol.
+const KITTY_CHUNK_SIZE: usize = 4096;
+
+fn encode_kitty(image: &Image, out: &mut Vec<u8>) {
+    let rgb = image
+        .pixels
+        .iter()
+        .flat_map(|pixel| {
+            let [_, r, g, b] = pixel.to_be_bytes();
+            [r, g, b]
+        })
+        .collect::<Vec<u8>>();
+
+    let data = base64(&rgb);
+    let mut chunks = data.chunks(KITTY_CHUNK_SIZE).peekable();
+
+    // Reusing the same image and placement ids replaces the previous frame,
+    // instead of stacking images on top of each other. `C=1` stops the cursor
+    // from moving, so drawing can't scroll the terminal.
+    write!(
+        out,
+        "\x1b_Ga=T,f=24,s={},v={},i=1,p=1,q=2,C=1,",
+        image.width, image.height
+    )
+    .unwrap();
+
+    while let Some(chunk) = chunks.next() {
+        let more = chunks.peek().is_some() as u8;
+        write!(out, "m={more};").unwrap();
+        out.extend_from_slice(chunk);
+        out.extend_from_slice(b"\x1b\\");
+
+        if more == 1 {
+            out.extend_from_slice(b"\x1b_G");
+        }
+    }
+}
+
+fn base64(bytes: &[u8]) -> Vec<u8> {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = Vec::with_capacity(bytes.len().div_ceil(3) * 4);
+
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, byte)| n | (*byte as u32) << (16 - 8 * i));
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize]);
+            } else {
+                out.push(b'=');
+            }
+        }
+    }
+
+    out
+}
+
+/// How long to wait for the terminal to answer queries.
+#[cfg(unix)]
+const QUERY_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Asks the terminal which protocols it supports, preferring Kitty.
+///
+/// Expects raw mode to be enabled, so the response isn't echoed.
+#[cfg(unix)]
+pub fn detect() -> Option<Graphics> {
+    // Query Kitty support with a 1x1 image, followed by the primary device
+    // attributes, which every terminal answers. So once the attributes arrive
+    // there is no need to wait for the Kitty response any longer.
+    let mut stdout = std::io::stdout();
+    stdout
+        .write_all(b"\x1b_Gi=31,s=1,v=1,a=q,t=d,f=24;AAAA\x1b\\\x1b[c")
+        .ok()?;
+    stdout.flush().ok()?;
+
+    let response = read_response(|response| device_attributes(response).is_some());
+
+    if String::from_utf8_lossy(&response).contains("\x1b_Gi=31;OK") {
+        Some(Graphics::Kitty)
+    } else if device_attributes(&response)?
+        .split(';')
+        .any(|attribute| attribute == "4")
+    {
+        // Attribute 4 is sixel graphics.
+        Some(Graphics::Sixel)
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+pub fn detect() -> Option<Graphics> {
+    None
+}
+
+/// Parameters of a primary device attributes response, `CSI ? ... c`.
+fn device_attributes(response: &[u8]) -> Option<&str> {
+    let response = std::str::from_utf8(response).ok()?;
+    let start = response.find("\x1b[?")? + 3;
+    let len = response[start..].find('c')?;
+    Some(&response[start..start + len])
+}
+
+/// Reads from the terminal until `done` returns true, or [QUERY_TIMEOUT]
+/// passes.
+#[cfg(unix)]
+fn read_response(done: impl Fn(&[u8]) -> bool) -> Vec<u8> {
+    use std::fs::File;
+    use std::io::{self, IsTerminal};
+    use std::os::fd::AsRawFd;
+    use std::time::Instant;
+
+    // The terminal answers on the tty, which isn't stdin when the ROM is
+    // piped in.
+    let tty = (!io::stdin().is_terminal())
+        .then(|| File::open("/dev/tty").ok())
+        .flatten();
+    let tty_fd = tty
+        .as_ref()
+        .map_or(libc::STDIN_FILENO, |tty| tty.as_raw_fd());
+
+    let deadline = Instant::now() + QUERY_TIMEOUT;
+    let mut response = vec![];
+
+    while !done(&response) {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+
+        let mut fd = libc::pollfd {
+            fd: tty_fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // Safe as `fd` is a single valid `pollfd`.
+        let ready = unsafe { libc::poll(&mut fd, 1, remaining.as_millis() as libc::c_int) };
+        if ready <= 0 {
+            break;
+        }
+
+        let mut buf = [0u8; 256];
+        // Safe as `buf` is valid for writes of its length. Reading the file
+        // descriptor directly avoids the buffering of `Stdin`, which would
+        // hold on to input after the response.
+        let len = unsafe { libc::read(tty_fd, buf.as_mut_ptr().cast(), buf.len()) };
+        if len <= 0 {
+            break;
+        }
+        response.extend_from_slice(&buf[..len as usize]);
+    }
+
+    response
+}
+
+/// Size of a terminal cell in pixels, if the terminal reports it.
+#[cfg(unix)]
+pub fn cell_size() -> Option<(usize, usize)> {
+    let mut size = libc::winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // Safe as `TIOCGWINSZ` only writes a `winsize` to the pointer.
+    let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+
+    if result != 0 || size.ws_col == 0 || size.ws_row == 0 {
+        return None;
+    }
+
+    // Terminals which don't know the size in pixels report 0, and cells
+    // smaller than a pixel can't be drawn in.
+    let width = (size.ws_xpixel / size.ws_col) as usize;
+    let height = (size.ws_ypixel / size.ws_row) as usize;
+    (width > 0 && height > 0).then_some((width, height))
+}
+
+#[cfg(not(unix))]
+pub fn cell_size() -> Option<(usize, usize)> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: u32 = 0x000000;
+    const WHITE: u32 = 0xFFFFFF;
+
+    #[test]
+    fn base64_vectors() {
+        assert_eq!(base64(b""), b"");
+        assert_eq!(base64(b"f"), b"Zg==");
+        assert_eq!(base64(b"fo"), b"Zm8=");
+        assert_eq!(base64(b"foo"), b"Zm9v");
+        assert_eq!(base64(b"foobar"), b"Zm9vYmFy");
+        assert_eq!(base64(&[0xFF, 0xFE]), b"//4=");
+    }
+
+    #[test]
+    fn sixel_runs() {
+        let image = Image {
+            width: 5,
+            height: 1,
+            pixels: vec![BLACK, BLACK, BLACK, BLACK, WHITE],
+        };
+        let mut out = vec![];
+        encode_sixel(&image, &mut out);
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\x1bP0;1q\"1;1;5;1#0;2;0;0;0#1;2;100;100;100#0!4@$#1!4?@$-\x1b\\"
+        );
+    }
+
+    #[test]
+    fn sixel_bands() {
+        let image = Image {
+            width: 3,
+            height: 7,
+            pixels: vec![WHITE; 21],
+        };
+        let mut out = vec![];
+        encode_sixel(&image, &mut out);
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\x1bP0;1q\"1;1;3;7#0;2;100;100;100#0~~~$-#0@@@$-\x1b\\"
+        );
+    }
+
+    /// Kitty output for an image `pixels` wide, which is `pixels * 4` base64
+    /// characters as each pixel is 3 bytes.
+    fn kitty(pixels: usize) -> String {
+        let image = Image {
+            width: pixels,
+            height: 1,
+            pixels: vec![WHITE; pixels],
+        };
+        let mut out = vec![];
+        encode_kitty(&image, &mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn kitty_fits_one_chunk() {
+        let out = kitty(KITTY_CHUNK_SIZE / 4);
+        let header = format!(
+            "\x1b_Ga=T,f=24,s={},v=1,i=1,p=1,q=2,C=1,m=0;",
+            KITTY_CHUNK_SIZE / 4
+        );
+
+        assert_eq!(
+            out,
+            format!("{header}{}\x1b\\", "/".repeat(KITTY_CHUNK_SIZE))
+        );
+    }
+
+    #[test]
+    fn kitty_splits_chunks() {
+        let out = kitty(KITTY_CHUNK_SIZE / 4 + 1);
+        let header = format!(
+            "\x1b_Ga=T,f=24,s={},v=1,i=1,p=1,q=2,C=1,m=1;",
+            KITTY_CHUNK_SIZE / 4 + 1
+        );
+
+        assert_eq!(
+            out,
+            format!(
+                "{header}{}\x1b\\\x1b_Gm=0;////\x1b\\",
+                "/".repeat(KITTY_CHUNK_SIZE)
+            )
+        );
+    }
+
+    fn has_sixel(response: &[u8]) -> bool {
+        device_attributes(response)
+            .unwrap()
+            .split(';')
+            .any(|attribute| attribute == "4")
+    }
+
+    #[test]
+    fn device_attributes_responses() {
+        assert_eq!(device_attributes(b"\x1b[?62;4;22c"), Some("62;4;22"));
+        assert!(has_sixel(b"\x1b[?62;4;22c"));
+        assert!(!has_sixel(b"\x1b[?62;22;44c"));
+        // After a Kitty response.
+        assert_eq!(
+            device_attributes(b"\x1b_Gi=31;OK\x1b\\\x1b[?1;2c"),
+            Some("1;2")
+        );
+        assert_eq!(device_attributes(b"\x1b[?62;4"), None);
+        assert_eq!(device_attributes(b""), None);
+    }
+}
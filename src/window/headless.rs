@@ -0,0 +1,71 @@
+//! A [Window] which shows nothing, for running ROMs without a display.
+
+use crate::error::Error;
+use crate::keypad::Key;
+use crate::menu::Menu;
+use crate::screen::{Damage, Framebuffer};
+
+use super::{Hotkey, MenuKey, Status, Window};
+
+/// Runs for a fixed number of frames as fast as possible, with no keys
+/// pressed, e.g. to benchmark the emulator or to check what a test ROM draws.
+pub struct HeadlessWindow {
+    frames_left: u64,
+}
+
+impl HeadlessWindow {
+    pub fn new(frames: u64) -> Self {
+        Self {
+            frames_left: frames,
+        }
+    }
+}
+
+impl Window for HeadlessWindow {
+    fn is_running(&mut self) -> bool {
+        self.frames_left > 0
+    }
+
+    fn is_key_down(&self, _key: Key) -> bool {
+        false
+    }
+
+    fn is_key_up(&self, _key: Key) -> bool {
+        true
+    }
+
+    fn wait_for_next_key(&mut self) -> Option<Key> {
+        None
+    }
+
+    fn hotkeys_pressed(&self) -> Vec<Hotkey> {
+        vec![]
+    }
+
+    fn is_hotkey_down(&self, _hotkey: Hotkey) -> bool {
+        false
+    }
+
+    fn menu_keys_pressed(&self) -> Vec<MenuKey> {
+        vec![]
+    }
+
+    fn draw_menu(&mut self, _menu: &Menu) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// There's no display to wait for, so frames aren't paced at all.
+    fn paces_frames(&self) -> bool {
+        true
+    }
+
+    fn update(
+        &mut self,
+        _buffer: Framebuffer,
+        _damage: Damage,
+        _status: &Status,
+    ) -> Result<(), Error> {
+        self.frames_left -= 1;
+        Ok(())
+    }
+}
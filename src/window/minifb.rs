@@ -7,18 +7,82 @@ use std::time::Duration;
 use crate::constant::{FPS, HEIGHT, WIDTH};
 use crate::error::Error;
 use crate::keypad::Key;
+use crate::menu::Menu;
+use crate::palette::Palette;
+use crate::screen::{Damage, Framebuffer, ALL_ROWS};
+use crate::window::font;
+use crate::window::phosphor::{Persistence, Phosphor};
+use crate::window::{Hotkey, MenuKey, Status};
 use minifb::{KeyRepeat, Scale, Window, WindowOptions};
 
-pub struct MinifbWindow(Window);
+pub struct MinifbWindow {
+    window: Window,
+    palette: Palette,
+    phosphor: Option<Phosphor>,
+    /// Pixels last sent to the window, scaled up by [SCALE].
+    buffer: Vec<u32>,
+    /// Whether the [Status] is drawn over the top left of the screen.
+    hud: bool,
+    /// Whether the HUD was drawn in the last frame.
+    hud_drawn: bool,
+    /// Hotkeys pressed since the last call to `is_running`.
+    hotkeys_pressed: Vec<Hotkey>,
+    /// Whether [minifb] paces frames, see [MinifbWindow::new].
+    vsync: bool,
+    /// Menu keys pressed since the last call to `is_running`.
+    menu_keys_pressed: Vec<MenuKey>,
+    /// Whether the menu was drawn over the screen since the last `update`.
+    menu_drawn: bool,
+    /// Whether key presses were read since [minifb] last updated them, which
+    /// it only does when showing a frame. Reading them again would see the
+    /// same presses twice, e.g. the Esc which exits a program would also
+    /// leave the menu shown after it.
+    keys_read: bool,
+}
+
+/// Size of each CHIP-8 pixel in window pixels.
+///
+/// The buffer is scaled up here rather than by [minifb], so that the HUD can
+/// be drawn at a finer resolution than the screen.
+const SCALE: usize = 8;
+
+/// Size of each font pixel of the HUD in window pixels.
+const HUD_SCALE: usize = 2;
+
+/// Space around the text of the HUD, in window pixels.
+const HUD_PADDING: usize = 4;
+
+/// Toggles showing the HUD.
+const HUD_KEY: minifb::Key = minifb::Key::F1;
+
+/// Keys bound to each [Hotkey].
+const HOTKEYS: [(minifb::Key, Hotkey); 5] = [
+    (minifb::Key::P, Hotkey::Pause),
+    (minifb::Key::N, Hotkey::FrameAdvance),
+    (minifb::Key::Tab, Hotkey::Turbo),
+    (minifb::Key::M, Hotkey::SlowMotion),
+    (minifb::Key::Escape, Hotkey::Exit),
+];
+
+/// Keys bound to each [MenuKey], and whether they repeat while held.
+const MENU_KEYS: [(minifb::Key, MenuKey, KeyRepeat); 4] = [
+    (minifb::Key::Up, MenuKey::Up, KeyRepeat::Yes),
+    (minifb::Key::Down, MenuKey::Down, KeyRepeat::Yes),
+    (minifb::Key::Enter, MenuKey::Select, KeyRepeat::No),
+    (minifb::Key::Escape, MenuKey::Back, KeyRepeat::No),
+];
 
 impl MinifbWindow {
-    pub fn new() -> Self {
+    /// With `vsync`, frames are paced by [minifb] when they are shown, instead
+    /// of by the emulator. [minifb] doesn't expose the vertical sync of the
+    /// display, so this waits for its own 60 Hz timer.
+    pub fn new(palette: Palette, persistence: Option<Persistence>, vsync: bool) -> Self {
         let mut window = Window::new(
-            "chip8 - Press ESC to exit",
-            WIDTH,
-            HEIGHT,
+            "chip8 - Press ESC to exit, F1 for HUD",
+            WIDTH * SCALE,
+            HEIGHT * SCALE,
             WindowOptions {
-                scale: Scale::X8,
+                scale: Scale::X1,
                 ..WindowOptions::default()
             },
         )
@@ -27,46 +91,240 @@ impl MinifbWindow {
         });
 
         // This setting affects how long `window.update` will take to return.
-        window.limit_update_rate(Some(Duration::from_secs_f64(FPS)));
+        window.limit_update_rate(vsync.then(|| Duration::from_secs_f64(FPS)));
 
-        Self(window)
+        Self {
+            window,
+            palette,
+            phosphor: persistence.map(Phosphor::new),
+            buffer: vec![palette.background(); WIDTH * SCALE * HEIGHT * SCALE],
+            hud: false,
+            hud_drawn: false,
+            hotkeys_pressed: vec![],
+            vsync,
+            menu_keys_pressed: vec![],
+            menu_drawn: false,
+            keys_read: false,
+        }
     }
 }
 
-const PIXEL_COLOR: u32 = u32::MAX;
+impl Default for MinifbWindow {
+    fn default() -> Self {
+        Self::new(Palette::default(), None, false)
+    }
+}
 
 impl crate::window::Window for MinifbWindow {
     fn is_running(&mut self) -> bool {
-        self.0.is_open() && !self.0.is_key_down(minifb::Key::Escape)
+        if self.keys_read {
+            self.hotkeys_pressed.clear();
+            self.menu_keys_pressed.clear();
+            return self.window.is_open();
+        }
+        self.keys_read = true;
+
+        if self.window.is_key_pressed(HUD_KEY, KeyRepeat::No) {
+            self.hud = !self.hud;
+        }
+
+        self.hotkeys_pressed = HOTKEYS
+            .into_iter()
+            .filter(|(key, _)| self.window.is_key_pressed(*key, KeyRepeat::No))
+            .map(|(_, hotkey)| hotkey)
+            .collect();
+
+        self.menu_keys_pressed = MENU_KEYS
+            .into_iter()
+            .filter(|(key, _, repeat)| self.window.is_key_pressed(*key, *repeat))
+            .map(|(_, menu_key, _)| menu_key)
+            .collect();
+
+        self.window.is_open()
     }
 
     fn is_key_down(&self, key: Key) -> bool {
-        self.0.is_key_down(key.into())
+        self.window.is_key_down(key.into())
     }
 
     fn is_key_up(&self, key: Key) -> bool {
-        self.0.is_key_released(key.into())
+        self.window.is_key_released(key.into())
     }
 
-    fn wait_for_next_key(&self) -> Option<Key> {
-        self.0
+    fn wait_for_next_key(&mut self) -> Option<Key> {
+        self.window
             .get_keys_pressed(KeyRepeat::No)
             .into_iter()
-            .map(|key| Key::try_from(key))
+            .map(Key::try_from)
             .filter_map(|key| key.ok())
-            .nth(0)
+            .next()
+    }
+
+    fn hotkeys_pressed(&self) -> Vec<Hotkey> {
+        self.hotkeys_pressed.clone()
     }
 
-    fn update(&mut self, buffer: &[bool; WIDTH * HEIGHT]) -> Result<(), Error> {
-        let buffer = buffer
+    fn is_hotkey_down(&self, hotkey: Hotkey) -> bool {
+        HOTKEYS
             .into_iter()
-            .map(|on| if *on { PIXEL_COLOR } else { 0 })
-            .collect::<Vec<u32>>();
+            .any(|(key, bound)| bound == hotkey && self.window.is_key_down(key))
+    }
+
+    fn menu_keys_pressed(&self) -> Vec<MenuKey> {
+        self.menu_keys_pressed.clone()
+    }
+
+    fn draw_menu(&mut self, menu: &Menu) -> Result<(), Error> {
+        let (width, height) = (WIDTH * SCALE, HEIGHT * SCALE);
+        let line_height = font::line_height(HUD_SCALE);
+        let lines = menu.lines(
+            (width - HUD_PADDING * 2) / font::advance(HUD_SCALE),
+            (height - HUD_PADDING * 2) / line_height,
+        );
+
+        self.buffer.fill(self.palette.background());
+
+        for (i, line) in lines.iter().enumerate() {
+            let y = HUD_PADDING + i * line_height;
+
+            // Draw the selected line inverted, with the band starting a font
+            // pixel above the text so that it is centred.
+            let color = if line.selected {
+                let top = (y - HUD_SCALE) * width;
+                self.buffer[top..top + line_height * width].fill(self.palette.foreground());
+                self.palette.background()
+            } else {
+                self.palette.foreground()
+            };
+
+            font::draw_text(
+                &mut self.buffer,
+                width,
+                (HUD_PADDING, y),
+                HUD_SCALE,
+                &line.text,
+                color,
+            );
+        }
+
+        self.menu_drawn = true;
+        self.keys_read = false;
 
-        self.0
-            .update_with_buffer(&buffer, WIDTH, HEIGHT)
+        self.window
+            .update_with_buffer(&self.buffer, width, height)
             .map_err(|err| Error::ErrorStr(err.to_string()))
     }
+
+    fn paces_frames(&self) -> bool {
+        self.vsync
+    }
+
+    fn update(
+        &mut self,
+        buffer: Framebuffer,
+        damage: Damage,
+        status: &Status,
+    ) -> Result<(), Error> {
+        let damage = match &mut self.phosphor {
+            Some(phosphor) => phosphor.update(buffer, damage),
+            None => damage,
+        };
+
+        // The status changes all the time, so redraw the HUD on every frame it
+        // is shown, and once more to clear it after it is hidden.
+        let lines = status.lines();
+        // The menu covers the whole screen.
+        let damage = if self.menu_drawn {
+            self.menu_drawn = false;
+            damage.with_rows(ALL_ROWS)
+        } else {
+            damage
+        };
+
+        let damage = if self.hud || self.hud_drawn {
+            damage.with_rows(hud_rows(&lines))
+        } else {
+            damage
+        };
+
+        if damage.is_empty() {
+            // Still needs to be called to process input events.
+            self.window.update();
+            self.keys_read = false;
+            return Ok(());
+        }
+
+        let width = WIDTH * SCALE;
+        for y in damage.rows() {
+            for x in 0..WIDTH {
+                let color = match &self.phosphor {
+                    Some(phosphor) => self.palette.shade(phosphor.level(x, y)),
+                    None => self.palette.color(buffer.pixel(x, y) as usize),
+                };
+
+                for dy in 0..SCALE {
+                    let start = (y * SCALE + dy) * width + x * SCALE;
+                    self.buffer[start..start + SCALE].fill(color);
+                }
+            }
+        }
+
+        if self.hud {
+            self.draw_hud(&lines);
+        }
+        self.hud_drawn = self.hud;
+        self.keys_read = false;
+
+        self.window
+            .update_with_buffer(&self.buffer, width, HEIGHT * SCALE)
+            .map_err(|err| Error::ErrorStr(err.to_string()))
+    }
+}
+
+impl MinifbWindow {
+    /// Draws `lines` of the status over the top left corner of the buffer.
+    fn draw_hud(&mut self, lines: &[String]) {
+        let width = WIDTH * SCALE;
+        let (box_width, box_height) = hud_size(lines);
+
+        for y in 0..box_height.min(HEIGHT * SCALE) {
+            let start = y * width;
+            self.buffer[start..start + box_width.min(width)].fill(self.palette.background());
+        }
+
+        for (i, line) in lines.iter().enumerate() {
+            font::draw_text(
+                &mut self.buffer,
+                width,
+                (HUD_PADDING, HUD_PADDING + i * font::line_height(HUD_SCALE)),
+                HUD_SCALE,
+                line,
+                self.palette.foreground(),
+            );
+        }
+    }
+}
+
+/// Size of the HUD in window pixels.
+fn hud_size(lines: &[String]) -> (usize, usize) {
+    let columns = lines
+        .iter()
+        .map(|line| line.chars().count())
+        .max()
+        .unwrap_or(0);
+
+    (
+        columns * font::advance(HUD_SCALE) + HUD_PADDING * 2,
+        lines.len() * font::line_height(HUD_SCALE) + HUD_PADDING * 2,
+    )
+}
+
+/// Bitmask of the rows of the screen which the HUD covers.
+fn hud_rows(lines: &[String]) -> u64 {
+    let (_, height) = hud_size(lines);
+    let rows = height.div_ceil(SCALE).min(HEIGHT);
+
+    ALL_ROWS >> (HEIGHT - rows)
 }
 
 impl TryFrom<minifb::Key> for Key {
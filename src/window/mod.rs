@@ -1,8 +1,18 @@
-use crate::constant::{HEIGHT, WIDTH};
+use std::fmt;
+use std::time::Duration;
+
 use crate::error::Error;
 use crate::keypad::Key;
+use crate::menu::Menu;
+use crate::pacing::FrameTimes;
+use crate::quirks::Quirks;
+use crate::screen::{Damage, Framebuffer};
 
+pub mod font;
+pub mod graphics;
+pub mod headless;
 pub mod minifb;
+pub mod phosphor;
 pub mod terminal;
 
 /// Interface for UI backends.
@@ -13,7 +23,148 @@ pub trait Window {
 
     fn is_key_up(&self, key: Key) -> bool;
 
-    fn wait_for_next_key(&self) -> Option<Key>;
+    fn wait_for_next_key(&mut self) -> Option<Key>;
+
+    /// Hotkeys pressed since the last call to `is_running`.
+    fn hotkeys_pressed(&self) -> Vec<Hotkey>;
+
+    fn is_hotkey_down(&self, hotkey: Hotkey) -> bool;
+
+    /// Keys pressed to navigate the [Menu] since the last call to
+    /// `is_running`.
+    fn menu_keys_pressed(&self) -> Vec<MenuKey>;
+
+    /// Shows `menu` instead of the screen, until the next `update`.
+    fn draw_menu(&mut self, menu: &Menu) -> Result<(), Error>;
+
+    /// Whether `update` waits for the display to refresh, so the emulator
+    /// doesn't need to wait between frames itself.
+    fn paces_frames(&self) -> bool;
+
+    /// Shows the current frame.
+    ///
+    /// `damage` describes what changed since the previous call, so only those
+    /// parts need to be redrawn. `status` is for backends to show on a HUD.
+    fn update(&mut self, buffer: Framebuffer, damage: Damage, status: &Status)
+        -> Result<(), Error>;
+}
+
+/// Keys which control the emulator, rather than being passed to the program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hotkey {
+    /// Pauses or resumes emulation.
+    Pause,
+    /// Pauses emulation if it is running, and then runs a single frame.
+    FrameAdvance,
+    /// Runs faster while held, see [crate::pacing::Turbo].
+    Turbo,
+    /// Cycles through [crate::pacing::SLOW_MOTION].
+    SlowMotion,
+    /// Stops the program, going back to the [Menu] if it was started from one.
+    Exit,
+}
+
+impl Hotkey {
+    pub const ALL: [Hotkey; 5] = [
+        Hotkey::Pause,
+        Hotkey::FrameAdvance,
+        Hotkey::Turbo,
+        Hotkey::SlowMotion,
+        Hotkey::Exit,
+    ];
+}
+
+/// Keys which navigate the [Menu].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MenuKey {
+    Up,
+    Down,
+    Select,
+    Back,
+}
+
+/// What the emulator is doing, for backends to show on a HUD.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Status {
+    /// Name of the loaded ROM.
+    pub rom: String,
+    /// Instructions emulated in the last second.
+    pub instructions_per_second: u32,
+    /// Frames shown in the last second.
+    pub frames_per_second: u32,
+    /// How long frames took in the last second.
+    pub frame_times: FrameTimes,
+    pub paused: bool,
+    /// Whether the program stopped, e.g. on a stack overflow.
+    pub halted: bool,
+    /// Where each subroutine which hasn't returned yet was called from, the
+    /// innermost first.
+    pub backtrace: Vec<u16>,
+    pub quirks: Quirks,
+    /// How many times faster than normal the emulator runs, which is infinite
+    /// when unthrottled.
+    pub speed: f64,
+    /// Whether the sound timer is running, which is when a tone would play.
+    pub sound: bool,
+}
+
+impl Status {
+    /// The status split into short lines, for backends with little space.
+    pub fn lines(&self) -> Vec<String> {
+        let backtrace = if self.backtrace.is_empty() {
+            "none".to_string()
+        } else {
+            // Outermost first, to read like a path.
+            self.backtrace
+                .iter()
+                .rev()
+                .map(|call| format!("{call:03X}"))
+                .collect::<Vec<_>>()
+                .join(" > ")
+        };
+        let speed = if self.speed.is_finite() {
+            format!("{}x", self.speed)
+        } else {
+            "max".to_string()
+        };
+
+        let mut state = vec![speed];
+        if self.halted {
+            state.push("halted".to_string());
+        }
+        if self.paused {
+            state.push("paused".to_string());
+        }
+        if self.sound {
+            state.push("sound".to_string());
+        }
+
+        vec![
+            self.rom.clone(),
+            format!(
+                "{} ips {} fps",
+                self.instructions_per_second, self.frames_per_second
+            ),
+            format!(
+                "frame {:.1}ms sd {:.1} max {:.1}",
+                millis(self.frame_times.mean),
+                millis(self.frame_times.jitter),
+                millis(self.frame_times.max)
+            ),
+            format!("quirks: {}", self.quirks),
+            state.join(" "),
+            // Last, as it can be long.
+            format!("calls: {backtrace}"),
+        ]
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.
+}
 
-    fn update(&mut self, buffer: &[bool; WIDTH * HEIGHT]) -> Result<(), Error>;
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.lines().join(" | "))
+    }
 }
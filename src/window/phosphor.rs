@@ -0,0 +1,118 @@
+//! Filter between the [crate::screen::Screen] and a backend which simulates
+//! the persistence of a phosphor display.
+//!
+//! CHIP-8 programs move sprites by XORing them off and on again, so moving
+//! objects are missing from some frames and flicker. Keeping pixels lit for a
+//! little while after they are turned off hides this. Only what is shown is
+//! affected, never the emulation itself.
+
+use std::str::FromStr;
+
+use crate::constant::{HEIGHT, WIDTH};
+use crate::error::Error;
+use crate::screen::{Damage, Framebuffer};
+
+/// Brightness of a pixel which is on.
+pub const MAX_LEVEL: u8 = u8::MAX;
+
+/// Faded pixels dimmer than this are turned off, instead of slowly fading
+/// forever.
+const MIN_LEVEL: u8 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Persistence {
+    /// A pixel is shown if it was on in either of the last two frames.
+    TwoFrames,
+    /// Pixels which are turned off fade out, losing this fraction of their
+    /// brightness every frame.
+    Decay(f32),
+}
+
+impl FromStr for Persistence {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "two-frames" {
+            return Ok(Persistence::TwoFrames);
+        }
+
+        match s.parse::<f32>() {
+            Ok(decay) if decay > 0. && decay <= 1. => Ok(Persistence::Decay(decay)),
+            _ => Err(Error::ErrorStr(format!(
+                "Invalid persistence '{s}', expected `two-frames` or a decay between 0 and 1"
+            ))),
+        }
+    }
+}
+
+pub struct Phosphor {
+    persistence: Persistence,
+    /// Brightness of each pixel that is shown.
+    levels: [u8; WIDTH * HEIGHT],
+    /// Whether each pixel was on in the previous frame.
+    previous: [bool; WIDTH * HEIGHT],
+    /// Whether the last frame changed any levels, so the next one might too
+    /// even if the screen didn't change.
+    changing: bool,
+}
+
+impl Phosphor {
+    pub fn new(persistence: Persistence) -> Self {
+        Self {
+            persistence,
+            levels: [0; WIDTH * HEIGHT],
+            previous: [false; WIDTH * HEIGHT],
+            changing: false,
+        }
+    }
+
+    /// Advances by a frame.
+    ///
+    /// Returns `damage` extended with the rows whose levels changed.
+    pub fn update(&mut self, buffer: Framebuffer, damage: Damage) -> Damage {
+        if damage.is_empty() && !self.changing {
+            return damage;
+        }
+
+        let mut rows = 0;
+
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let i = y * WIDTH + x;
+                let on = buffer.pixel(x, y);
+
+                let level = if on {
+                    MAX_LEVEL
+                } else {
+                    match self.persistence {
+                        Persistence::TwoFrames if self.previous[i] => MAX_LEVEL,
+                        Persistence::TwoFrames => 0,
+                        Persistence::Decay(decay) => {
+                            let level = (self.levels[i] as f32 * (1. - decay)) as u8;
+                            if level < MIN_LEVEL {
+                                0
+                            } else {
+                                level
+                            }
+                        }
+                    }
+                };
+
+                if level != self.levels[i] {
+                    rows |= 1 << y;
+                    self.levels[i] = level;
+                }
+                self.previous[i] = on;
+            }
+        }
+
+        self.changing = rows != 0;
+
+        damage.with_rows(rows)
+    }
+
+    /// Brightness of the pixel at `x`, `y`, up to [MAX_LEVEL].
+    pub fn level(&self, x: usize, y: usize) -> u8 {
+        self.levels[y * WIDTH + x]
+    }
+}
@@ -2,32 +2,48 @@
 //!
 //! This should be the only file in this crate which depends on [crossterm].
 
+use std::env;
 use std::io::stdout;
 use std::io::Stdout;
 use std::io::Write;
-use std::iter::zip;
-use std::thread;
-use std::time::Duration;
+use std::panic;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::Once;
+use std::time::{Duration, Instant};
 
-use crate::constant::{FPS, HEIGHT, WIDTH};
+use crate::constant::{HEIGHT, WIDTH};
 use crate::error::Error;
 use crate::keypad::Key;
-use crate::window::Window;
+use crate::menu::{Menu, MenuLine};
+use crate::palette::Palette;
+use crate::screen::{Damage, Framebuffer, ALL_ROWS};
+use crate::window::graphics::{self, Graphics, GraphicsMode, Image};
+use crate::window::phosphor::{Persistence, Phosphor, MAX_LEVEL};
+use crate::window::{Hotkey, MenuKey, Status, Window};
+use clap::ValueEnum;
 use crossterm::cursor::Hide;
 use crossterm::cursor::MoveTo;
 use crossterm::cursor::Show;
-use crossterm::event::read;
 use crossterm::event::Event;
 use crossterm::event::KeyCode;
 use crossterm::event::KeyEvent;
+use crossterm::event::KeyEventKind;
 use crossterm::event::KeyModifiers;
+use crossterm::style::Attribute;
+use crossterm::style::Color;
 use crossterm::style::Print;
+use crossterm::style::ResetColor;
+use crossterm::style::SetAttribute;
+use crossterm::style::SetBackgroundColor;
+use crossterm::style::SetForegroundColor;
 use crossterm::terminal::disable_raw_mode;
 use crossterm::terminal::enable_raw_mode;
 use crossterm::terminal::Clear;
 use crossterm::terminal::ClearType;
 use crossterm::terminal::EnterAlternateScreen;
 use crossterm::terminal::LeaveAlternateScreen;
+use crossterm::Command;
 use crossterm::ErrorKind;
 use crossterm::ExecutableCommand;
 use crossterm::QueueableCommand;
@@ -35,165 +51,855 @@ use crossterm::QueueableCommand;
 pub struct TerminalWindow {
     stdout: Stdout,
     lines: Vec<String>,
+    /// When unset, pixels are drawn with the default colours of the terminal.
+    colors: Option<Colors>,
+    phosphor: Option<Phosphor>,
+    glyphs: Glyphs,
+    /// When set, pixels are drawn as an image instead of with [Glyphs].
+    graphics: Option<Graphics>,
+    scale: Option<usize>,
+    /// Recomputed when the terminal is resized.
+    layout: Option<Layout>,
+
+    /// Whether the status line shows the [Status] of the emulator.
+    hud: bool,
+    /// Text last drawn to the status line.
+    status_line: String,
+
+    /// When each key was last pressed, indexed by keypad value.
+    pressed_at: [Option<Instant>; 16],
+    /// Key pressed since the last call to `is_running`.
+    last_pressed: Option<Key>,
+    /// When each hotkey was last pressed, indexed by [Hotkey].
+    hotkey_pressed_at: [Option<Instant>; Hotkey::ALL.len()],
+    /// Hotkeys pressed since the last call to `is_running`.
+    hotkeys_pressed: Vec<Hotkey>,
+    /// Menu keys pressed since the last call to `is_running`.
+    menu_keys_pressed: Vec<MenuKey>,
+
+    /// Size of the terminal when the menu was last drawn, or `None` if the
+    /// screen was drawn since.
+    menu_size: Option<(u16, u16)>,
+    /// Lines of the menu last drawn.
+    menu_lines: Vec<MenuLine>,
+}
+
+/// Options for [TerminalWindow].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TerminalOptions {
+    /// When unset, pixels are drawn with the default colours of the terminal.
+    pub palette: Option<Palette>,
+    pub persistence: Option<Persistence>,
+    pub glyphs: Glyphs,
+    pub graphics: GraphicsMode,
+    /// Integer factor to scale pixels by, in both directions. With glyphs, a
+    /// scale of 2 draws each half block pixel as 2 cells. With graphics, this
+    /// is the size of each pixel in image pixels, up to the largest which
+    /// fits the terminal, which is used when unset.
+    pub scale: Option<usize>,
 }
 
 impl TerminalWindow {
-    pub fn new() -> Self {
+    pub fn new(options: TerminalOptions) -> Self {
         let mut stdout = stdout();
 
+        // Make sure the terminal is restored however the process ends.
+        ACTIVE.store(true, Ordering::SeqCst);
+        install_panic_hook();
+        #[cfg(unix)]
+        install_signal_handlers();
+
         // This causes the terminal to be output on an alternate buffer.
         stdout.execute(EnterAlternateScreen).unwrap();
         stdout.execute(Hide).unwrap();
 
         enable_raw_mode().unwrap();
 
+        let graphics = match options.graphics {
+            GraphicsMode::None => None,
+            GraphicsMode::Auto => graphics::detect(),
+            GraphicsMode::Force(graphics) => Some(graphics),
+        };
+
         Self {
             stdout,
-            lines: vec!["".to_string(); HEIGHT / 2],
+            lines: vec![],
+            colors: options.palette.map(|palette| Colors {
+                palette,
+                truecolor: supports_truecolor(),
+            }),
+            phosphor: options.persistence.map(Phosphor::new),
+            glyphs: options.glyphs,
+            graphics,
+            // A scale of 0 would draw nothing, so pick one which fits instead.
+            scale: options.scale.filter(|scale| *scale > 0),
+            layout: None,
+            hud: false,
+            status_line: String::new(),
+            pressed_at: [None; 16],
+            last_pressed: None,
+            hotkey_pressed_at: [None; Hotkey::ALL.len()],
+            hotkeys_pressed: vec![],
+            menu_keys_pressed: vec![],
+            menu_size: None,
+            menu_lines: vec![],
         }
     }
 }
 
-const FAST_IO_DURATION: Duration = Duration::from_secs((FPS / 10.) as u64);
+impl Default for TerminalWindow {
+    fn default() -> Self {
+        Self::new(TerminalOptions::default())
+    }
+}
+
+/// Toggles showing the [Status] on the status line.
+const HUD_KEY: KeyCode = KeyCode::F(1);
+
+/// Terminals only report when keys are pressed, not released, so keys are
+/// treated as held down for this long after each press. Holding a key down
+/// repeats the press, which keeps it down.
+const KEY_HOLD_DURATION: Duration = Duration::from_millis(250);
 
 impl Window for TerminalWindow {
     fn is_running(&mut self) -> bool {
-        // When `enable_raw_mode` is set, `Ctrl-C` events to interrupt the
-        // process is ignored. So manually handle the interrupt.
+        // Once the terminal is restored, e.g. after `Ctrl-C`, nothing can be
+        // drawn in it any more.
+        if SIGNAL.load(Ordering::SeqCst) != 0 || !ACTIVE.load(Ordering::SeqCst) {
+            return false;
+        }
+
+        self.last_pressed = None;
+        self.hotkeys_pressed.clear();
+        self.menu_keys_pressed.clear();
+
+        // Handle every event which arrived since the last call, without
+        // waiting, as the emulator paces frames.
+        while crossterm::event::poll(Duration::ZERO).unwrap() {
+            // Guaranteed not to block if `poll` above is true.
+            match crossterm::event::read().unwrap() {
+                // When `enable_raw_mode` is set, `Ctrl-C` events to interrupt
+                // the process is ignored. So manually handle the interrupt.
+                Event::Key(event)
+                    if event == KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL) =>
+                {
+                    self.close();
+                    return false;
+                }
+                Event::Key(KeyEvent {
+                    code: HUD_KEY,
+                    kind: KeyEventKind::Press,
+                    ..
+                }) => self.hud = !self.hud,
+                Event::Key(event) => {
+                    // Keys of the menu and the program overlap, but only one
+                    // of them is shown at a time.
+                    if let Some(menu_key) = menu_key(event.code) {
+                        self.menu_keys_pressed.push(menu_key);
+                    }
+
+                    if let Some(hotkey) = hotkey(event.code) {
+                        self.hotkey_pressed_at[hotkey as usize] = Some(Instant::now());
+                        self.hotkeys_pressed.push(hotkey);
+                    } else if let Ok(key) = Key::try_from(event) {
+                        self.pressed_at[key.to_keypad() as usize] = Some(Instant::now());
+                        self.last_pressed = Some(key);
+                    }
+                }
+                Event::Resize(_, _) => self.layout = None,
+                _ => {}
+            }
+        }
+
+        true
+    }
+
+    fn is_key_down(&self, key: Key) -> bool {
+        self.pressed_at[key.to_keypad() as usize]
+            .is_some_and(|pressed_at| pressed_at.elapsed() < KEY_HOLD_DURATION)
+    }
+
+    fn is_key_up(&self, key: Key) -> bool {
+        !self.is_key_down(key)
+    }
+
+    fn wait_for_next_key(&mut self) -> Option<Key> {
+        self.last_pressed.take()
+    }
+
+    fn hotkeys_pressed(&self) -> Vec<Hotkey> {
+        self.hotkeys_pressed.clone()
+    }
 
-        if !crossterm::event::poll(FAST_IO_DURATION).unwrap() {
-            return true;
+    fn is_hotkey_down(&self, hotkey: Hotkey) -> bool {
+        self.hotkey_pressed_at[hotkey as usize]
+            .is_some_and(|pressed_at| pressed_at.elapsed() < KEY_HOLD_DURATION)
+    }
+
+    fn menu_keys_pressed(&self) -> Vec<MenuKey> {
+        self.menu_keys_pressed.clone()
+    }
+
+    fn draw_menu(&mut self, menu: &Menu) -> Result<(), Error> {
+        let (columns, rows) = crossterm::terminal::size()?;
+
+        // Redraw everything when the terminal is resized, or the screen was
+        // drawn over the menu.
+        if self.menu_size != Some((columns, rows)) {
+            if let Some(graphics) = self.graphics {
+                let mut out = vec![];
+                graphics.clear(&mut out);
+                self.stdout.write_all(&out)?;
+            }
+            self.stdout.queue(Clear(ClearType::All))?;
+
+            self.menu_size = Some((columns, rows));
+            self.menu_lines.clear();
+            // So that the screen is laid out again after the menu.
+            self.layout = None;
+        }
+
+        let lines = menu.lines(columns as usize, rows as usize);
+        let blank = MenuLine {
+            text: String::new(),
+            selected: false,
+        };
+
+        for i in 0..lines.len().max(self.menu_lines.len()) {
+            let line = lines.get(i).unwrap_or(&blank);
+            if self.menu_lines.get(i) == Some(line) {
+                continue;
+            }
+
+            self.stdout.queue(MoveTo(0, i as u16))?;
+            if line.selected {
+                self.stdout.queue(SetAttribute(Attribute::Reverse))?;
+            }
+            self.stdout.queue(Print(format!(
+                "{:width$}",
+                line.text,
+                width = columns as usize
+            )))?;
+            self.stdout.queue(SetAttribute(Attribute::Reset))?;
         }
-        // Guaranteed not to block if `poll` above is true.
-        let event = crossterm::event::read().unwrap();
 
-        if event == Event::Key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)) {
-            // Be a good citizen and restore the previous terminal.
-            disable_raw_mode().unwrap();
-            self.stdout.execute(Show).unwrap();
-            self.stdout.execute(LeaveAlternateScreen).unwrap();
-            false
+        self.stdout.flush()?;
+        self.menu_lines = lines;
+
+        Ok(())
+    }
+
+    fn paces_frames(&self) -> bool {
+        false
+    }
+
+    fn update(
+        &mut self,
+        buffer: Framebuffer,
+        damage: Damage,
+        status: &Status,
+    ) -> Result<(), Error> {
+        self.menu_size = None;
+
+        let damage = match &mut self.phosphor {
+            Some(phosphor) => phosphor.update(buffer, damage),
+            None => damage,
+        };
+
+        let damage = if self.layout.is_none() {
+            self.relayout()?;
+            damage.with_rows(ALL_ROWS)
         } else {
-            true
+            damage
+        };
+
+        if !damage.is_empty() {
+            match self.graphics {
+                Some(graphics) => self.draw_image(buffer, graphics)?,
+                None => self.draw(buffer, damage)?,
+            }
         }
+
+        self.draw_status(status)?;
+
+        Ok(())
     }
+}
 
-    fn is_key_down(&self, key: Key) -> bool {
-        match read().unwrap() {
-            Event::Key(received) => {
-                if let Ok(received_key) = Key::try_from(received) {
-                    received_key == key
-                } else {
-                    false
-                }
+impl TerminalWindow {
+    /// Be a good citizen and restore the previous terminal.
+    fn close(&mut self) {
+        if !ACTIVE.load(Ordering::SeqCst) {
+            return;
+        }
+
+        if let Some(graphics) = self.graphics {
+            let mut out = vec![];
+            graphics.clear(&mut out);
+            // Errors are ignored here and in `restore`, as there is nothing
+            // left to do about them.
+            let _ = self.stdout.write_all(&out);
+        }
+
+        restore();
+    }
+}
+
+impl Drop for TerminalWindow {
+    fn drop(&mut self) {
+        self.close();
+
+        #[cfg(unix)]
+        reraise_signal();
+    }
+}
+
+/// Whether the terminal has been set up by [TerminalWindow::new], and not been
+/// restored yet.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Restores the terminal to how it was before [TerminalWindow::new].
+///
+/// Does nothing if it is already restored, so it is safe to call from every
+/// exit path.
+fn restore() {
+    if !ACTIVE.swap(false, Ordering::SeqCst) {
+        return;
+    }
+
+    let mut stdout = stdout();
+    let _ = disable_raw_mode();
+    let _ = stdout.execute(Show);
+    let _ = stdout.execute(LeaveAlternateScreen);
+}
+
+/// Restores the terminal before a panic message is printed, so that the
+/// message isn't lost in the alternate screen, and the shell is usable after.
+fn install_panic_hook() {
+    static INSTALL: Once = Once::new();
+
+    INSTALL.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            restore();
+            previous(info);
+        }));
+    });
+}
+
+/// Signal which asked the process to stop, or 0 if there wasn't one.
+static SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+/// Stops the emulator gracefully on signals which would otherwise kill the
+/// process with the terminal still in raw mode.
+#[cfg(unix)]
+fn install_signal_handlers() {
+    extern "C" fn handle(signal: libc::c_int) {
+        SIGNAL.store(signal, Ordering::SeqCst);
+    }
+
+    for signal in [libc::SIGTERM, libc::SIGHUP, libc::SIGINT] {
+        // Safe as the handler only stores to an atomic, which is
+        // async-signal-safe.
+        unsafe {
+            libc::signal(signal, handle as *const () as libc::sighandler_t);
+        }
+    }
+}
+
+/// After the terminal is restored, dies from the signal which stopped the
+/// emulator, as would have happened without the handler, so that the parent
+/// process sees the right exit status.
+#[cfg(unix)]
+fn reraise_signal() {
+    let signal = SIGNAL.load(Ordering::SeqCst);
+    if signal == 0 {
+        return;
+    }
+
+    // Safe as restoring the default disposition and raising a signal don't
+    // touch any memory.
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+}
+
+impl TerminalWindow {
+    /// Clears the terminal, and works out where to draw for its current size.
+    fn relayout(&mut self) -> Result<(), Error> {
+        let (columns, rows) = crossterm::terminal::size()?;
+
+        self.stdout.queue(Clear(ClearType::All))?;
+
+        let layout = if let Some(graphics) = self.graphics {
+            // Images are positioned every time they are drawn, so only need to
+            // be cleared.
+            let mut out = vec![];
+            graphics.clear(&mut out);
+            self.stdout.write_all(&out)?;
+
+            Layout::Image { columns, rows }
+        } else {
+            Layout::new(columns, rows, self.glyphs, self.scale)
+        };
+
+        match layout {
+            Layout::TooSmall { columns, rows } => {
+                let notice = format!("Terminal too small, resize to at least {columns}x{rows}");
+                let (width, height) = crossterm::terminal::size()?;
+
+                self.stdout.queue(MoveTo(
+                    width.saturating_sub(notice.chars().count() as u16) / 2,
+                    height / 2,
+                ))?;
+                self.stdout.queue(Print(notice))?;
+            }
+            Layout::Playfield { column, row, scale } => {
+                let (width, height) = self.glyphs.cells(scale);
+                self.draw_border(column, row, width, height)?;
+                self.lines = vec!["".to_string(); height];
             }
-            _ => false,
+            Layout::Image { .. } => {}
         }
+
+        self.status_line = String::new();
+
+        self.stdout.flush()?;
+        self.layout = Some(layout);
+
+        Ok(())
     }
 
-    fn is_key_up(&self, key: Key) -> bool {
-        // This doesn't exactly match the semantics of the method, but I guess
-        // it's good enough for now.
-        self.wait_for_next_key()
-            .map_or(true, |received| key != received)
+    /// Draws the border around the playfield.
+    fn draw_border(
+        &mut self,
+        column: u16,
+        row: u16,
+        width: usize,
+        height: usize,
+    ) -> Result<(), Error> {
+        let horizontal = BORDER_HORIZONTAL.to_string().repeat(width);
+
+        self.stdout.queue(MoveTo(column - 1, row - 1))?;
+        self.stdout.queue(Print(format!(
+            "{BORDER_TOP_LEFT}{horizontal}{BORDER_TOP_RIGHT}"
+        )))?;
+        for y in 0..height as u16 {
+            self.stdout.queue(MoveTo(column - 1, row + y))?;
+            self.stdout.queue(Print(BORDER_VERTICAL))?;
+            self.stdout.queue(MoveTo(column + width as u16, row + y))?;
+            self.stdout.queue(Print(BORDER_VERTICAL))?;
+        }
+        self.stdout.queue(MoveTo(column - 1, row + height as u16))?;
+        self.stdout.queue(Print(format!(
+            "{BORDER_BOTTOM_LEFT}{horizontal}{BORDER_BOTTOM_RIGHT}"
+        )))?;
+
+        Ok(())
     }
 
-    fn wait_for_next_key(&self) -> Option<Key> {
-        match read().unwrap() {
-            Event::Key(received) => {
-                if let Ok(received_key) = Key::try_from(received) {
-                    Some(received_key)
-                } else {
-                    None
+    /// Draws the status line, below the playfield or at the bottom of the
+    /// terminal when drawing images.
+    fn draw_status(&mut self, status: &Status) -> Result<(), Error> {
+        let Some((column, row, width, scale)) = self
+            .layout
+            .and_then(|layout| layout.status_line(self.glyphs))
+        else {
+            return Ok(());
+        };
+
+        let text = if self.hud {
+            status.to_string()
+        } else {
+            let size = match scale {
+                Some(scale) => format!("{WIDTH}x{HEIGHT} at {scale}x - "),
+                None => String::new(),
+            };
+            format!("{size}Ctrl-C to quit, F1 for HUD")
+        };
+
+        let text = text.chars().take(width).collect::<String>();
+        if text == self.status_line {
+            return Ok(());
+        }
+
+        let padding = (width - text.chars().count()) / 2;
+        let line = format!("{:padding$}{text:width$}", "", width = width - padding);
+
+        self.stdout.queue(MoveTo(column, row))?;
+        self.stdout.queue(Print(line))?;
+        self.stdout.flush()?;
+
+        self.status_line = text;
+
+        Ok(())
+    }
+
+    fn draw(&mut self, buffer: Framebuffer, damage: Damage) -> Result<(), Error> {
+        let Some(Layout::Playfield { column, row, scale }) = self.layout else {
+            return Ok(());
+        };
+
+        // Refreshing the entire terminal (with the clear char) and outputting
+        // everything on every iteration is costly and causes the terminal to
+        // flicker.
+        //
+        // Instead, only "re-render" the lines covering rows which changed, if
+        // they are different from the previous frame.
+
+        let cell_height = self.glyphs.cell_height();
+
+        for (i, prev) in self.lines.iter_mut().enumerate() {
+            // Rows of the screen covered by this line, after scaling.
+            let y = i * cell_height;
+            let rows = y / scale..((y + cell_height).div_ceil(scale)).min(HEIGHT);
+            if !rows.into_iter().any(|y| damage.is_row_dirty(y)) {
+                continue;
+            }
+
+            let phosphor = self.phosphor.as_ref();
+            let level = |x: usize, y: usize| {
+                let (x, y) = (x / scale, y / scale);
+                match phosphor {
+                    Some(phosphor) => phosphor.level(x, y),
+                    None => buffer.pixel(x, y) as u8 * MAX_LEVEL,
                 }
+            };
+
+            let current = line(level, y, scale, self.glyphs, self.colors.as_ref());
+            if *prev != current {
+                self.stdout.queue(MoveTo(column, row + i as u16))?;
+                self.stdout.queue(Print(&current))?;
+                *prev = current;
             }
-            _ => None,
         }
+
+        self.stdout.flush()?;
+
+        Ok(())
     }
+}
 
-    fn update(&mut self, buffer: &[bool; WIDTH * HEIGHT]) -> Result<(), Error> {
-        let mut buffer = buffer.to_vec();
-        // Always process an even number of rows.
-        if buffer.len() % 2 != 0 {
-            buffer.extend(vec![false; WIDTH]);
+/// Where to draw on the terminal, for its current size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Layout {
+    /// The terminal needs to be at least this many columns and rows to fit the
+    /// playfield.
+    TooSmall { columns: u16, rows: u16 },
+    /// The top left cell of the playfield, inside the border.
+    Playfield { column: u16, row: u16, scale: usize },
+    /// Drawing with [Graphics], which is positioned for every image, in a
+    /// terminal of this size.
+    Image { columns: u16, rows: u16 },
+}
+
+impl Layout {
+    /// Centres the playfield with the largest `scale` which fits, if unset.
+    fn new(columns: u16, rows: u16, glyphs: Glyphs, scale: Option<usize>) -> Self {
+        // Size including the border, and the status line below, which can be
+        // more than a terminal has room for with a large `scale`.
+        let size = |scale| {
+            let (width, height) = glyphs.cells(scale);
+            (width.saturating_add(2), height.saturating_add(3))
+        };
+        let fits = |scale| {
+            let (width, height) = size(scale);
+            width <= columns as usize && height <= rows as usize
+        };
+
+        let scale =
+            scale.unwrap_or_else(|| (2..).take_while(|scale| fits(*scale)).last().unwrap_or(1));
+        let (width, height) = size(scale);
+
+        if !fits(scale) {
+            return Layout::TooSmall {
+                columns: width.try_into().unwrap_or(u16::MAX),
+                rows: height.try_into().unwrap_or(u16::MAX),
+            };
         }
 
-        // Each element is one pixel, but when it is rendered to the terminal,
-        // two rows share one character, using the unicode BLOCK characters.
+        Layout::Playfield {
+            column: (columns - width as u16) / 2 + 1,
+            row: (rows - height as u16) / 2 + 1,
+            scale,
+        }
+    }
 
-        // Group alternate rows together, so zipping them allows two consecutive
-        // rows to be processed into terminal characters at the same time.
-        let tops = buffer
-            .iter()
-            .enumerate()
-            .filter(|(i, _)| {
-                let row = i / WIDTH;
+    /// Where the status line goes, as its first column and row, its width,
+    /// and the scale to show in it, if there's room for one.
+    fn status_line(&self, glyphs: Glyphs) -> Option<(u16, u16, usize, Option<usize>)> {
+        match *self {
+            Layout::Playfield { column, row, scale } => {
+                let (width, height) = glyphs.cells(scale);
+                // Span the border too.
+                Some((column - 1, row + height as u16 + 1, width + 2, Some(scale)))
+            }
+            Layout::Image { columns, rows } => {
+                Some((0, rows.checked_sub(1)?, columns as usize, None))
+            }
+            Layout::TooSmall { .. } => None,
+        }
+    }
+}
 
-                if row % 2 == 0 {
-                    true
-                } else {
-                    false
-                }
+impl TerminalWindow {
+    /// Draws the whole screen as one image, centred in the terminal.
+    fn draw_image(&mut self, buffer: Framebuffer, graphics: Graphics) -> Result<(), Error> {
+        let (columns, rows) = crossterm::terminal::size()?;
+        let (columns, rows) = (columns as usize, rows as usize);
+        let (cell_width, cell_height) = graphics::cell_size().unwrap_or(DEFAULT_CELL_SIZE);
+
+        // Leave the last row empty, as images which touch the bottom of the
+        // terminal can scroll it.
+        let fits = (columns * cell_width / WIDTH)
+            .min(rows.saturating_sub(1) * cell_height / HEIGHT)
+            .max(1);
+        let scale = self.scale.map_or(fits, |scale| scale.min(fits));
+
+        let palette = self
+            .colors
+            .as_ref()
+            .map_or_else(Palette::default, |colors| colors.palette);
+        let phosphor = self.phosphor.as_ref();
+
+        let (width, height) = (WIDTH * scale, HEIGHT * scale);
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x / scale, y / scale)))
+            .map(|(x, y)| match phosphor {
+                Some(phosphor) => palette.shade(phosphor.level(x, y)),
+                None => palette.color(buffer.pixel(x, y) as usize),
             })
-            .map(|(_, val)| *val);
-        let bottoms = buffer
-            .iter()
-            .enumerate()
-            .filter(|(i, _)| {
-                let row = i / WIDTH;
+            .collect();
+
+        let image = Image {
+            width,
+            height,
+            pixels,
+        };
+
+        let column = columns.saturating_sub(width.div_ceil(cell_width)) / 2;
+        let row = rows.saturating_sub(height.div_ceil(cell_height)) / 2;
 
-                if row % 2 == 1 {
-                    true
+        let mut out = vec![];
+        graphics.encode(&image, &mut out);
+
+        self.stdout.queue(MoveTo(column as u16, row as u16))?;
+        self.stdout.write_all(&out)?;
+        self.stdout.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Size of a cell in pixels, for terminals which don't report it.
+const DEFAULT_CELL_SIZE: (usize, usize) = (10, 20);
+
+/// Renders the pixel rows starting from `y` to a line of the terminal, where
+/// pixels are `scale` times larger than on the screen.
+///
+/// Each element is one pixel, but when it is rendered to the terminal, a cell
+/// of several pixels shares one character, depending on the [Glyphs].
+fn line(
+    level: impl Fn(usize, usize) -> u8,
+    y: usize,
+    scale: usize,
+    glyphs: Glyphs,
+    colors: Option<&Colors>,
+) -> String {
+    let (cell_width, cell_height) = (glyphs.cell_width(), glyphs.cell_height());
+    let (width, height) = (WIDTH * scale, HEIGHT * scale);
+
+    // Levels of the pixels in each cell, in row-major order. Pixels past the
+    // edges of the screen are treated as off.
+    let cells = (0..width).step_by(cell_width).map(|x| {
+        (0..cell_height)
+            .flat_map(move |dy| (0..cell_width).map(move |dx| (x + dx, y + dy)))
+            .map(|(x, y)| {
+                if x < width && y < height {
+                    level(x, y)
                 } else {
-                    false
+                    0
                 }
             })
-            .map(|(_, val)| *val);
+            .collect::<Vec<u8>>()
+    });
 
-        let lines = zip(tops, bottoms)
+    // Without colours, faded pixels can only be on or off.
+    let on = |level| level > MAX_LEVEL / 2;
+    let bits = |cell: &[u8]| {
+        cell.iter()
             .enumerate()
-            .fold(vec![], |mut acc, (i, top_and_bottom)| {
-                if i % WIDTH == 0 {
-                    acc.push("".to_string());
-                }
-                let character = match top_and_bottom {
-                    (true, true) => BLOCK_FULL,
-                    (true, false) => BLOCK_UPPER,
-                    (false, true) => BLOCK_LOWER,
-                    (false, false) => BLOCK_EMPTY,
-                };
+            .fold(0, |bits, (i, level)| bits | (on(*level) as u8) << i)
+    };
+
+    let Some(colors) = colors else {
+        return cells.map(|cell| glyphs.glyph(bits(&cell))).collect();
+    };
+
+    // With colours, half blocks are always drawn as an upper block, where the
+    // foreground colour draws the top pixel and the background colour the
+    // bottom one. Other glyphs are drawn in the colour of the brightest pixel
+    // in the cell. Escape sequences are only written when the colours change.
+    let mut line = String::new();
+    let mut current = None;
+
+    for cell in cells {
+        let (character, cell_colors) = match glyphs {
+            Glyphs::HalfBlock => (BLOCK_UPPER, (colors.shade(cell[0]), colors.shade(cell[1]))),
+            _ => {
+                let brightest = cell.iter().copied().max().unwrap_or(0);
+                (
+                    glyphs.glyph(bits(&cell)),
+                    (colors.shade(brightest), colors.shade(0)),
+                )
+            }
+        };
+
+        if current != Some(cell_colors) {
+            SetForegroundColor(cell_colors.0)
+                .write_ansi(&mut line)
+                .unwrap();
+            SetBackgroundColor(cell_colors.1)
+                .write_ansi(&mut line)
+                .unwrap();
+            current = Some(cell_colors);
+        }
+        line.push(character);
+    }
+
+    ResetColor.write_ansi(&mut line).unwrap();
 
-                let current_line = acc.last_mut().unwrap();
-                current_line.push(character);
+    line
+}
 
-                acc
-            });
+/// Characters used to draw pixels to the terminal.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Glyphs {
+    /// Half blocks, for 1x2 pixels per character.
+    #[default]
+    HalfBlock,
+    /// Quadrant blocks, for 2x2 pixels per character.
+    Quadrant,
+    /// Braille patterns, for 2x4 pixels per character.
+    Braille,
+}
 
-        assert!(lines.len() == HEIGHT / 2);
-        assert!(lines.len() == self.lines.len());
+impl Glyphs {
+    /// Columns and rows of cells needed to draw the screen at `scale`.
+    fn cells(&self, scale: usize) -> (usize, usize) {
+        (
+            WIDTH.saturating_mul(scale).div_ceil(self.cell_width()),
+            HEIGHT.saturating_mul(scale).div_ceil(self.cell_height()),
+        )
+    }
 
-        // Refreshing the entire terminal (with the clear char) and outputting
-        // everything on every iteration is costly and causes the terminal to
-        // flicker.
-        //
-        // Instead, only "re-render" the current line, if it is different from
-        // the previous frame.
+    fn cell_width(&self) -> usize {
+        match self {
+            Glyphs::HalfBlock => 1,
+            Glyphs::Quadrant | Glyphs::Braille => 2,
+        }
+    }
+
+    fn cell_height(&self) -> usize {
+        match self {
+            Glyphs::HalfBlock | Glyphs::Quadrant => 2,
+            Glyphs::Braille => 4,
+        }
+    }
 
-        for (i, (prev, current)) in zip(&self.lines, &lines).enumerate() {
-            if prev != current {
-                self.stdout.queue(MoveTo(0, i as u16))?;
-                self.stdout.queue(Clear(ClearType::CurrentLine))?;
-                self.stdout.queue(Print(current))?;
+    /// Character for a cell where bit `i` is set if the `i`th pixel of the
+    /// cell is on, counting in row-major order.
+    fn glyph(&self, bits: u8) -> char {
+        match self {
+            Glyphs::HalfBlock => [BLOCK_EMPTY, BLOCK_UPPER, BLOCK_LOWER, BLOCK_FULL][bits as usize],
+            Glyphs::Quadrant => QUADRANTS[bits as usize],
+            Glyphs::Braille => {
+                // Braille dots are numbered down the left column first, then
+                // down the right column, with the bottom row added last.
+                const DOTS: [u32; 8] = [0x01, 0x08, 0x02, 0x10, 0x04, 0x20, 0x40, 0x80];
+
+                let dots = (0..8)
+                    .filter(|i| bits >> i & 1 == 1)
+                    .fold(0, |dots, i| dots | DOTS[i]);
+
+                char::from_u32(BRAILLE_BLANK as u32 + dots).unwrap()
             }
         }
+    }
+}
 
-        self.stdout.flush()?;
+impl FromStr for Glyphs {
+    type Err = Error;
 
-        self.lines = lines;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        <Self as ValueEnum>::from_str(s, false).map_err(|_| {
+            Error::ErrorStr(format!(
+                "Unknown glyphs '{s}', expected one of: half-block, quadrant, braille"
+            ))
+        })
+    }
+}
 
-        thread::sleep(Duration::from_secs_f64(FPS));
+/// A [Palette], converted to the colours supported by the terminal.
+struct Colors {
+    palette: Palette,
+    /// Whether 24-bit colours are supported, otherwise the closest of the 256
+    /// standard colours is used.
+    truecolor: bool,
+}
 
-        Ok(())
+impl Colors {
+    /// See [Palette::shade].
+    fn shade(&self, level: u8) -> Color {
+        let rgb = self.palette.shade(level);
+        let [_, r, g, b] = rgb.to_be_bytes();
+
+        if self.truecolor {
+            Color::Rgb { r, g, b }
+        } else {
+            Color::AnsiValue(ansi_256(r, g, b))
+        }
+    }
+}
+
+/// Index of the closest colour in the 6x6x6 cube of the 256 colour palette.
+fn ansi_256(r: u8, g: u8, b: u8) -> u8 {
+    let level = |c: u8| match c {
+        0..=47 => 0,
+        48..=114 => 1,
+        c => (c - 35) / 40,
+    };
+
+    16 + 36 * level(r) + 6 * level(g) + level(b)
+}
+
+/// There isn't a reliable way to query this, but terminals with 24-bit colour
+/// support conventionally advertise it in `COLORTERM`.
+fn supports_truecolor() -> bool {
+    env::var("COLORTERM").is_ok_and(|value| value == "truecolor" || value == "24bit")
+}
+
+/// The [Hotkey] bound to `code`, if any.
+fn hotkey(code: KeyCode) -> Option<Hotkey> {
+    match code {
+        KeyCode::Char('p') => Some(Hotkey::Pause),
+        KeyCode::Char('n') => Some(Hotkey::FrameAdvance),
+        KeyCode::Tab => Some(Hotkey::Turbo),
+        KeyCode::Char('m') => Some(Hotkey::SlowMotion),
+        KeyCode::Esc => Some(Hotkey::Exit),
+        _ => None,
+    }
+}
+
+/// The [MenuKey] bound to `code`, if any.
+fn menu_key(code: KeyCode) -> Option<MenuKey> {
+    match code {
+        KeyCode::Up => Some(MenuKey::Up),
+        KeyCode::Down => Some(MenuKey::Down),
+        KeyCode::Enter => Some(MenuKey::Select),
+        KeyCode::Esc => Some(MenuKey::Back),
+        _ => None,
     }
 }
 
@@ -229,8 +935,87 @@ const BLOCK_UPPER: char = '▀';
 const BLOCK_FULL: char = '█';
 const BLOCK_EMPTY: char = ' ';
 
+/// Quadrant blocks, indexed by bits for the top left, top right, bottom left
+/// and bottom right quadrants.
+const QUADRANTS: [char; 16] = [
+    ' ', '▘', '▝', '▀', '▖', '▌', '▞', '▛', '▗', '▚', '▐', '▜', '▄', '▙', '▟', '█',
+];
+
+const BORDER_HORIZONTAL: char = '─';
+const BORDER_VERTICAL: char = '│';
+const BORDER_TOP_LEFT: char = '┌';
+const BORDER_TOP_RIGHT: char = '┐';
+const BORDER_BOTTOM_LEFT: char = '└';
+const BORDER_BOTTOM_RIGHT: char = '┘';
+
+const BRAILLE_BLANK: char = '\u{2800}';
+
 impl From<ErrorKind> for Error {
     fn from(value: ErrorKind) -> Self {
         Error::ErrorStr(value.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_line_is_below_the_playfield() {
+        let layout = Layout::new(80, 24, Glyphs::HalfBlock, Some(1));
+
+        assert_eq!(
+            layout,
+            Layout::Playfield {
+                column: 8,
+                row: 3,
+                scale: 1
+            }
+        );
+        assert_eq!(
+            layout.status_line(Glyphs::HalfBlock),
+            Some((7, 20, 66, Some(1)))
+        );
+    }
+
+    #[test]
+    fn large_scales_are_too_small() {
+        assert_eq!(
+            Layout::new(80, 24, Glyphs::HalfBlock, Some(2)),
+            Layout::TooSmall {
+                columns: 130,
+                rows: 35
+            }
+        );
+        assert_eq!(
+            Layout::new(80, 24, Glyphs::Braille, Some(usize::MAX)),
+            Layout::TooSmall {
+                columns: u16::MAX,
+                rows: u16::MAX
+            }
+        );
+    }
+
+    #[test]
+    fn status_line_is_at_the_bottom_with_images() {
+        let layout = Layout::Image {
+            columns: 80,
+            rows: 24,
+        };
+
+        assert_eq!(
+            layout.status_line(Glyphs::HalfBlock),
+            Some((0, 23, 80, None))
+        );
+    }
+
+    #[test]
+    fn no_status_line_without_rows() {
+        let layout = Layout::Image {
+            columns: 80,
+            rows: 0,
+        };
+
+        assert_eq!(layout.status_line(Glyphs::HalfBlock), None);
+    }
+}